@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast::error::RecvError;
+use crate::chat::message::{ChatMessage, ErrorMessage, Message};
+use crate::chat::room::Room;
+use crate::llm::json::extract_json_array;
+use crate::llm::LLM;
+use crate::model::session::Visibility;
+
+/// Number of recent messages given to the LLM as context of the evaluated message.
+const CONTEXT_SIZE: usize = 10;
+
+/// Keeps a part of the state of the agents up to date by asking the LLM how each message changes it.
+#[async_trait]
+pub(crate) trait Evaluator: Send + Sync + 'static {
+    /// What the evaluator updates, e.g. `relationships`
+    const NAME: &'static str;
+    /// A change output by the LLM, as an element of a JSON array
+    type Change: DeserializeOwned + Send;
+
+    /// Asks how the new message changes the state of the witnesses, the agents who saw it.
+    fn prompt(&self, room: &Room, witnesses: &[String], recent: &str, line: &str) -> String;
+
+    /// Applies the changes of the LLM, ignoring the ones about agents who didn't see the message.
+    async fn apply(&self, room: &Room, chat: &ChatMessage, witnesses: &[String], changes: Vec<Self::Change>) -> Result<(), Box<dyn Error>>;
+}
+
+/// A recent message, as given to the LLM, with who could see it.
+struct RecentLine {
+    from: String,
+    visibility: Visibility,
+    line: String,
+}
+
+/// Runs the evaluator on each message in the room.
+pub(crate) fn start<E: Evaluator>(room: Arc<Room>, llm: Arc<dyn LLM>, evaluator: E) {
+    let mut receiver = room.subscribe();
+    tokio::spawn(async move {
+        let mut recent: Vec<RecentLine> = Vec::new();
+        loop {
+            let chat = match receiver.recv().await {
+                Ok(Message::Chat(chat)) => chat,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            let line = format!("{}(@{}): {}", chat.from_username, chat.from_user_id, chat.read_content().await);
+            if let Err(e) = evaluate(&room, llm.as_ref(), &evaluator, &chat, &line, &recent).await {
+                room.send_error(Arc::new(ErrorMessage { msg: format!("Failed to update {}: {}", E::NAME, e) }))
+                    .expect("cannot send error msg");
+            }
+            recent.push(RecentLine { from: chat.from_user_id.clone(), visibility: chat.visibility.clone(), line });
+            if recent.len() > CONTEXT_SIZE {
+                recent.remove(0);
+            }
+        }
+    });
+}
+
+async fn evaluate<E: Evaluator>(room: &Room, llm: &dyn LLM, evaluator: &E, chat: &ChatMessage, line: &str, recent: &[RecentLine]) -> Result<(), Box<dyn Error>> {
+    // Only the agents who saw the message can be changed by it
+    let witnesses: Vec<String> = room.profiles().iter()
+        .filter(|p| chat.visible_to(&p.id))
+        .map(|p| p.id.clone())
+        .collect();
+    for (context, witnesses) in contexts(recent, witnesses) {
+        let prompt = evaluator.prompt(room, &witnesses, &context, line);
+        let response = llm.single_chat(Arc::new(prompt)).await?;
+        let changes: Vec<E::Change> = serde_json::from_str(extract_json_array(&response))
+            .map_err(|e| format!("the LLM returned invalid changes: {}", e))?;
+        evaluator.apply(room, chat, &witnesses, changes).await?;
+    }
+    Ok(())
+}
+
+/// Groups the witnesses by the recent messages they saw, so each is evaluated without
+/// the whispers and local speech it missed, with the context of each group.
+fn contexts(recent: &[RecentLine], witnesses: Vec<String>) -> Vec<(String, Vec<String>)> {
+    let mut groups: BTreeMap<Vec<usize>, Vec<String>> = BTreeMap::new();
+    for id in witnesses {
+        let seen = recent.iter().enumerate()
+            .filter(|(_, r)| r.visibility.includes(&r.from, &id))
+            .map(|(i, _)| i)
+            .collect();
+        groups.entry(seen).or_default().push(id);
+    }
+    groups.into_iter()
+        .map(|(seen, witnesses)| (seen.iter().map(|i| recent[*i].line.as_str()).collect::<Vec<_>>().join("\n"), witnesses))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recent(from: &str, visibility: Visibility, line: &str) -> RecentLine {
+        RecentLine { from: from.to_string(), visibility, line: line.to_string() }
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn contexts_leave_out_what_each_witness_missed() {
+        let recent = [
+            recent("ann", Visibility::Public, "ann: hello"),
+            recent("ann", Visibility::Whisper(ids(&["bob"])), "ann: psst bob"),
+            recent("cid", Visibility::Local { location: "cellar".to_string(), present: ids(&["dan"]) }, "cid: down here"),
+        ];
+        assert_eq!(contexts(&recent, ids(&["ann", "bob", "cid", "dan", "eve"])), [
+            ("ann: hello".to_string(), ids(&["eve"])),
+            ("ann: hello\nann: psst bob".to_string(), ids(&["ann", "bob"])),
+            ("ann: hello\ncid: down here".to_string(), ids(&["cid", "dan"])),
+        ]);
+        assert!(contexts(&recent, Vec::new()).is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::NaiveDateTime;
+use log::error;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+use crate::chat::message::{EventMessage, Message, SimulationCommand};
+use crate::chat::room::Room;
+use crate::model::event_script::{EventScript, EventTrigger, ScriptedEvent};
+use crate::model::session::Visibility;
+
+/// How often the time triggers are checked.
+const TICK: Duration = Duration::from_secs(1);
+
+struct PendingEvent {
+    event: ScriptedEvent,
+    fired: bool,
+}
+
+/// How far the chat is, to check the triggers against.
+struct Progress {
+    elapsed_secs: f64,
+    message_count: usize,
+    /// Whether a chat message was just sent
+    new_message: bool,
+    world_time: Option<NaiveDateTime>,
+}
+
+/// Real time the simulation has been running for, leaving out the pauses.
+struct RunningTime {
+    /// Time run before the last pause
+    before: Duration,
+    /// When the simulation last started running, if it's running
+    since: Option<Instant>,
+}
+
+impl RunningTime {
+    fn start() -> Self {
+        RunningTime { before: Duration::ZERO, since: Some(Instant::now()) }
+    }
+
+    fn pause(&mut self) {
+        if let Some(since) = self.since.take() {
+            self.before += since.elapsed();
+        }
+    }
+
+    fn resume(&mut self) {
+        self.since.get_or_insert_with(Instant::now);
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.before + self.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+}
+
+impl PendingEvent {
+    /// Whether the event fires now. Events that already fired only fire again when they repeat.
+    fn matches(&self, progress: &Progress, rng: &mut impl Rng) -> bool {
+        if self.fired && !self.event.repeat {
+            return false;
+        }
+        match &self.event.trigger {
+            EventTrigger::AtSimTime(at) => !self.fired && progress.world_time.is_some_and(|now| now >= *at),
+            EventTrigger::AfterSecs(secs) => !self.fired && progress.elapsed_secs >= *secs,
+            // Repeated events fire every `count` messages
+            EventTrigger::AfterMessages(count) => progress.new_message && *count > 0 && if self.event.repeat {
+                progress.message_count.is_multiple_of(*count)
+            } else {
+                progress.message_count == *count
+            },
+            EventTrigger::Probability(p) => progress.new_message && rng.random_bool(*p),
+        }
+    }
+}
+
+/// Fires the events of the script into the room when their triggers match.
+/// Nothing fires while the simulation is paused.
+pub fn start(room: Arc<Room>, script: EventScript) -> Result<(), Box<dyn Error>> {
+    for event in script.events.iter() {
+        if matches!(event.trigger, EventTrigger::AtSimTime(_)) && room.world_time().is_none() {
+            return Err(format!("Event `{}` fires at a simulated time but the room has no clock", event.text).into());
+        }
+    }
+    let mut rng = match script.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let mut events: Vec<PendingEvent> = script.events.into_iter().map(|event| PendingEvent { event, fired: false }).collect();
+    let mut receiver = room.subscribe();
+    tokio::spawn(async move {
+        let mut running_time = RunningTime::start();
+        let mut ticks = interval(TICK);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut message_count = 0;
+        let mut paused = false;
+        loop {
+            let new_message = tokio::select! {
+                _ = ticks.tick() => false,
+                msg = receiver.recv() => match msg {
+                    Ok(Message::Chat(_)) => {
+                        message_count += 1;
+                        true
+                    }
+                    Ok(Message::Simulation(SimulationCommand::Pause)) => {
+                        paused = true;
+                        running_time.pause();
+                        continue;
+                    }
+                    Ok(Message::Simulation(SimulationCommand::Resume)) => {
+                        paused = false;
+                        running_time.resume();
+                        continue;
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            if paused {
+                continue;
+            }
+            let progress = Progress {
+                elapsed_secs: running_time.elapsed().as_secs_f64(),
+                message_count,
+                new_message,
+                world_time: room.world_time(),
+            };
+            for pending in events.iter_mut() {
+                let matched = pending.matches(&progress, &mut rng);
+                if matched {
+                    pending.fired = true;
+                    if let Err(e) = room.send_event(Arc::new(EventMessage { text: pending.event.text.clone(), visibility: Visibility::Public })) {
+                        error!("Failed to send event: {}", e);
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::room_config::parse_world_time;
+
+    fn pending(trigger: EventTrigger, repeat: bool) -> PendingEvent {
+        PendingEvent { event: ScriptedEvent { text: "It rains.".to_string(), trigger, repeat }, fired: false }
+    }
+
+    fn after_message(message_count: usize) -> Progress {
+        Progress { elapsed_secs: 0.0, message_count, new_message: true, world_time: None }
+    }
+
+    fn seeded() -> StdRng {
+        StdRng::seed_from_u64(7)
+    }
+
+    #[test]
+    fn after_messages_fires_once_at_the_count_or_every_count_when_repeated() {
+        let mut rng = seeded();
+        let mut once = pending(EventTrigger::AfterMessages(3), false);
+        let fired: Vec<usize> = (1..=9).filter(|&n| once.matches(&after_message(n), &mut rng)).collect();
+        assert_eq!(fired, [3]);
+        once.fired = true;
+        assert!(!once.matches(&after_message(3), &mut rng));
+
+        let mut repeated = pending(EventTrigger::AfterMessages(3), true);
+        repeated.fired = true;
+        let fired: Vec<usize> = (1..=9).filter(|&n| repeated.matches(&after_message(n), &mut rng)).collect();
+        assert_eq!(fired, [3, 6, 9]);
+        let tick = Progress { new_message: false, ..after_message(6) };
+        assert!(!repeated.matches(&tick, &mut rng));
+        assert!(!pending(EventTrigger::AfterMessages(0), true).matches(&after_message(0), &mut rng));
+    }
+
+    #[test]
+    fn probability_only_rolls_after_messages_and_replays_with_the_seed() {
+        let always = pending(EventTrigger::Probability(1.0), true);
+        let never = pending(EventTrigger::Probability(0.0), true);
+        let mut rng = seeded();
+        assert!(always.matches(&after_message(1), &mut rng));
+        assert!(!never.matches(&after_message(1), &mut rng));
+        assert!(!always.matches(&Progress { new_message: false, ..after_message(1) }, &mut rng));
+
+        let half = pending(EventTrigger::Probability(0.5), true);
+        let run = |rng: &mut StdRng| (1..=50).map(|n| half.matches(&after_message(n), rng)).collect::<Vec<_>>();
+        let first = run(&mut seeded());
+        assert_eq!(first, run(&mut seeded()));
+        assert!(first.contains(&true) && first.contains(&false));
+    }
+
+    #[test]
+    fn at_sim_time_fires_once_the_clock_reaches_it() {
+        let mut rng = seeded();
+        let mut event = pending(EventTrigger::AtSimTime(parse_world_time("2024-05-01 12:00").unwrap()), true);
+        let at = |time: &str| Progress { new_message: false, world_time: parse_world_time(time).ok(), ..after_message(0) };
+        assert!(!event.matches(&at("2024-05-01 11:59"), &mut rng));
+        assert!(event.matches(&at("2024-05-01 12:00"), &mut rng));
+        assert!(event.matches(&at("2024-05-02 08:00"), &mut rng));
+        assert!(!event.matches(&Progress { world_time: None, ..at("2024-05-01 12:00") }, &mut rng));
+        // Even when repeated, a point in time only passes once
+        event.fired = true;
+        assert!(!event.matches(&at("2024-05-02 08:00"), &mut rng));
+    }
+
+    #[test]
+    fn after_secs_fires_once_the_time_has_passed() {
+        let mut rng = seeded();
+        let mut event = pending(EventTrigger::AfterSecs(2.5), false);
+        let at = |elapsed_secs| Progress { elapsed_secs, new_message: false, ..after_message(0) };
+        assert!(!event.matches(&at(2.0), &mut rng));
+        assert!(event.matches(&at(2.5), &mut rng));
+        event.fired = true;
+        assert!(!event.matches(&at(3.0), &mut rng));
+    }
+
+    #[tokio::test]
+    async fn running_time_leaves_out_the_pauses() {
+        let mut running_time = RunningTime::start();
+        running_time.pause();
+        let paused_at = running_time.elapsed();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(running_time.elapsed(), paused_at);
+        running_time.resume();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(running_time.elapsed() >= paused_at + Duration::from_millis(20));
+    }
+}
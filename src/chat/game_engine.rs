@@ -0,0 +1,351 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use log::info;
+use rand::rngs::StdRng;
+use rand::seq::IndexedRandom;
+use rand::SeedableRng;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+use tokio::time::{timeout_at, Instant};
+use crate::chat::message::{ChatMessage, ErrorMessage, EventMessage, Message, NoticeMessage};
+use crate::chat::room::Room;
+use crate::llm::json::extract_json_object;
+use crate::llm::router::LLMRouter;
+use crate::model::game::{Game, Phase, Role};
+use crate::model::room_config::GameConfig;
+use crate::model::session::Visibility;
+
+/// Gives the chat time to open before the first announcement.
+const START_DELAY: Duration = Duration::from_secs(2);
+/// How often the game checks whether the human voted.
+const VOTE_POLL: Duration = Duration::from_millis(500);
+/// Number of recent messages given to the agents when they vote.
+const CONTEXT_SIZE: usize = 30;
+
+#[derive(Deserialize)]
+struct Ballot {
+    vote: String,
+    #[serde(default)]
+    reason: String,
+}
+
+/// Runs a game of Werewolf in the room: deals the roles, gives the floor to the agents,
+/// collects the votes and announces the results until a side wins.
+struct GameEngine {
+    room: Arc<Room>,
+    llms: Arc<LLMRouter>,
+    config: GameConfig,
+    /// Id of the human, who plays unless they spectate
+    human: String,
+    receiver: Receiver<Message>,
+    /// Recent messages of the room, for the prompts of the ballots
+    history: Vec<Arc<ChatMessage>>,
+    rng: StdRng,
+}
+
+/// Deals the roles between the agents and the human, and starts the game.
+pub fn start(room: Arc<Room>, llms: Arc<LLMRouter>, config: GameConfig, human: String) -> Result<(), Box<dyn Error>> {
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let mut players: Vec<String> = room.profiles().iter().map(|p| p.id.clone()).collect();
+    let spectator = if config.spectate {
+        Some(human.clone())
+    } else {
+        players.push(human.clone());
+        None
+    };
+    let game = Game::new(&players, &config, spectator, &mut rng).map_err(|e| format!("Can't start the game: {}", e))?;
+    room.start_game(game);
+    let engine = GameEngine { receiver: room.subscribe(), room: room.clone(), llms, config, human, history: Vec::new(), rng };
+    tokio::spawn(async move {
+        if let Err(e) = engine.run().await {
+            room.send_error(Arc::new(ErrorMessage { msg: format!("The game stopped: {}", e) }))
+                .expect("cannot send error msg");
+        }
+    });
+    Ok(())
+}
+
+impl GameEngine {
+    async fn run(mut self) -> Result<(), Box<dyn Error>> {
+        tokio::time::sleep(START_DELAY).await;
+        let game = self.game()?;
+        self.hidden(format!("Roles: {}", game.describe_roles()))?;
+        let wolves = self.config.werewolves;
+        self.announce(format!("A game of Werewolf begins between {}. {} of them {}{}. \
+            Each night the werewolves kill a villager in secret, and each day the village votes to lynch a suspect.",
+            mentions(&game.alive), wolves, if wolves == 1 { "is a werewolf" } else { "are werewolves" },
+            if self.config.seer { ", and one is the seer who learns the role of a player each night" } else { "" }))?;
+        if let Some(role) = game.roles.get(&self.human) {
+            let allies = match role {
+                Role::Werewolf => format!(" The werewolves are {}.", mentions(&game.werewolves())),
+                _ => String::new(),
+            };
+            self.tell(&self.human, format!("Your secret role: you are {}.{}", role.describe(), allies))?;
+        }
+        loop {
+            let victim = self.night().await?;
+            self.set_phase(Phase::Day);
+            let game = self.game()?;
+            match victim {
+                Some(victim) => self.announce(format!("Day {}. @{} was found dead this morning, they were {}.",
+                    game.round, victim, game.roles[&victim].describe()))?,
+                None => self.announce(format!("Day {}. Nobody died during the night.", game.round))?,
+            }
+            if self.check_winner()? {
+                break;
+            }
+            self.day().await?;
+            if self.check_winner()? {
+                break;
+            }
+            self.room.update_game(|g| g.round += 1);
+            self.set_phase(Phase::Night);
+        }
+        Ok(())
+    }
+
+    /// The werewolves agree on a victim and the seer inspects a player. Returns the victim.
+    async fn night(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        let game = self.game()?;
+        self.announce(format!("Night {} falls. The village sleeps, except the werewolves who choose their victim.", game.round))?;
+        let wolves = game.werewolves();
+        if wolves.len() > 1 {
+            for wolf in wolves.iter().filter(|w| **w != self.human).cloned().collect::<Vec<_>>() {
+                self.take_turn(&wolf, "It's night. Only the other werewolves hear you: \
+                    agree with them on who to kill tonight. Keep it short.").await?;
+            }
+        }
+        self.collect_votes().await?;
+        let game = self.game()?;
+        if let Some(seer) = game.seer()
+            && let Some(target) = game.votes.get(&seer) {
+            let role = game.roles[target];
+            self.room.update_game(|g| g.inspections.push((target.clone(), role)));
+            self.hidden(format!("The seer @{} learns that @{} is {}", seer, target, role.describe()))?;
+            self.tell(&seer, format!("The seer learns that @{} is {}.", target, role.describe()))?;
+        }
+        let victim = game.tally(&mut self.rng);
+        if let Some(victim) = &victim {
+            self.room.update_game(|g| g.eliminate(victim));
+            self.hidden(format!("The werewolves kill @{}", victim))?;
+        }
+        Ok(victim)
+    }
+
+    /// The village discusses, then votes to lynch a suspect.
+    async fn day(&mut self) -> Result<(), Box<dyn Error>> {
+        for _ in 0..self.config.discussion_rounds {
+            let speakers: Vec<String> = self.game()?.alive.into_iter().filter(|id| *id != self.human).collect();
+            for id in speakers.iter() {
+                self.take_turn(id, "It's day. Discuss with the others who might be a werewolf, \
+                    the village votes to lynch a suspect after the discussion. Keep it short.").await?;
+            }
+        }
+        let ballots = self.collect_votes().await?;
+        if !ballots.is_empty() {
+            let lines: Vec<String> = ballots.iter()
+                .map(|(voter, target, reason)| match reason.is_empty() {
+                    true => format!("@{} votes for @{}", voter, target),
+                    false => format!("@{} votes for @{}: {}", voter, target, reason),
+                })
+                .collect();
+            self.announce(format!("The village votes.\n{}", lines.join("\n")))?;
+        }
+        let game = self.game()?;
+        match game.tally(&mut self.rng) {
+            Some(lynched) => {
+                self.room.update_game(|g| g.eliminate(&lynched));
+                self.announce(format!("The village lynches @{}, they were {}.", lynched, game.roles[&lynched].describe()))
+            }
+            None => self.announce("The village can't agree, nobody is lynched today.".to_string()),
+        }
+    }
+
+    /// Asks every voter of the phase for a ballot. Returns the (voter, target, reason) of each ballot cast.
+    async fn collect_votes(&mut self) -> Result<Vec<(String, String, String)>, Box<dyn Error>> {
+        self.drain();
+        let game = self.game()?;
+        let mut ballots = Vec::new();
+        for voter in game.voters().into_iter().filter(|v| *v != self.human) {
+            let candidates = game.candidates(&voter);
+            let (target, reason) = match self.ask_ballot(&game, &voter, &candidates).await {
+                Some(ballot) if candidates.iter().any(|c| c == ballot.vote.trim().trim_start_matches('@')) =>
+                    (ballot.vote.trim().trim_start_matches('@').to_string(), ballot.reason),
+                _ => match candidates.choose(&mut self.rng) {
+                    Some(target) => (target.clone(), "no clear choice, voted at random".to_string()),
+                    None => continue,
+                },
+            };
+            self.room.cast_vote(&voter, &target)?;
+            self.hidden(format!("@{} votes for @{}: {}", voter, target, reason))?;
+            ballots.push((voter, target, reason));
+        }
+        if game.voters().contains(&self.human) {
+            self.wait_for_human_vote(&game).await?;
+            if let Some(target) = self.game()?.votes.get(&self.human) {
+                self.hidden(format!("@{} votes for @{}", self.human, target))?;
+                ballots.push((self.human.clone(), target.clone(), String::new()));
+            }
+        }
+        Ok(ballots)
+    }
+
+    async fn wait_for_human_vote(&mut self, game: &Game) -> Result<(), Box<dyn Error>> {
+        let task = match (game.phase, game.roles.get(&self.human)) {
+            (Phase::Day, _) => "Vote for who the village lynches",
+            (Phase::Night, Some(Role::Werewolf)) => "Vote for who the werewolves kill tonight",
+            (Phase::Night, _) => "Choose whose role you learn tonight",
+        };
+        self.tell(&self.human, format!("{} with /vote @id, among {}. You have {} seconds.",
+            task, mentions(&game.candidates(&self.human)), self.config.vote_timeout_secs))?;
+        let deadline = Instant::now() + Duration::from_secs_f64(self.config.vote_timeout_secs);
+        while !self.game()?.votes.contains_key(&self.human) {
+            if Instant::now() >= deadline {
+                return self.tell(&self.human, "Time is up, the vote closes without you.".to_string());
+            }
+            self.next_chat((Instant::now() + VOTE_POLL).min(deadline)).await?;
+        }
+        Ok(())
+    }
+
+    /// Asks the LLM of the agent who it votes for. Returns `None` if it didn't give a usable answer.
+    async fn ask_ballot(&self, game: &Game, voter: &str, candidates: &[String]) -> Option<Ballot> {
+        let profile = self.room.profiles().into_iter().find(|p| p.id == voter)?;
+        let briefing = game.briefing(voter)?;
+        let mut conversation = Vec::new();
+        for m in self.history.iter().filter(|m| m.visible_to(voter)) {
+            let label = m.visibility_label().map(|l| format!(" ({})", l)).unwrap_or_default();
+            conversation.push(format!("{}(@{}){}: {}", m.from_username, m.from_user_id, label, m.read_content().await));
+        }
+        let task = match (game.phase, game.roles.get(voter)) {
+            (Phase::Day, _) => "The village votes to lynch the player it suspects the most of being a werewolf.",
+            (Phase::Night, Some(Role::Werewolf)) => "The werewolves choose who they kill tonight.",
+            (Phase::Night, _) => "The seer chooses whose role it learns tonight.",
+        };
+        let prompt = format!("You play {}(@{}) in a game of Werewolf. Here is the background of the profile:\n\
+            {}\n\
+            Here is what the profile knows about the game:\n\
+            {}\n\
+            Here is the recent conversation the profile saw:\n\
+            {}\n\
+            \n\
+            {} Vote as the profile would, for one of {}. Output a JSON object with the fields `vote` \
+            (the id of the player, without @) and `reason` (one short sentence in the voice of the profile) and nothing else.",
+            profile.name, profile.id, profile.background, briefing, conversation.join("\n"), task, mentions(candidates));
+        let response = match self.llms.agent(voter).single_chat(Arc::new(prompt)).await {
+            Ok(response) => response,
+            Err(e) => {
+                info!("@{} failed to vote: {}", voter, e);
+                return None;
+            }
+        };
+        serde_json::from_str(extract_json_object(&response))
+            .inspect_err(|e| info!("@{} cast an invalid ballot {}: {}", voter, response, e))
+            .ok()
+    }
+
+    /// Gives the floor to the agent and waits until its message is complete.
+    async fn take_turn(&mut self, id: &str, instruction: &str) -> Result<(), Box<dyn Error>> {
+        self.drain();
+        self.room.request_turn(id, instruction, None)?;
+        let deadline = Instant::now() + Duration::from_secs_f64(self.config.turn_secs);
+        loop {
+            let chat = self.next_chat(deadline).await?;
+            match chat {
+                Some(chat) if chat.from_user_id == id => {
+                    chat.read_content().await;
+                    return Ok(());
+                }
+                Some(_) => {}
+                None => {
+                    info!("@{} didn't take its turn in time", id);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Keeps the messages of the room in the history until the next chat message, or `None` at the deadline.
+    async fn next_chat(&mut self, deadline: Instant) -> Result<Option<Arc<ChatMessage>>, Box<dyn Error>> {
+        loop {
+            match timeout_at(deadline, self.receiver.recv()).await {
+                Err(_) => return Ok(None),
+                Ok(Ok(msg)) => if let Some(chat) = self.absorb(msg) {
+                    return Ok(Some(chat));
+                },
+                Ok(Err(RecvError::Lagged(_))) => {}
+                Ok(Err(RecvError::Closed)) => return Err("the room was closed".into()),
+            }
+        }
+    }
+
+    /// Keeps the messages already received in the history.
+    fn drain(&mut self) {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(msg) => {
+                    self.absorb(msg);
+                }
+                Err(TryRecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Keeps the message in the history. Returns it if it's a chat message.
+    fn absorb(&mut self, msg: Message) -> Option<Arc<ChatMessage>> {
+        let (chat, is_chat) = match msg {
+            Message::Chat(chat) => (chat, true),
+            Message::Event(event) | Message::WorldChanged(event) => (Arc::new(event.to_chat()), false),
+            _ => return None,
+        };
+        self.history.push(chat.clone());
+        if self.history.len() > CONTEXT_SIZE {
+            self.history.remove(0);
+        }
+        is_chat.then_some(chat)
+    }
+
+    /// Ends the game if a side won. Returns whether it did.
+    fn check_winner(&mut self) -> Result<bool, Box<dyn Error>> {
+        let Some(winner) = self.game()?.check_winner() else { return Ok(false) };
+        self.room.update_game(|g| g.winner = Some(winner));
+        let side = if winner == Role::Werewolf { "werewolves" } else { "villagers" };
+        self.announce(format!("The {} win! The roles were: {}.", side, self.game()?.describe_roles()))?;
+        Ok(true)
+    }
+
+    fn set_phase(&self, phase: Phase) {
+        self.room.update_game(|g| {
+            g.phase = phase;
+            g.votes.clear();
+        });
+    }
+
+    fn game(&self) -> Result<Game, Box<dyn Error>> {
+        Ok(self.room.game().ok_or("no game is played in the room")?)
+    }
+
+    fn announce(&self, text: String) -> Result<(), Box<dyn Error>> {
+        self.room.send_event(Arc::new(EventMessage { text, visibility: Visibility::Public }))
+    }
+
+    /// Tells something to the player only, and to the spectator.
+    fn tell(&self, id: &str, text: String) -> Result<(), Box<dyn Error>> {
+        let to = std::iter::once(id.to_string()).chain(self.game()?.spectator).collect();
+        self.room.send_event(Arc::new(EventMessage { text, visibility: Visibility::Whisper(to) }))
+    }
+
+    fn hidden(&self, msg: String) -> Result<(), Box<dyn Error>> {
+        self.room.send_hidden(Arc::new(NoticeMessage { msg }))
+    }
+}
+
+fn mentions(ids: &[String]) -> String {
+    ids.iter().map(|id| format!("@{}", id)).collect::<Vec<_>>().join(", ")
+}
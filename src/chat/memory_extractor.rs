@@ -0,0 +1,94 @@
+use crate::llm::json::extract_json_array;
+use crate::dao::Store;
+use crate::llm::LLM;
+use crate::model::memory::{Memory, MemoryKind};
+use crate::model::profile::Profile;
+use crate::model::session::Session;
+use chrono::Local;
+use serde::Deserialize;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Max number of characters of transcript sent to the LLM. The end of the session is kept.
+const TRANSCRIPT_LIMIT: usize = 12000;
+
+#[derive(Deserialize)]
+struct ExtractedMemory {
+    kind: MemoryKind,
+    content: String,
+}
+
+/// Asks the LLM what the profile should remember from the session, leaving out what it already remembers.
+/// Only the messages the profile could have seen are used.
+pub async fn extract_memories(llm: &dyn LLM, profile: &Profile, session: &Session, known: &[Memory]) -> Result<Vec<Memory>, Box<dyn Error>> {
+    let mut lines = Vec::new();
+    let mut length = 0;
+    for m in session.messages.iter().rev() {
+        if !m.visibility.includes(&m.from_user_id, &profile.id) {
+            continue;
+        }
+        // Whispers are remembered as such, so they stay secrets
+        let label = m.visibility.label().map(|l| format!(" ({})", l)).unwrap_or_default();
+        let line = format!("{}(@{}){}: {}", m.from_username, m.from_user_id, label, m.content);
+        length += line.len();
+        if length > TRANSCRIPT_LIMIT {
+            break;
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        return Ok(Vec::new());
+    }
+    lines.reverse();
+    let transcript = lines.join("\n");
+    let known = if known.is_empty() {
+        "nothing yet".to_string()
+    } else {
+        known.iter().map(|m| format!("- {}", m.content)).collect::<Vec<_>>().join("\n")
+    };
+    let prompt = format!("{name}(@{id}) took part in this group chat:\n\
+        {transcript}\n\
+        \n\
+        Here is what {name} already remembers:\n\
+        {known}\n\
+        \n\
+        List the new things {name} should remember from this chat in the long term: facts about the people \
+        they talked with, important events, and promises made by or to them. Write each one as a short sentence \
+        from the point of view of an observer, with names instead of pronouns. Skip small talk and what is already remembered.\n\
+        Output a JSON array of objects with the fields `kind` (one of `person`, `event`, `promise` or `fact`) \
+        and `content`, and nothing else. Output an empty array if there is nothing worth remembering.",
+        name = profile.name, id = profile.id);
+    let response = llm.single_chat(Arc::new(prompt)).await?;
+    let extracted: Vec<ExtractedMemory> = serde_json::from_str(extract_json_array(&response))
+        .map_err(|e| format!("The LLM returned invalid memories for {}: {}", profile.id, e))?;
+    Ok(extracted.into_iter()
+        .filter(|m| !m.content.trim().is_empty())
+        .map(|m| Memory {
+            kind: m.kind,
+            content: m.content.trim().to_string(),
+            session_id: Some(session.id.clone()),
+            created_at: Local::now(),
+        })
+        .collect())
+}
+
+/// Extracts and stores the memories of each agent of the session.
+/// Returns the number of new memories of each agent, or why they couldn't be extracted.
+pub async fn remember_session(llm: &dyn LLM, store: &Store, session: &Session) -> Vec<(String, Result<usize, String>)> {
+    let mut results = Vec::new();
+    for id in session.profile_ids.iter() {
+        let result = remember(llm, store, session, id).await.map_err(|e| e.to_string());
+        results.push((id.clone(), result));
+    }
+    results
+}
+
+async fn remember(llm: &dyn LLM, store: &Store, session: &Session, id: &str) -> Result<usize, Box<dyn Error>> {
+    let profile = store.profiles.get(id).await?.ok_or(format!("Profile {} not found", id))?;
+    let mut memories = store.memories.get(id).await?;
+    let new_memories = extract_memories(llm, &profile, session, &memories).await?;
+    let count = new_memories.len();
+    memories.extend(new_memories);
+    store.memories.save(id, &memories).await?;
+    Ok(count)
+}
@@ -1,17 +1,51 @@
 use std::sync::Arc;
 use log::info;
+use crate::model::profile::Profile;
+use crate::model::poll::{Poll, PollResult};
+use crate::model::session::Visibility;
 use tokio::sync::RwLock;
-use tokio::sync::watch::Sender;
+use tokio::sync::watch::{self, Sender};
+use crate::llm::ROLE_SYSTEM;
+
+/// Content chunks of a message received so far, and whether the message is complete.
+pub type ContentState = (Arc<RwLock<Vec<String>>>, bool);
 
 #[derive(Debug)]
 pub struct ChatMessage {
     pub from_user_id: String,
     pub from_username: String,
     pub role: String,
-    pub content_stream: Arc<Sender<(Arc<RwLock<Vec<String>>>, bool)>>,
+    pub content_stream: Arc<Sender<ContentState>>,
+    /// Names of the lorebook entries included in the prompt of this reply
+    pub activated_lore: Vec<String>,
+    pub visibility: Visibility,
 }
 
 impl ChatMessage {
+    /// Whether the participant could have seen this message.
+    pub fn visible_to(&self, id: &str) -> bool {
+        self.visibility.includes(&self.from_user_id, id)
+    }
+
+    /// The visibility of a reply from `id` to this message: whispers are answered in the same circle.
+    pub fn reply_visibility(&self, id: &str) -> Visibility {
+        match &self.visibility {
+            Visibility::Public => Visibility::Public,
+            Visibility::Whisper(to) => Visibility::Whisper(
+                std::iter::once(&self.from_user_id).chain(to.iter())
+                    .filter(|t| *t != id)
+                    .cloned()
+                    .collect()),
+            // The room knows who is present when the reply is sent
+            Visibility::Local { .. } => self.visibility.clone(),
+        }
+    }
+
+    /// Describes who the message is whispered to or where it's said, for prompts and transcripts.
+    pub fn visibility_label(&self) -> Option<String> {
+        self.visibility.label()
+    }
+
     pub async fn read_content(&self) -> String {
         let mut sub = self.content_stream.subscribe();
         let mut final_content: Arc<RwLock<Vec<String>>> = Arc::new(RwLock::new(vec![]));
@@ -31,7 +65,17 @@ impl ChatMessage {
                 break;
             }
         }
-        final_content.read().await.join("").replace(&format!("{}(@{}): ", self.from_username, self.from_user_id), "")
+        self.strip_name(final_content.read().await.join(""))
+    }
+
+    /// The content received so far, without waiting for the message to complete.
+    pub async fn current_content(&self) -> String {
+        let content = self.content_stream.borrow().0.clone();
+        self.strip_name(content.read().await.join(""))
+    }
+
+    fn strip_name(&self, content: String) -> String {
+        content.replace(&format!("{}(@{}): ", self.from_username, self.from_user_id), "")
     }
 }
 
@@ -40,8 +84,80 @@ pub struct ErrorMessage {
     pub msg: String,
 }
 
+/// A notice from the system shown in the chat, e.g. a reloaded profile.
+#[derive(Debug)]
+pub struct NoticeMessage {
+    pub msg: String,
+}
+
+/// Id of the world in the history and the transcripts, as the sender of the events.
+pub const WORLD_ID: &str = "world";
+
+/// Id of the narrator of the room in the history and the transcripts.
+pub const NARRATOR_ID: &str = "narrator";
+
+/// Something happening in the world of the room, e.g. `The power goes out.`
+#[derive(Debug)]
+pub struct EventMessage {
+    pub text: String,
+    pub visibility: Visibility,
+}
+
+impl EventMessage {
+    /// The event as a message from the world, to keep it in the history of the chat.
+    pub fn to_chat(&self) -> ChatMessage {
+        let (sender, _rx) = watch::channel((Arc::new(RwLock::new(vec![self.text.clone()])), true));
+        ChatMessage {
+            from_user_id: WORLD_ID.to_string(),
+            from_username: "World".to_string(),
+            role: ROLE_SYSTEM.to_string(),
+            content_stream: Arc::new(sender),
+            activated_lore: Vec::new(),
+            visibility: self.visibility.clone(),
+        }
+    }
+}
+
+/// Asks an agent to speak now, e.g. when a game gives it the floor.
+#[derive(Debug)]
+pub struct TurnRequest {
+    pub profile_id: String,
+    /// Added to the system prompt of the reply
+    pub instruction: String,
+    /// The reply is cut off after this number of words
+    pub max_words: Option<usize>,
+}
+
+/// Controls how the agents take turns, sent by the human.
+#[derive(Clone, Copy, Debug)]
+pub enum SimulationCommand {
+    /// Stop the agents from replying on their own
+    Pause,
+    Resume,
+    /// Play a single turn, even while paused
+    Step,
+    /// Pass the turn the turn protocol gave the human, closing the protocol if they host or moderate it
+    End,
+}
+
 #[derive(Clone, Debug)]
 pub enum Message {
     Chat(Arc<ChatMessage>),
     Error(Arc<ErrorMessage>),
+    Notice(Arc<NoticeMessage>),
+    Event(Arc<EventMessage>),
+    /// The result of an action in the world, seen by the participants where it happened.
+    WorldChanged(Arc<EventMessage>),
+    /// A profile in the room was replaced by a new version.
+    ProfileUpdated(Arc<Profile>),
+    Simulation(SimulationCommand),
+    /// Gives the floor to an agent, bypassing the speaker selection.
+    Turn(Arc<TurnRequest>),
+    /// Something the participants don't know, e.g. a secret role, kept for the transcript
+    /// and shown to a spectating human.
+    Hidden(Arc<NoticeMessage>),
+    /// Asks every agent to vote on a poll, sent by the human.
+    Poll(Arc<Poll>),
+    /// The ballots of the agents once they all voted.
+    PollResult(Arc<PollResult>),
 }
\ No newline at end of file
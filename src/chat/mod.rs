@@ -1,3 +1,17 @@
 pub mod plan_agent;
 pub mod room;
-pub mod message;
\ No newline at end of file
+pub mod message;
+pub mod session_recorder;
+pub mod profile_watcher;
+pub mod memory_extractor;
+pub mod evaluator;
+pub mod relationship_evaluator;
+pub mod world_clock;
+pub mod event_engine;
+pub mod world_engine;
+pub mod game_engine;
+pub mod rpg_engine;
+pub mod protocol_engine;
+pub mod poll_engine;
+pub mod mood_evaluator;
+pub mod room_builder;
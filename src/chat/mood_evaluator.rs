@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+use async_trait::async_trait;
+use log::info;
+use serde::Deserialize;
+use crate::chat::evaluator::{self, Evaluator};
+use crate::chat::message::ChatMessage;
+use crate::chat::room::Room;
+use crate::dao::profile_state_dao::ProfileStateDao;
+use crate::llm::LLM;
+
+/// Max change of valence, arousal or an emotion from a single message, to keep moods from swinging wildly.
+const MAX_CHANGE: f32 = 0.3;
+
+#[derive(Deserialize)]
+struct MoodChange {
+    who: String,
+    #[serde(default)]
+    valence: f32,
+    #[serde(default)]
+    arousal: f32,
+    #[serde(default)]
+    emotions: BTreeMap<String, f32>,
+}
+
+struct MoodEvaluator;
+
+/// Updates the moods of the agents after each message in the room.
+pub fn start(room: Arc<Room>, llm: Arc<dyn LLM>) {
+    evaluator::start(room, llm, MoodEvaluator);
+}
+
+#[async_trait]
+impl Evaluator for MoodEvaluator {
+    const NAME: &'static str = "moods";
+    type Change = MoodChange;
+
+    fn prompt(&self, room: &Room, witnesses: &[String], recent: &str, line: &str) -> String {
+        let current = witnesses.iter()
+            .map(|id| {
+                let mood = room.mood(id);
+                let emotions = mood.emotions.iter().map(|(name, i)| format!("{} {:.1}", name, i)).collect::<Vec<_>>();
+                format!("@{}: valence {:.1}, arousal {:.1}, emotions: {}", id, mood.valence, mood.arousal,
+                    if emotions.is_empty() { "none".to_string() } else { emotions.join(", ") })
+            })
+            .collect::<Vec<_>>().join("\n");
+        let witnesses_str = witnesses.iter().map(|id| format!("@{}", id)).collect::<Vec<_>>().join(", ");
+        format!("You track the moods of the participants of a group chat.\n\
+            Here is the recent conversation:\n\
+            {recent}\n\
+            Here is the new message:\n\
+            {line}\n\
+            Here are the current moods, with valence (unhappy to happy) and arousal (calm to excited) from -1 to 1, \
+            and named emotions from 0 to 1:\n\
+            {current}\n\
+            \n\
+            How does the new message change the moods of {witnesses_str}? \
+            Output a JSON array of objects with the fields `who` (participant id without @), \
+            `valence` and `arousal` (changes between -{MAX_CHANGE} and {MAX_CHANGE}) and optionally `emotions` \
+            (an object of emotion names, e.g. `joy`, `anger` or `curiosity`, to changes between -{MAX_CHANGE} and {MAX_CHANGE}). \
+            Only include moods that change, and output an empty array if nothing changes. Output nothing else.")
+    }
+
+    async fn apply(&self, room: &Room, _chat: &ChatMessage, witnesses: &[String], changes: Vec<MoodChange>) -> Result<(), Box<dyn Error>> {
+        for change in changes {
+            let who = change.who.trim_start_matches('@');
+            if !witnesses.iter().any(|w| w == who) {
+                info!("Ignoring mood change of @{}", who);
+                continue;
+            }
+            let emotions = change.emotions.into_iter().map(|(name, c)| (name, c.clamp(-MAX_CHANGE, MAX_CHANGE))).collect();
+            room.adjust_mood(who, change.valence.clamp(-MAX_CHANGE, MAX_CHANGE), change.arousal.clamp(-MAX_CHANGE, MAX_CHANGE), &emotions);
+        }
+        Ok(())
+    }
+}
+
+/// Saves the moods that changed during the chat, so they carry over to the next chats.
+/// Saved once at the end, to not race with the other evaluators saving the same states.
+pub async fn save(room: &Room, dao: &dyn ProfileStateDao) -> Result<(), Box<dyn Error>> {
+    for profile in room.profiles() {
+        let Some((mood, changed_at)) = room.changed_mood(&profile.id) else { continue };
+        let mut state = dao.get(&profile.id).await?;
+        state.mood = Some(mood);
+        state.mood_changed_at = Some(changed_at);
+        dao.save(&profile.id, &state).await?;
+    }
+    Ok(())
+}
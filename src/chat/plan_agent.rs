@@ -1,31 +1,88 @@
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::{watch, RwLock};
 use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
 use log::info;
+use rand::seq::IndexedRandom;
 use tokio_stream::StreamExt;
-use crate::chat::message::{ChatMessage, ErrorMessage, Message};
-use crate::chat::room::Room;
-use crate::llm::{LLMConversation, LLM, ROLE_ASSISTANT};
+use crate::chat::message::{ChatMessage, ErrorMessage, Message, NoticeMessage, SimulationCommand, NARRATOR_ID};
+use crate::chat::room::{Room, TIME_FORMAT};
+use crate::chat::{rpg_engine, world_engine};
+use crate::llm::router::LLMRouter;
+use crate::llm::{LLMConversation, LLMTool, LLMToolCall, LLM, ROLE_ASSISTANT};
+use crate::dao::memory_dao::MemoryDao;
+use crate::model::dice::DiceRoll;
+use crate::model::lorebook::{self, Lorebook};
+use crate::model::memory;
 use crate::model::profile::Profile;
+use crate::model::room_config::{AutonomousConfig, NarratorConfig, RoomConfig, RoomLimits, SpeakerSelection};
+use crate::model::session::Visibility;
+
+/// Number of recent narrations kept as the state of the scene in the prompts.
+const SCENE_SIZE: usize = 3;
+const DEFAULT_NARRATOR_PROMPT: &str = "Describe the scene and what happens around the characters, \
+    resolve the actions they attempt with fair and interesting outcomes, and move the story forward when it stalls.";
+
+enum NextSpeaker {
+    Agent(Arc<Profile>),
+    Narrator,
+}
 
 pub struct PlanAgent {
-    llm: Arc<dyn LLM>,
+    llms: Arc<LLMRouter>,
     room: Arc<Room>,
     msg_receiver: Receiver<Message>,
     recent_chats: Vec<Arc<ChatMessage>>,
     profiles_summarize: String,
+    speaker_selection: SpeakerSelection,
+    limits: RoomLimits,
+    /// Number of messages in the room so far
+    message_count: usize,
+    /// Id of the agent that replied last, for round-robin selection
+    last_speaker: Option<String>,
+    lorebook: Lorebook,
+    memories: Arc<dyn MemoryDao>,
+    autonomous: AutonomousConfig,
+    /// Agents don't reply on their own while paused
+    paused: bool,
+    /// When the next reply is due in autonomous mode, after the pace delay
+    next_turn_at: Option<Instant>,
+    narrator: Option<NarratorConfig>,
+    /// Number of character messages since the narrator last spoke
+    since_narration: usize,
+    /// Recent narrations, describing the current state of the scene
+    scene: Vec<String>,
+    /// Agents only speak when given the floor, e.g. by a game or a turn protocol
+    driven: bool,
+    /// Agents in an excited mood speak more often
+    mood_talkativeness: bool,
 }
 
 impl PlanAgent {
-    pub fn new(llm: Arc<dyn LLM>, room: Arc<Room>) -> Self {
+    pub fn new(llms: Arc<LLMRouter>, room: Arc<Room>, config: &RoomConfig, lorebook: Lorebook, memories: Arc<dyn MemoryDao>) -> Self {
         PlanAgent{
-            llm,
+            llms,
             room: room.clone(),
             msg_receiver: room.subscribe(),
             recent_chats: Vec::new(),
-            profiles_summarize: Self::summarize_profile(&room.profiles)
+            profiles_summarize: Self::summarize_profile(&room.profiles()),
+            speaker_selection: config.speaker_selection,
+            limits: config.limits.clone(),
+            message_count: 0,
+            last_speaker: None,
+            lorebook,
+            memories,
+            autonomous: config.autonomous.clone(),
+            paused: false,
+            next_turn_at: None,
+            narrator: config.narrator.clone(),
+            since_narration: 0,
+            scene: Vec::new(),
+            driven: config.game.is_some() || config.protocol.is_some(),
+            mood_talkativeness: config.mood_talkativeness,
         }
     }
 
@@ -46,26 +103,156 @@ impl PlanAgent {
     }
 
     async fn loop_worker(&mut self) -> Result<(), Box<dyn Error>> {
-        let msg = self.msg_receiver.recv().await?;
+        // Keep reading commands during the pace delay, so /pause and /step apply right away
+        let msg = if let Some(at) = self.next_turn_at {
+            match timeout_at(at, self.msg_receiver.recv()).await {
+                Ok(msg) => msg?,
+                Err(_) => {
+                    self.next_turn_at = None;
+                    if self.paused {
+                        return Ok(());
+                    }
+                    self.take_turn().await?;
+                    return Ok(());
+                }
+            }
+        } else if self.autonomous.enabled && !self.paused && !self.driven {
+            let idle_timeout = Duration::from_secs_f64(self.autonomous.idle_timeout_secs);
+            match tokio::time::timeout(idle_timeout, self.msg_receiver.recv()).await {
+                Ok(msg) => msg?,
+                Err(_) => return self.start_thread().await,
+            }
+        } else {
+            self.msg_receiver.recv().await?
+        };
         match msg {
             Message::Chat(chat) => {
                 info!("received chat: {:?}", chat);
                 Ok(self.on_chat(chat).await?)
             }
+            // Events go in the history like messages from the world, for the agents to react to
+            Message::Event(event) => self.on_chat(Arc::new(event.to_chat())).await,
+            // What the agents did is part of the history, but doesn't call for a reply by itself
+            Message::WorldChanged(change) => {
+                self.remember(Arc::new(change.to_chat()));
+                Ok(())
+            }
+            Message::Simulation(command) => self.on_simulation(command).await,
+            Message::Turn(turn) => {
+                let Some(profile) = self.room.profiles().into_iter().find(|p| p.id == turn.profile_id) else { return Ok(()) };
+                // Games and protocols stay within the budget too, the reply is counted with the other messages
+                if self.limit_reached() {
+                    return self.room.send_notice(Arc::new(NoticeMessage {
+                        msg: format!("The room reached its message limit, @{} can't take its turn", profile.id),
+                    }));
+                }
+                self.last_speaker = Some(profile.id.clone());
+                self.complete_chat(&profile, Some(&turn.instruction), turn.max_words).await
+            }
+            Message::ProfileUpdated(profile) => {
+                info!("profile updated: {}", profile.id);
+                self.profiles_summarize = Self::summarize_profile(&self.room.profiles());
+                Ok(())
+            }
             _ => Ok(())
         }
     }
 
-    fn summarize_profile(profiles: &Vec<Arc<Profile>>) -> String {
+    fn summarize_profile(profiles: &[Arc<Profile>]) -> String {
         profiles.iter()
             .map(|p| format!("ID: {}\nName: {}\nBackground: {}", p.id, p.name, p.background))
             .collect::<Vec<String>>().join("\n--------------")
     }
 
-    async fn get_prompt(profile_summary: &String, recent_messages: &Vec<Arc<ChatMessage>>) -> String {
+    fn summarize_human(user: Option<&Arc<Profile>>) -> String {
+        match user {
+            Some(p) => format!("ID: {}\nName: {}\nBackground: {}", p.id, p.name, p.background),
+            None => "Nothing is known about the human.".to_string(),
+        }
+    }
+
+    fn summarize_topic(topic: Option<String>) -> String {
+        topic.unwrap_or_else(|| "No topic is set, the conversation is free.".to_string())
+    }
+
+    /// The simulated time and what each agent is doing, if the room has a clock.
+    fn summarize_time(room: &Room) -> String {
+        let Some(time) = room.world_time() else { return "The room has no notion of time.".to_string() };
+        let mut lines = vec![format!("It is {}.", time.format(TIME_FORMAT))];
+        for p in room.profiles() {
+            if let Some(activity) = room.activity(&p) {
+                let note = if activity.availability.can_talk() { "" } else { ", can't take part in the chat" };
+                lines.push(format!("@{} is {} ({:?}{})", p.id, activity.activity, activity.availability, note));
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn summarize_world(room: &Room) -> String {
+        let Some(world) = room.world() else { return "The room has no places, everyone hears everything.".to_string() };
+        let mut lines: Vec<String> = world.positions.iter()
+            .map(|(id, location)| format!("@{} is at {}", id, world.location_name(location)))
+            .collect();
+        if lines.is_empty() {
+            lines.push("Nobody is anywhere in particular.".to_string());
+        }
+        lines.join("\n")
+    }
+
+    fn summarize_narrator(narrator: Option<&NarratorConfig>) -> String {
+        match narrator {
+            Some(narrator) => format!("@{NARRATOR_ID} ({}) is the narrator, not a character. Select it to describe the scene, \
+                resolve what the characters try to do or move the story forward, but not after every message.", narrator.name),
+            None => format!("There is no narrator, never select @{NARRATOR_ID}."),
+        }
+    }
+
+    fn summarize_sheets(room: &Room) -> String {
+        let sheets = room.sheets();
+        if sheets.is_empty() {
+            return "Nobody has a character sheet.".to_string();
+        }
+        sheets.iter().map(|(id, sheet)| format!("@{}: {}", id, sheet.describe())).collect::<Vec<_>>().join("\n")
+    }
+
+    fn summarize_moods(room: &Room) -> String {
+        room.profiles().iter()
+            .map(|p| {
+                let mood = room.mood(&p.id);
+                format!("@{}: valence {:.1}, arousal {:.1}, {}", p.id, mood.valence, mood.arousal, mood.describe())
+            })
+            .collect::<Vec<_>>().join("\n")
+    }
+
+    fn summarize_relationships(room: &Room) -> String {
+        let relationships = room.all_relationships();
+        if relationships.is_empty() {
+            return "None known.".to_string();
+        }
+        relationships.iter()
+            .map(|(from, to, r)| format!("@{} -> @{}: affinity {:.1}, trust {:.1}, {}", from, to, r.affinity, r.trust, r.description))
+            .collect::<Vec<_>>().join("\n")
+    }
+
+    async fn get_prompt(&self) -> String {
+        let profile_summary = &self.profiles_summarize;
+        let human_summary = Self::summarize_human(self.room.user().as_ref());
+        let topic = Self::summarize_topic(self.room.topic());
+        let relationships = Self::summarize_relationships(&self.room);
+        let moods = if self.mood_talkativeness {
+            format!("Agents in an excited mood are more likely to speak, calm or drowsy ones less.\n\
+                Here are the moods of the agents, with valence and arousal from -1 to 1: \n\
+                {}\n", Self::summarize_moods(&self.room))
+        } else {
+            String::new()
+        };
+        let time = Self::summarize_time(&self.room);
+        let world = Self::summarize_world(&self.room);
+        let narrator = Self::summarize_narrator(self.narrator.as_ref());
         let mut recent_msg_vec = Vec::new();
-        for m in recent_messages.iter() {
-            recent_msg_vec.push(format!("{}(@{}): {}", m.from_username, m.from_user_id, m.read_content().await));
+        for m in self.recent_chats.iter() {
+            let label = m.visibility_label().map(|l| format!(" ({})", l)).unwrap_or_default();
+            recent_msg_vec.push(format!("{}(@{}){}: {}", m.from_username, m.from_user_id, label, m.read_content().await));
         }
         let recent_msg_str = recent_msg_vec.join("\n");
         format!("You are given a summary of profiles for all the LLM agent in the conversation.\
@@ -77,66 +264,517 @@ impl PlanAgent {
         * Only select the profile from the profile summary. The recent conversations also contain the real user IDs that you shouldn't select from.
         \n\
         Follow the output format strictly and output nothing else.\n\
-        If the last message is sent by the user, there always should have an agent to reply.\
+        If the last message is sent by the user, there always should have an agent to reply. \
+        Messages from @world are events happening around the agents, the agents should react to them. \
         Otherwise it's optional for other agents to reply.\n\
+        Prefer agents that have something to contribute to the topic of the room.\n\
+        Messages marked as whispers are only seen by their sender and the participants they are whispered to. \
+        If the last message is a whisper, only select an agent it is whispered to.\n\
+        Here is the topic of the room: \n\
+        {topic}\n\
+        Agents with strong feelings, good or bad, about the last speaker are more likely to reply to them.\n\
+        Here are the relationships between the participants, with affinity and trust from -1 to 1: \n\
+        {relationships}\n\
+        {moods}\
+        Never select agents who can't take part in the chat because they are away or asleep.\n\
+        Here is the current time and what the agents are doing: \n\
+        {time}\n\
+        Messages said at a location are only heard by the participants there. \
+        If the last message was said at a location, only select an agent who was there.\n\
+        Here is where the participants are: \n\
+        {world}\n\
+        Here is the narrator of the room: \n\
+        {narrator}\n\
         Here are the agent profile summary: \n\
         {profile_summary}
+        Here is the human in this room. It's not an agent and must never be selected: \n\
+        {human_summary}
         Here are the recent conversations: \n\
         {recent_msg_str}
         ")
     }
 
     async fn on_chat(&mut self, msg: Arc<ChatMessage>) -> Result<(), Box<dyn Error>> {
+        if self.room.profiles().iter().any(|p| p.id == msg.from_user_id) {
+            self.since_narration += 1;
+        }
+        self.remember(msg);
+        self.message_count += 1;
+        if let Some(max_messages) = self.limits.max_messages
+            && self.message_count == max_messages {
+            self.room.send_notice(Arc::new(NoticeMessage {
+                msg: format!("The room reached its limit of {} messages, agents stop replying", max_messages),
+            }))?;
+        }
+        if self.paused || self.driven {
+            return Ok(());
+        }
+        if self.autonomous.enabled {
+            // A newer message postpones the reply, which then answers the latest one
+            self.next_turn_at = Some(Instant::now() + Duration::from_secs_f64(self.autonomous.pace_secs));
+            return Ok(());
+        }
+        self.take_turn().await?;
+        Ok(())
+    }
+
+    fn remember(&mut self, msg: Arc<ChatMessage>) {
         self.recent_chats.push(msg);
-        let prompt = Self::get_prompt(&self.profiles_summarize, &self.recent_chats).await;
-        let next_user = self.llm.single_chat(Arc::new(prompt)).await?;
+        if self.recent_chats.len() > self.limits.history_size {
+            self.recent_chats.drain(..self.recent_chats.len() - self.limits.history_size);
+        }
+    }
+
+    fn limit_reached(&self) -> bool {
+        self.limits.max_messages.is_some_and(|max| self.message_count >= max)
+    }
+
+    /// Lets the next agent reply to the last message. Returns whether an agent replied.
+    async fn take_turn(&mut self) -> Result<bool, Box<dyn Error>> {
+        if self.limit_reached() {
+            return Ok(false);
+        }
+        let Some(msg) = self.recent_chats.last().cloned() else { return Ok(false) };
+        if self.narrator.as_ref().and_then(|n| n.every).is_some_and(|every| self.since_narration >= every) {
+            self.narrate().await?;
+            return Ok(true);
+        }
+        // Messages from the human and world events always deserve a reply
+        let needs_reply = msg.role != ROLE_ASSISTANT;
+        // Only the available agents who could see the message can reply to it
+        let candidates: Vec<Arc<Profile>> = self.room.profiles().into_iter()
+            .filter(|p| p.id != msg.from_user_id && msg.visible_to(&p.id) && self.room.is_available(p))
+            .collect();
+        let next_speaker = match self.speaker_selection {
+            // The planner may pick an agent who didn't see the message or can't reply
+            SpeakerSelection::Planner => match self.plan_next_speaker().await? {
+                Some(NextSpeaker::Agent(profile)) if !candidates.iter().any(|c| c.id == profile.id) => {
+                    info!("Plan agent selected {} who can't reply to the last message", profile.id);
+                    if needs_reply { self.random_speaker(&candidates, &msg) } else { None }
+                }
+                next => next,
+            },
+            // Without a planner, agents only reply to the human to avoid replying to each other forever,
+            // unless the room runs on its own
+            _ if !needs_reply && !self.autonomous.enabled => None,
+            SpeakerSelection::RoundRobin => {
+                let profiles = self.room.profiles();
+                let last_index = self.last_speaker.as_ref()
+                    .and_then(|id| profiles.iter().position(|p| &p.id == id));
+                let start = last_index.map_or(0, |i| i + 1);
+                (0..profiles.len())
+                    .map(|offset| &profiles[(start + offset) % profiles.len()])
+                    .find(|p| candidates.iter().any(|c| c.id == p.id))
+                    .cloned()
+                    .map(NextSpeaker::Agent)
+            }
+            SpeakerSelection::Random => self.random_speaker(&candidates, &msg),
+        };
+        match next_speaker {
+            Some(NextSpeaker::Agent(profile)) => {
+                self.last_speaker = Some(profile.id.clone());
+                self.complete_chat(&profile, None, None).await?;
+                Ok(true)
+            }
+            Some(NextSpeaker::Narrator) => {
+                self.narrate().await?;
+                Ok(true)
+            }
+            None => {
+                info!("No reply needed from plan agent.");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Lets a random agent bring up something new when the room has gone quiet.
+    async fn start_thread(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.limit_reached() {
+            return Ok(());
+        }
+        let profiles: Vec<Arc<Profile>> = self.room.profiles().into_iter()
+            .filter(|p| self.room.is_available(p))
+            .collect();
+        let others: Vec<&Arc<Profile>> = profiles.iter()
+            .filter(|p| self.last_speaker.as_ref() != Some(&p.id))
+            .collect();
+        let Some(profile) = others.choose_weighted(&mut rand::rng(), |p| self.talkativeness(&p.id)).ok().map(|p| (*p).clone())
+            .or_else(|| profiles.first().cloned()) else { return Ok(()) };
+        self.last_speaker = Some(profile.id.clone());
+        self.complete_chat(&profile, Some("Nobody has said anything for a while. Start a new thread: \
+            bring up something new that fits the topic of the room and the profile, instead of replying to the last message."), None).await
+    }
+
+    /// Picks one of the candidates to reply to the message at random.
+    /// Agents with strong feelings about the last speaker are more likely to reply.
+    fn random_speaker(&self, candidates: &[Arc<Profile>], msg: &ChatMessage) -> Option<NextSpeaker> {
+        candidates.choose_weighted(&mut rand::rng(), |p| {
+            let feelings = 1.0 + self.room.relationships(&p.id).get(&msg.from_user_id).map_or(0.0, |r| r.affinity.abs());
+            feelings * self.talkativeness(&p.id)
+        }).ok().cloned().map(NextSpeaker::Agent)
+    }
+
+    /// How much more likely the agent is to speak than others, from its mood if it affects talkativeness.
+    fn talkativeness(&self, id: &str) -> f32 {
+        if self.mood_talkativeness { self.room.mood(id).talkativeness() } else { 1.0 }
+    }
+
+    async fn on_simulation(&mut self, command: SimulationCommand) -> Result<(), Box<dyn Error>> {
+        match command {
+            SimulationCommand::Pause => {
+                self.paused = true;
+                self.next_turn_at = None;
+                self.room.set_clock_running(false);
+                self.room.send_notice(Arc::new(NoticeMessage { msg: "Simulation paused, /step to play one turn".to_string() }))
+            }
+            SimulationCommand::Resume => {
+                self.paused = false;
+                self.room.set_clock_running(self.autonomous.enabled);
+                self.room.send_notice(Arc::new(NoticeMessage { msg: "Simulation resumed".to_string() }))?;
+                if self.autonomous.enabled && !self.take_turn().await? {
+                    self.start_thread().await?;
+                }
+                Ok(())
+            }
+            SimulationCommand::Step => {
+                // The step replaces the reply that was due
+                self.next_turn_at = None;
+                if self.limit_reached() {
+                    return self.room.send_notice(Arc::new(NoticeMessage { msg: "The room reached its message limit".to_string() }));
+                }
+                if !self.take_turn().await? {
+                    self.start_thread().await?;
+                }
+                Ok(())
+            }
+            // Handled by the turn protocol
+            SimulationCommand::End => Ok(()),
+        }
+    }
+
+    async fn plan_next_speaker(&self) -> Result<Option<NextSpeaker>, Box<dyn Error>> {
+        let prompt = self.get_prompt().await;
+        let next_user = self.llms.planner().single_chat(Arc::new(prompt)).await?;
         if next_user.starts_with("@") {
             let next_id = next_user.trim_start_matches("@").to_string();
-            match self.room.profiles.iter().find(|p| p.id == next_id) {
-                Some(profile) => Ok(self.complete_chat(profile).await?),
+            if self.narrator.is_some() && next_id == NARRATOR_ID {
+                // The narrator doesn't follow up on its own narration
+                let narrated_last = self.recent_chats.last().is_some_and(|m| m.from_user_id == NARRATOR_ID);
+                return Ok((!narrated_last).then_some(NextSpeaker::Narrator));
+            }
+            if self.room.user().is_some_and(|user| user.id == next_id) {
+                return Err(format!("Plan agent selected the human {} to reply", next_id).into());
+            }
+            match self.room.profiles().into_iter().find(|p| p.id == next_id) {
+                Some(profile) => Ok(Some(NextSpeaker::Agent(profile))),
                 None => Err(format!("No profile found for id {}", next_id).into()),
             }
         } else if next_user.eq("no reply") {
-            info!("No reply needed from plan agent.");
-            Ok(())
+            Ok(None)
         } else {
             Err(format!("Got unexpected result from plan agent: {}", next_user).into())
         }
     }
 
-    async fn complete_chat(&self, profile: &Profile) -> Result<(), Box<dyn Error>> {
-        // TODO: include profile conversation examples
+    /// Streams the reply of the profile into the room. The instruction is added to the system prompt,
+    /// and the reply is cut off after `max_words` words.
+    async fn complete_chat(&self, profile: &Profile, instruction: Option<&str>, max_words: Option<usize>) -> Result<(), Box<dyn Error>> {
+        // Speech the game refuses, e.g. of a dead player or a villager at night, is never sent
+        if let Err(e) = self.reply_visibility(&profile.id) {
+            info!("@{} can't reply: {}", profile.id, e);
+            return Ok(());
+        }
+        let conversation = self.conversation_seen_by(&profile.id).await;
+        let scanned_text = self.scanned_text(&conversation);
+        let lore = lorebook::activate(self.lorebook.entries.iter().chain(profile.lorebook.iter()),
+            &scanned_text, self.limits.lore_token_budget);
+        let lore_summary = if lore.is_empty() {
+            "Nothing in particular.".to_string()
+        } else {
+            lore.iter().map(|e| format!("* {}: {}", e.name, e.content)).collect::<Vec<_>>().join("\n")
+        };
+        let memories = self.memories.get(&profile.id).await?;
+        let recalled = memory::retrieve(&memories, &scanned_text, self.limits.memory_count);
+        let memory_summary = if recalled.is_empty() {
+            "Nothing that comes to mind.".to_string()
+        } else {
+            recalled.iter().map(|m| format!("* {}", m.content)).collect::<Vec<_>>().join("\n")
+        };
+        let relationships = self.room.relationships(&profile.id);
+        let relationship_summary = if relationships.is_empty() {
+            "You don't know the others yet.".to_string()
+        } else {
+            relationships.iter().map(|(to, r)| format!("* {}", r.describe(to))).collect::<Vec<_>>().join("\n")
+        };
+        let mood_summary = self.room.mood(&profile.id).describe();
+        let time_summary = match (self.room.world_time(), self.room.activity(profile)) {
+            (Some(time), Some(activity)) => format!("It is {}. The profile is {}.", time.format(TIME_FORMAT), activity.activity),
+            (Some(time), None) => format!("It is {}.", time.format(TIME_FORMAT)),
+            (None, _) => "Unknown.".to_string(),
+        };
+        let scene_summary = if self.scene.is_empty() {
+            "Nothing was narrated yet.".to_string()
+        } else {
+            self.scene.join("\n")
+        };
+        let examples = profile.examples_within(self.limits.examples_token_budget);
+        let examples_summary = if examples.is_empty() {
+            "No examples.".to_string()
+        } else {
+            examples.iter().map(|e| format!("* {}", e)).collect::<Vec<_>>().join("\n")
+        };
         let system_prompt = format!("You are simulating a profile in a group chat to reply a new message. \
-            You must reply the message.\n\
+            You must reply the message. Messages marked as whispers were only seen by their sender and recipients; \
+            never reveal what was whispered to you to participants who didn't see it unless the profile would.\n\
             Here is the background of the profile: \n\
             id: {}\n\
             name: {}\n\
-            background:\n{}\
-            ", profile.id, profile.name, profile.background);
+            background:\n{}\n\
+            Here are messages the profile wrote, match their voice and style without repeating them: \n\
+            {}\n\
+            Here is the human in this room: \n\
+            {}\n\
+            Here is the topic of the room, stay in it: \n\
+            {}\n\
+            Here is what the profile knows about the world that is relevant to the conversation: \n\
+            {}\n\
+            Here is the scene as the narrator described it: \n\
+            {}\n\
+            Here is what the profile remembers from past conversations: \n\
+            {}\n\
+            Here is how the profile feels about the other participants: \n\
+            {}\n\
+            Here is the mood of the profile right now, let it color the reply: \n\
+            {}\n\
+            Here is the current time and what the profile is doing: \n\
+            {}\
+            ", profile.id, profile.name, profile.background, examples_summary, Self::summarize_human(self.room.user().as_ref()),
+            Self::summarize_topic(self.room.topic()), lore_summary, scene_summary, memory_summary, relationship_summary, mood_summary, time_summary);
+        let world = self.room.world();
+        let system_prompt = match &world {
+            Some(world) => format!("{}\n\
+                Here is where the profile is and what is around it: \n\
+                {}\n\
+                Use the tools to act in the world: go to other locations, take objects and give them to others. \
+                Only the participants at the same location hear what the profile says.",
+                system_prompt, world.describe_surroundings(&profile.id).unwrap_or_else(|| "Nowhere in particular.".to_string())),
+            None => system_prompt,
+        };
+        let system_prompt = match self.room.game().and_then(|g| g.briefing(&profile.id)) {
+            Some(briefing) => format!("{}\n\
+                Here is the game the profile plays, never reveal a secret role unless the profile would: \n\
+                {}", system_prompt, briefing),
+            None => system_prompt,
+        };
+        let system_prompt = if self.room.plays_rpg() {
+            format!("{}\n\
+                Here are the character sheets of the participants: \n\
+                {}\n\
+                Use the roll tool for anything the profile attempts that is left to chance, never make up a result.",
+                system_prompt, Self::summarize_sheets(&self.room))
+        } else {
+            system_prompt
+        };
+        let system_prompt = match instruction {
+            Some(instruction) => format!("{}\n{}", system_prompt, instruction),
+            None => system_prompt,
+        };
+        let activated_lore = lore.iter().map(|e| e.name.clone()).collect();
+        let llm = self.llms.agent(&profile.id);
+        let mut tools = Vec::new();
+        if world.is_some() {
+            tools.extend(world_engine::tools());
+        }
+        if self.room.plays_rpg() {
+            tools.extend(rpg_engine::tools(false));
+        }
+        if tools.is_empty() {
+            let msg = Self::new_reply(&profile.id, &profile.name, activated_lore, self.reply_visibility(&profile.id)?);
+            self.stream_reply(llm.as_ref(), msg, &system_prompt, &conversation, max_words).await?;
+            return Ok(());
+        }
+        // Actions are applied before the reply, so the profile is heard where it ends up
+        let acted = self.act(llm.as_ref(), &profile.id, &system_prompt, &conversation, &tools).await?;
+        let msg = Self::new_reply(&profile.id, &profile.name, activated_lore, self.reply_visibility(&profile.id)?);
+        self.send_reply(llm.as_ref(), msg, acted, &system_prompt, &conversation, max_words).await?;
+        Ok(())
+    }
+
+    /// Lets the speaker act with the tools. Returns what it says and the dice it rolled.
+    async fn act(&self, llm: &dyn LLM, actor: &str, system_prompt: &str, conversation: &[LLMConversation],
+                 tools: &[LLMTool]) -> Result<(String, Vec<DiceRoll>), Box<dyn Error>> {
+        let response = llm.complete_with_tools(system_prompt, conversation, tools).await?;
+        // The LLM may call tools it wasn't given, e.g. an agent changing the character sheets
+        let (calls, made_up): (Vec<LLMToolCall>, Vec<LLMToolCall>) = response.tool_calls.into_iter()
+            .partition(|call| tools.iter().any(|tool| tool.name == call.name));
+        for call in made_up {
+            self.room.send_error(Arc::new(ErrorMessage {
+                msg: format!("@{} called `{}`, which it can't use", actor, call.name),
+            }))?;
+        }
+        let (rpg_calls, world_calls): (Vec<LLMToolCall>, Vec<LLMToolCall>) = calls.into_iter()
+            .partition(rpg_engine::handles);
+        let mut speech = vec![response.content];
+        speech.extend(world_engine::perform(&self.room, actor, &world_calls)?);
+        let rolls = rpg_engine::perform(&self.room, actor, &rpg_calls)?;
+        let speech = speech.iter().map(|s| s.trim()).filter(|s| !s.is_empty()).collect::<Vec<_>>().join("\n");
+        Ok((speech, rolls))
+    }
+
+    /// Sends what the speaker says. When it rolled dice, the reply is written again knowing the results,
+    /// so the LLM never makes them up. Returns the content.
+    async fn send_reply(&self, llm: &dyn LLM, msg: ChatMessage, (speech, rolls): (String, Vec<DiceRoll>), system_prompt: &str,
+                        conversation: &[LLMConversation], max_words: Option<usize>) -> Result<String, Box<dyn Error>> {
+        if !rolls.is_empty() {
+            let system_prompt = format!("{}\n{}", system_prompt, rpg_engine::results_prompt(&rolls));
+            return self.stream_reply(llm, msg, &system_prompt, conversation, max_words).await;
+        }
+        let content = speech.replace(&format!("{}(@{}): ", msg.from_username, msg.from_user_id), "");
+        let content = max_words.and_then(|max| cut_words(&content, max)).unwrap_or(content);
+        if !content.is_empty() {
+            msg.content_stream.send_replace((Arc::new(RwLock::new(vec![content.clone()])), true));
+            self.room.send_chat(Arc::new(msg))?;
+        }
+        Ok(content)
+    }
+
+    /// Whispers are answered in the same circle, everything else is heard by the participants around.
+    /// During a game, the game decides who hears the profile, and fails if it can't talk.
+    fn reply_visibility(&self, id: &str) -> Result<Visibility, String> {
+        if self.room.game().is_some() {
+            return self.room.speech_visibility(id);
+        }
+        Ok(match self.recent_chats.last() {
+            Some(last) if last.visible_to(id) && matches!(last.visibility, Visibility::Whisper(_)) => last.reply_visibility(id),
+            _ => self.room.local_visibility(id),
+        })
+    }
+
+    /// Lets the narrator describe the scene, and keeps what it said as the state of the scene for the agents.
+    async fn narrate(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(narrator) = self.narrator.clone() else { return Ok(()) };
+        self.since_narration = 0;
+        // The narration is public, so the narrator doesn't see the whispers either
+        let conversation = self.conversation_seen_by(NARRATOR_ID).await;
+        let lore = lorebook::activate(self.lorebook.entries.iter(), &self.scanned_text(&conversation), self.limits.lore_token_budget);
+        let lore_summary = if lore.is_empty() {
+            "Nothing in particular.".to_string()
+        } else {
+            lore.iter().map(|e| format!("* {}: {}", e.name, e.content)).collect::<Vec<_>>().join("\n")
+        };
+        let time_summary = match self.room.world_time() {
+            Some(time) => format!("It is {}.", time.format(TIME_FORMAT)),
+            None => "Unknown.".to_string(),
+        };
+        let system_prompt = format!("You are the narrator of a group chat role-play, not one of its characters. {}\n\
+            Here is the topic of the room, the story happens in it: \n\
+            {}\n\
+            Here are the characters: \n\
+            {}\n\
+            Here is the human in this room: \n\
+            {}\n\
+            Here is what is known about the world that is relevant to the scene: \n\
+            {}\n\
+            Here is the current time: \n\
+            {}\n\
+            Only write the narration, in the third person, in a short paragraph. Never speak for the characters.",
+            narrator.prompt.as_deref().unwrap_or(DEFAULT_NARRATOR_PROMPT), Self::summarize_topic(self.room.topic()),
+            self.profiles_summarize, Self::summarize_human(self.room.user().as_ref()), lore_summary, time_summary);
+        let msg = Self::new_reply(NARRATOR_ID, &narrator.name, lore.iter().map(|e| e.name.clone()).collect(), Visibility::Public);
+        let llm = self.llms.narrator();
+        let narration = if self.room.plays_rpg() {
+            let system_prompt = format!("{}\n\
+                Here are the character sheets: \n\
+                {}\n\
+                Use the tools to roll dice for what the characters attempt, and to hurt or heal them and give or take their items. \
+                Never make up a result.", system_prompt, Self::summarize_sheets(&self.room));
+            let acted = self.act(llm.as_ref(), NARRATOR_ID, &system_prompt, &conversation, &rpg_engine::tools(true)).await?;
+            self.send_reply(llm.as_ref(), msg, acted, &system_prompt, &conversation, None).await?
+        } else {
+            self.stream_reply(llm.as_ref(), msg, &system_prompt, &conversation, None).await?
+        };
+        self.scene.push(narration);
+        if self.scene.len() > SCENE_SIZE {
+            self.scene.remove(0);
+        }
+        Ok(())
+    }
+
+    /// The recent messages the participant could have seen, labelled with their sender.
+    async fn conversation_seen_by(&self, id: &str) -> Vec<LLMConversation> {
         let mut conversation = Vec::new();
-        for m in self.recent_chats.iter() {
+        for m in self.recent_chats.iter().filter(|m| m.visible_to(id)) {
+            let label = m.visibility_label().map(|l| format!(" ({})", l)).unwrap_or_default();
             conversation.push(LLMConversation{
                 role: m.role.clone(),
-                content: Arc::new(format!("{}(@{}): {}", m.from_username, m.from_user_id, m.read_content().await)),
+                content: Arc::new(format!("{}(@{}){}: {}", m.from_username, m.from_user_id, label, m.read_content().await)),
             });
         }
-        let content_vec = Arc::new(RwLock::new(vec![]));
-        let (sender, _rx) = watch::channel((content_vec.clone(), false));
-        let sender_ref = Arc::new(sender);
-        let msg = ChatMessage{
-            from_user_id: profile.id.clone(),
-            from_username: profile.name.clone(),
+        conversation
+    }
+
+    /// Text searched for lorebook keywords: the last messages and the narrated scene.
+    fn scanned_text(&self, conversation: &[LLMConversation]) -> String {
+        lorebook::scanned_text(conversation.iter().map(|c| c.content.as_str()),
+            self.scene.iter().map(|s| s.as_str()), self.limits.lore_scan_depth)
+    }
+
+    fn new_reply(from_id: &str, from_name: &str, activated_lore: Vec<String>, visibility: Visibility) -> ChatMessage {
+        let (sender, _) = watch::channel((Arc::new(RwLock::new(vec![])), false));
+        ChatMessage{
+            from_user_id: from_id.to_string(),
+            from_username: from_name.to_string(),
             role: ROLE_ASSISTANT.to_string(),
-            content_stream: sender_ref.clone(),
-        };
+            content_stream: Arc::new(sender),
+            activated_lore,
+            visibility,
+        }
+    }
+
+    /// Streams the completion of the LLM into the room as the content of the message, up to `max_words` words.
+    /// Returns the full content.
+    async fn stream_reply(&self, llm: &dyn LLM, msg: ChatMessage, system_prompt: &str,
+                          conversation: &[LLMConversation], max_words: Option<usize>) -> Result<String, Box<dyn Error>> {
+        let sender_ref = msg.content_stream.clone();
+        // Keeps the channel open until the content is complete
+        let _rx = sender_ref.subscribe();
+        let content_vec = sender_ref.borrow().0.clone();
+        let name_prefix = format!("{}(@{}): ", msg.from_username, msg.from_user_id);
         self.room.send_chat(Arc::new(msg))?;
-        let mut stream = self.llm.complete(&system_prompt, &conversation);
+        let mut stream = llm.complete(system_prompt, conversation);
         while let Some(response) = stream.next().await {
-            let parsed_res = response.map_err(|e| e as Box<dyn Error>)?.replace(&format!("{}(@{}): ", profile.name, profile.id), "");
+            let parsed_res = response.map_err(|e| e as Box<dyn Error>)?.replace(&name_prefix, "");
             content_vec.write().await.push(parsed_res);
+            let cut = match max_words {
+                Some(max) => cut_words(&content_vec.read().await.join(""), max),
+                None => None,
+            };
+            if let Some(cut) = cut {
+                *content_vec.write().await = vec![cut];
+                break;
+            }
             sender_ref.send((content_vec.clone(), false))?;
         };
-        sender_ref.send((content_vec, true))?;
-        Ok(())
+        sender_ref.send((content_vec.clone(), true))?;
+        let content = content_vec.read().await.join("");
+        Ok(content)
+    }
+}
+
+/// The text cut off after `max_words` words, or `None` if it's short enough.
+fn cut_words(text: &str, max_words: usize) -> Option<String> {
+    let mut count = 0;
+    let mut in_word = false;
+    for (i, c) in text.char_indices() {
+        if c.is_whitespace() {
+            in_word = false;
+        } else if !in_word {
+            in_word = true;
+            count += 1;
+            if count > max_words {
+                return Some(format!("{}…", text[..i].trim_end()));
+            }
+        }
     }
+    None
 }
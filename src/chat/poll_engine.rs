@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::sync::Arc;
+use futures::future::join_all;
+use log::info;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use crate::chat::message::{ChatMessage, ErrorMessage, Message, NoticeMessage};
+use crate::chat::room::Room;
+use crate::llm::router::LLMRouter;
+use crate::llm::{LLMConversation, LLMTool, ROLE_USER};
+use crate::model::poll::{Ballot, Poll, PollResult};
+use crate::model::profile::Profile;
+
+/// Number of recent messages given to the voters as context of the poll.
+const CONTEXT_SIZE: usize = 20;
+
+const VOTE: &str = "vote";
+
+/// The tool the agents vote with, which only takes the options of the poll as a choice.
+fn vote_tool(poll: &Poll) -> LLMTool {
+    LLMTool {
+        name: VOTE.to_string(),
+        description: "Cast the vote of the profile in the poll.".to_string(),
+        parameters: json!({
+            "type": "object",
+            "properties": {
+                "choice": { "type": "string", "enum": poll.options, "description": "The option the profile votes for" },
+                "reason": { "type": "string", "description": "Why, in one short sentence in the voice of the profile" },
+            },
+            "required": ["choice", "reason"],
+        }),
+    }
+}
+
+/// Runs the polls of the human: every agent votes on its own, all at once, and the ballots
+/// are sent back to the room as a single result.
+pub fn start(room: Arc<Room>, llms: Arc<LLMRouter>) {
+    let mut receiver = room.subscribe();
+    tokio::spawn(async move {
+        let mut history: Vec<Arc<ChatMessage>> = Vec::new();
+        loop {
+            let chat = match receiver.recv().await {
+                Ok(Message::Chat(chat)) => chat,
+                Ok(Message::Event(event)) | Ok(Message::WorldChanged(event)) => Arc::new(event.to_chat()),
+                Ok(Message::Poll(poll)) => {
+                    let (room, llms, history) = (room.clone(), llms.clone(), history.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = run(&room, &llms, poll, history).await {
+                            let msg = format!("The poll failed: {}", e);
+                            // Nobody is listening anymore when the chat was closed during the poll
+                            if room.send_error(Arc::new(ErrorMessage { msg: msg.clone() })).is_err() {
+                                info!("{}", msg);
+                            }
+                        }
+                    });
+                    continue;
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            history.push(chat);
+            if history.len() > CONTEXT_SIZE {
+                history.remove(0);
+            }
+        }
+    });
+}
+
+async fn run(room: &Room, llms: &LLMRouter, poll: Arc<Poll>, history: Vec<Arc<ChatMessage>>) -> Result<(), Box<dyn Error>> {
+    // Agents who are away or asleep don't vote
+    let voters: Vec<Arc<Profile>> = room.profiles().into_iter().filter(|p| room.is_available(p)).collect();
+    if voters.is_empty() {
+        return Err("no agent is available to vote".into());
+    }
+    let notice = format!("Poll sent to {} agent(s): {}", voters.len(), poll.question);
+    room.send_notice(Arc::new(NoticeMessage { msg: notice }))?;
+    let ballots = join_all(voters.iter().map(|p| ask_ballot(room, llms, &poll, p, &history))).await;
+    let result = PollResult {
+        poll: (*poll).clone(),
+        ballots: voters.iter().map(|p| p.id.clone()).zip(ballots).collect(),
+    };
+    room.send_poll_result(Arc::new(result))
+}
+
+/// Asks the agent for its vote, independently of the others. Returns `None` if it gave no valid ballot.
+async fn ask_ballot(room: &Room, llms: &LLMRouter, poll: &Poll, profile: &Profile, history: &[Arc<ChatMessage>]) -> Option<Ballot> {
+    let mut conversation = Vec::new();
+    for m in history.iter().filter(|m| m.visible_to(&profile.id)) {
+        conversation.push(format!("{}(@{}): {}", m.from_username, m.from_user_id, m.current_content().await));
+    }
+    let conversation = if conversation.is_empty() { "nothing yet".to_string() } else { conversation.join("\n") };
+    let topic = room.topic().map(|t| format!("The topic of the chat is: {}\n", t)).unwrap_or_default();
+    let options = poll.options.iter().map(|o| format!("- {}", o)).collect::<Vec<_>>().join("\n");
+    let system_prompt = format!("You play {}(@{}) in a group chat. Here is the background of the profile:\n\
+        {}\n\
+        {topic}\
+        Here is the recent conversation the profile saw:\n\
+        {conversation}\n\
+        \n\
+        The profile is asked in a poll: {}\n\
+        The options are:\n\
+        {options}\n\
+        Vote as the profile would, on its own tastes and opinions, with the `{VOTE}` tool.",
+        profile.name, profile.id, profile.background, poll.question);
+    let conversation = [LLMConversation { role: ROLE_USER.to_string(), content: Arc::new(poll.question.clone()) }];
+    let response = match llms.agent(&profile.id).complete_with_tools(&system_prompt, &conversation, &[vote_tool(poll)]).await {
+        Ok(response) => response,
+        Err(e) => {
+            info!("@{} failed to vote in the poll: {}", profile.id, e);
+            return None;
+        }
+    };
+    let Some(call) = response.tool_calls.iter().find(|c| c.name == VOTE) else {
+        info!("@{} didn't vote in the poll: {}", profile.id, response.content);
+        return None;
+    };
+    let mut ballot: Ballot = serde_json::from_str(&call.arguments)
+        .inspect_err(|e| info!("@{} cast an invalid ballot {}: {}", profile.id, call.arguments, e))
+        .ok()?;
+    let Some(option) = poll.option_of(&ballot) else {
+        info!("@{} voted for {}, which isn't an option of the poll", profile.id, ballot.choice);
+        return None;
+    };
+    ballot.choice = option.clone();
+    Some(ballot)
+}
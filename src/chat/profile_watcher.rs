@@ -0,0 +1,37 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{error, info};
+use crate::chat::room::{ProfileChange, Room};
+use crate::dao::profile_dao::ProfileDao;
+
+/// Editors write a file in several steps, wait for them to settle before reloading.
+const RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+/// Reloads the profiles of the room when their documents change in the store.
+///
+/// Any changed document triggers a reload of all the profiles in the room, the human's included, since a profile
+/// may inherit from the changed one or include it as a fragment.
+/// Does nothing if the store can't be watched.
+pub fn start(room: Arc<Room>, dao: Arc<dyn ProfileDao>) -> Result<(), Box<dyn Error>> {
+    let Some(mut watch) = dao.watch()? else { return Ok(()) };
+    tokio::spawn(async move {
+        let _watcher = watch.watcher;
+        while let Some(name) = watch.changes.recv().await {
+            tokio::time::sleep(RELOAD_DELAY).await;
+            while watch.changes.try_recv().is_ok() {}
+            info!("Profile document {} changed, reloading profiles", name);
+            for profile in room.profiles().into_iter().chain(room.user()) {
+                let change = match dao.get(&profile.id).await {
+                    Ok(Some(p)) => ProfileChange::Updated(Box::new(p)),
+                    Ok(None) => continue,
+                    Err(e) => ProfileChange::Invalid { id: profile.id.clone(), error: e.to_string() },
+                };
+                if let Err(e) = room.apply_profile_change(change) {
+                    error!("Failed to apply profile change: {}", e);
+                }
+            }
+        }
+    });
+    Ok(())
+}
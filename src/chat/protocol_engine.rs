@@ -0,0 +1,333 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use log::info;
+use regex::Regex;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+use tokio::time::{timeout_at, Instant};
+use crate::chat::message::{ErrorMessage, EventMessage, Message, SimulationCommand};
+use crate::chat::room::Room;
+use crate::model::room_config::{ProtocolConfig, ProtocolFormat};
+use crate::model::session::Visibility;
+
+/// Gives the chat time to open before the first announcement.
+const START_DELAY: Duration = Duration::from_secs(2);
+
+/// Gives the floor to the speakers in the order of the protocol, whatever the planner would pick.
+struct ProtocolEngine {
+    room: Arc<Room>,
+    config: ProtocolConfig,
+    /// Id of the human, who speaks when the protocol gives them the floor
+    human: String,
+    receiver: Receiver<Message>,
+}
+
+/// Checks the speakers of the protocol and starts it.
+pub fn start(room: Arc<Room>, config: ProtocolConfig, human: String) -> Result<(), Box<dyn Error>> {
+    let agents: Vec<String> = room.profiles().iter().map(|p| p.id.clone()).collect();
+    let named: Vec<&String> = match &config.format {
+        ProtocolFormat::Debate { speakers, .. } => speakers.iter().collect(),
+        ProtocolFormat::Interview { host, guest, .. } => host.iter().chain(std::iter::once(guest)).collect(),
+        ProtocolFormat::Panel { moderator, panelists, .. } => std::iter::once(moderator).chain(panelists.iter()).collect(),
+    };
+    if let Some(unknown) = named.iter().find(|id| !agents.contains(id) && **id != &human) {
+        return Err(format!("The turn protocol names @{}, who isn't in the room", unknown).into());
+    }
+    let engine = ProtocolEngine { receiver: room.subscribe(), room: room.clone(), config, human };
+    tokio::spawn(async move {
+        if let Err(e) = engine.run().await {
+            room.send_error(Arc::new(ErrorMessage { msg: format!("The turn protocol stopped: {}", e) }))
+                .expect("cannot send error msg");
+        }
+    });
+    Ok(())
+}
+
+impl ProtocolEngine {
+    async fn run(mut self) -> Result<(), Box<dyn Error>> {
+        tokio::time::sleep(START_DELAY).await;
+        let agents: Vec<String> = self.room.profiles().iter().map(|p| p.id.clone()).collect();
+        match self.config.format.clone() {
+            ProtocolFormat::Debate { speakers, rebuttal_rounds } => {
+                let speakers = if speakers.is_empty() { agents } else { speakers };
+                self.debate(&speakers, rebuttal_rounds).await
+            }
+            ProtocolFormat::Interview { host, guest, questions } => {
+                let host = host.unwrap_or_else(|| self.human.clone());
+                self.interview(&host, &guest, questions).await
+            }
+            ProtocolFormat::Panel { moderator, panelists, questions } => {
+                let panelists = if panelists.is_empty() {
+                    agents.into_iter().filter(|id| *id != moderator).collect()
+                } else {
+                    panelists
+                };
+                self.panel(&moderator, &panelists, questions).await
+            }
+        }
+    }
+
+    async fn debate(&mut self, speakers: &[String], rebuttal_rounds: usize) -> Result<(), Box<dyn Error>> {
+        let words = self.config.max_words;
+        self.announce(format!("The debate begins between {}. Opening statements.", mentions(speakers)))?;
+        for id in speakers {
+            self.turn(id, &format!("It's your opening statement in the debate on the topic of the room. \
+                State your position and your main arguments in at most {} words.", words)).await?;
+        }
+        for round in 1..=rebuttal_rounds {
+            self.announce(format!("Rebuttals, round {}.", round))?;
+            for id in speakers {
+                self.turn(id, &format!("It's your rebuttal in the debate. Answer the arguments of the other debaters \
+                    without repeating your opening, in at most {} words.", words)).await?;
+            }
+        }
+        self.announce("Closing statements.".to_string())?;
+        for id in speakers.iter().rev() {
+            self.turn(id, &format!("It's your closing statement in the debate. Sum up your position without new arguments, \
+                in at most {} words.", words)).await?;
+        }
+        self.announce("The debate is over.".to_string())
+    }
+
+    async fn interview(&mut self, host: &str, guest: &str, questions: usize) -> Result<(), Box<dyn Error>> {
+        let words = self.config.max_words;
+        self.announce(format!("@{} interviews @{}.", host, guest))?;
+        let mut asked = 0;
+        // The human asks until they end the interview
+        while host == self.human || asked < questions {
+            let question = self.turn(host, &format!("You host an interview of @{}. Ask them your next question, \
+                building on their previous answers, in at most {} words.", guest, words)).await?;
+            if question.is_none() && host == self.human {
+                break;
+            }
+            // A missed turn uses up a question too, so a host who never asks can't stall the interview
+            asked += 1;
+            if question.is_none() {
+                continue;
+            }
+            self.turn(guest, &format!("You are interviewed by @{}. Answer their last question in at most {} words.", host, words)).await?;
+        }
+        // The human already closed it with /end
+        if host != self.human {
+            self.turn(host, &format!("Thank @{} and close the interview in at most {} words.", guest, words)).await?;
+        }
+        self.announce("The interview is over.".to_string())
+    }
+
+    async fn panel(&mut self, moderator: &str, panelists: &[String], questions: usize) -> Result<(), Box<dyn Error>> {
+        let words = self.config.max_words;
+        self.announce(format!("@{} moderates a panel with {}.", moderator, mentions(panelists)))?;
+        let mut asked = 0;
+        // The human moderates until they end the panel
+        while moderator == self.human || asked < questions {
+            let question = self.turn(moderator, &format!("You moderate the panel. Ask your next question and \
+                address it to one or more panelists by their @id, among {}, in at most {} words.", mentions(panelists), words)).await?;
+            if question.is_none() && moderator == self.human {
+                break;
+            }
+            // A missed turn uses up a question too, so a moderator who never asks can't stall the panel
+            asked += 1;
+            let Some(question) = question else { continue };
+            // Only the panelists the question is addressed to answer, everyone if it names nobody
+            let mut addressed = addressed(&question, panelists);
+            if addressed.is_empty() {
+                addressed = panelists.iter().collect();
+            }
+            for id in addressed {
+                self.turn(id, &format!("You are a panelist. Answer the last question of the moderator @{} \
+                    in at most {} words.", moderator, words)).await?;
+            }
+        }
+        if moderator != self.human {
+            self.turn(moderator, &format!("Thank the panelists and close the panel in at most {} words.", words)).await?;
+        }
+        self.announce("The panel is over.".to_string())
+    }
+
+    /// Gives the floor to the speaker and waits until it's done. Returns what it said,
+    /// or `None` if it ran out of time. The human has no time limit, but can pass with /end.
+    async fn turn(&mut self, id: &str, instruction: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.drain();
+        let deadline = if id == self.human {
+            self.room.send_event(Arc::new(EventMessage {
+                text: format!("Your turn: {} Send /end to pass, or to close the session if you lead it.", instruction),
+                visibility: Visibility::Whisper(vec![id.to_string()]),
+            }))?;
+            None
+        } else {
+            self.room.request_turn(id, instruction, Some(self.config.max_words))?;
+            Some(Instant::now() + Duration::from_secs_f64(self.config.turn_secs))
+        };
+        loop {
+            let received = match deadline {
+                Some(deadline) => match timeout_at(deadline, self.receiver.recv()).await {
+                    Ok(received) => received,
+                    Err(_) => {
+                        info!("@{} didn't take its turn in time", id);
+                        self.announce(format!("@{} ran out of time.", id))?;
+                        return Ok(None);
+                    }
+                },
+                None => self.receiver.recv().await,
+            };
+            match received {
+                Ok(Message::Chat(chat)) if chat.from_user_id == id => return Ok(Some(chat.read_content().await)),
+                Ok(Message::Simulation(SimulationCommand::End)) if id == self.human => {
+                    self.announce(format!("@{} passes.", id))?;
+                    return Ok(None);
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return Err("the room was closed".into()),
+            }
+        }
+    }
+
+    /// Skips the messages sent before the turn.
+    fn drain(&mut self) {
+        while !matches!(self.receiver.try_recv(), Err(TryRecvError::Empty) | Err(TryRecvError::Closed)) {}
+    }
+
+    fn announce(&self, text: String) -> Result<(), Box<dyn Error>> {
+        self.room.send_event(Arc::new(EventMessage { text, visibility: Visibility::Public }))
+    }
+}
+
+/// The participants mentioned by their whole @id in the text, in the order they are first mentioned.
+fn addressed<'a>(text: &str, ids: &'a [String]) -> Vec<&'a String> {
+    let mention_regex = Regex::new(r"@([\w-]+)").expect("invalid mention regex");
+    let mut addressed = Vec::new();
+    for mention in mention_regex.captures_iter(text) {
+        if let Some(id) = ids.iter().find(|id| **id == mention[1]) && !addressed.contains(&id) {
+            addressed.push(id);
+        }
+    }
+    addressed
+}
+
+fn mentions(ids: &[String]) -> String {
+    ids.iter().map(|id| format!("@{}", id)).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::sync::{watch, RwLock};
+    use crate::chat::message::ChatMessage;
+    use crate::llm::{ROLE_ASSISTANT, ROLE_USER};
+    use crate::model::room_config::ProtocolFormat;
+
+    const HUMAN: &str = "tuser";
+
+    fn chat(from: &str, role: &str, content: &str) -> Arc<ChatMessage> {
+        let (sender, _) = watch::channel((Arc::new(RwLock::new(vec![content.to_string()])), true));
+        Arc::new(ChatMessage {
+            from_user_id: from.to_string(),
+            from_username: from.to_string(),
+            role: role.to_string(),
+            content_stream: Arc::new(sender),
+            activated_lore: Vec::new(),
+            visibility: Visibility::Public,
+        })
+    }
+
+    fn engine(room: &Arc<Room>, format: ProtocolFormat, turn_secs: f64) -> ProtocolEngine {
+        ProtocolEngine {
+            room: room.clone(),
+            config: ProtocolConfig { format, max_words: 50, turn_secs },
+            human: HUMAN.to_string(),
+            receiver: room.subscribe(),
+        }
+    }
+
+    /// Plays the participants: the agents in `silent` never take their turn, the others answer at once,
+    /// and the human asks `questions` questions before sending /end. Returns what was announced and said.
+    fn play(room: &Arc<Room>, silent: &'static [&'static str], questions: usize) -> Arc<Mutex<Vec<String>>> {
+        let transcript = Arc::new(Mutex::new(Vec::new()));
+        let mut receiver = room.subscribe();
+        let (room, log) = (room.clone(), transcript.clone());
+        tokio::spawn(async move {
+            let mut asked = 0;
+            while let Ok(msg) = receiver.recv().await {
+                match msg {
+                    Message::Turn(turn) if !silent.contains(&turn.profile_id.as_str()) => {
+                        room.send_chat(chat(&turn.profile_id, ROLE_ASSISTANT, "An answer")).unwrap();
+                    }
+                    Message::Event(event) if event.text.starts_with("Your turn") => {
+                        if asked < questions {
+                            asked += 1;
+                            room.send_chat(chat(HUMAN, ROLE_USER, &format!("Question {}?", asked))).unwrap();
+                        } else {
+                            room.control_simulation(SimulationCommand::End).unwrap();
+                        }
+                    }
+                    Message::Event(event) => log.lock().unwrap().push(event.text.clone()),
+                    Message::Chat(chat) => {
+                        let line = format!("@{}: {}", chat.from_user_id, chat.read_content().await);
+                        log.lock().unwrap().push(line);
+                    }
+                    _ => {}
+                }
+            }
+        });
+        transcript
+    }
+
+    #[test]
+    fn addressed_matches_whole_ids_in_order() {
+        let ids = vec!["al".to_string(), "alice".to_string(), "bob".to_string()];
+        assert_eq!(addressed("@alice, what do you think?", &ids), ["alice"]);
+        assert_eq!(addressed("@bob and @al. Then @alice and @bob again", &ids), ["bob", "al", "alice"]);
+        assert_eq!(addressed("@alicia and @al-x, anyone?", &ids), Vec::<&String>::new());
+        assert!(addressed("What about you all?", &ids).is_empty());
+    }
+
+    #[tokio::test]
+    async fn human_host_ends_the_interview() {
+        let room = Arc::new(Room::new(64, Vec::new(), None, None, None, None, None));
+        let mut engine = engine(&room, ProtocolFormat::Interview { host: None, guest: "alice".to_string(), questions: 3 }, 5.0);
+        let transcript = play(&room, &[], 2);
+        tokio::time::timeout(Duration::from_secs(5), engine.interview(HUMAN, "alice", 3)).await
+            .expect("the interview never ended").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let transcript = transcript.lock().unwrap().clone();
+        assert_eq!(transcript, [
+            "@tuser interviews @alice.",
+            "@tuser: Question 1?",
+            "@alice: An answer",
+            "@tuser: Question 2?",
+            "@alice: An answer",
+            "@tuser passes.",
+            "The interview is over.",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn human_moderator_ends_the_panel() {
+        let room = Arc::new(Room::new(64, Vec::new(), None, None, None, None, None));
+        let panelists = vec!["alice".to_string(), "bob".to_string()];
+        let mut engine = engine(&room, ProtocolFormat::Panel { moderator: HUMAN.to_string(), panelists: panelists.clone(), questions: 3 }, 5.0);
+        let transcript = play(&room, &[], 1);
+        tokio::time::timeout(Duration::from_secs(5), engine.panel(HUMAN, &panelists, 3)).await
+            .expect("the panel never ended").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let transcript = transcript.lock().unwrap().clone();
+        assert_eq!(transcript.last().map(String::as_str), Some("The panel is over."));
+        assert_eq!(transcript.iter().filter(|l| *l == "@alice: An answer" || *l == "@bob: An answer").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn silent_agent_host_uses_up_its_questions() {
+        let room = Arc::new(Room::new(64, Vec::new(), None, None, None, None, None));
+        let mut engine = engine(&room, ProtocolFormat::Interview { host: Some("bob".to_string()), guest: "alice".to_string(), questions: 2 }, 0.05);
+        let transcript = play(&room, &["bob"], 0);
+        tokio::time::timeout(Duration::from_secs(5), engine.interview("bob", "alice", 2)).await
+            .expect("the interview never ended").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let transcript = transcript.lock().unwrap().clone();
+        assert_eq!(transcript.iter().filter(|l| *l == "@bob ran out of time.").count(), 3);
+        assert!(!transcript.iter().any(|l| l.starts_with("@alice")));
+        assert_eq!(transcript.last().map(String::as_str), Some("The interview is over."));
+    }
+}
@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::sync::Arc;
+use async_trait::async_trait;
+use log::info;
+use serde::Deserialize;
+use crate::chat::evaluator::{self, Evaluator};
+use crate::chat::message::ChatMessage;
+use crate::chat::room::Room;
+use crate::dao::profile_state_dao::ProfileStateDao;
+use crate::llm::LLM;
+
+/// Max change of affinity or trust from a single message, to keep relationships from swinging wildly.
+const MAX_CHANGE: f32 = 0.2;
+
+#[derive(Deserialize)]
+struct RelationshipChange {
+    from: String,
+    to: String,
+    #[serde(default)]
+    affinity: f32,
+    #[serde(default)]
+    trust: f32,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+struct RelationshipEvaluator;
+
+/// Updates the relationships of the agents after each message in the room.
+pub fn start(room: Arc<Room>, llm: Arc<dyn LLM>) {
+    evaluator::start(room, llm, RelationshipEvaluator);
+}
+
+#[async_trait]
+impl Evaluator for RelationshipEvaluator {
+    const NAME: &'static str = "relationships";
+    type Change = RelationshipChange;
+
+    fn prompt(&self, room: &Room, witnesses: &[String], recent: &str, line: &str) -> String {
+        let mut current = Vec::new();
+        for id in witnesses.iter() {
+            for (to, r) in room.relationships(id) {
+                current.push(format!("@{} -> @{}: affinity {:.1}, trust {:.1}, {}", id, to, r.affinity, r.trust, r.description));
+            }
+        }
+        let current = if current.is_empty() { "none yet".to_string() } else { current.join("\n") };
+        let witnesses_str = witnesses.iter().map(|id| format!("@{}", id)).collect::<Vec<_>>().join(", ");
+        format!("You track how the participants of a group chat feel about each other.\n\
+            Here is the recent conversation:\n\
+            {recent}\n\
+            Here is the new message:\n\
+            {line}\n\
+            Here are the current relationships, with affinity and trust from -1 to 1:\n\
+            {current}\n\
+            \n\
+            How does the new message change how {witnesses_str} feel about the other participants? \
+            Output a JSON array of objects with the fields `from` and `to` (participant ids without @), \
+            `affinity` and `trust` (changes between -{MAX_CHANGE} and {MAX_CHANGE}) and optionally `description` \
+            (a new short description of the relationship). Only include relationships that change, \
+            and output an empty array if nothing changes. Output nothing else.")
+    }
+
+    async fn apply(&self, room: &Room, chat: &ChatMessage, witnesses: &[String], changes: Vec<RelationshipChange>) -> Result<(), Box<dyn Error>> {
+        for change in changes {
+            let from = change.from.trim_start_matches('@');
+            let to = change.to.trim_start_matches('@');
+            let known_target = room.profiles().iter().any(|p| p.id == to)
+                || room.user().is_some_and(|u| u.id == to)
+                || chat.from_user_id == to;
+            if !witnesses.iter().any(|w| w == from) || !known_target || from == to {
+                info!("Ignoring relationship change from @{} to @{}", from, to);
+                continue;
+            }
+            room.adjust_relationship(from, to,
+                change.affinity.clamp(-MAX_CHANGE, MAX_CHANGE), change.trust.clamp(-MAX_CHANGE, MAX_CHANGE), change.description);
+        }
+        Ok(())
+    }
+}
+
+/// Saves the relationships that changed during the chat, so they carry over to the next chats.
+/// Saved once at the end like the moods, to not race with the other evaluators saving the same states.
+pub async fn save(room: &Room, dao: &dyn ProfileStateDao) -> Result<(), Box<dyn Error>> {
+    for profile in room.profiles() {
+        let changed = room.changed_relationships(&profile.id);
+        if changed.is_empty() {
+            continue;
+        }
+        let mut state = dao.get(&profile.id).await?;
+        state.relationships.extend(changed);
+        dao.save(&profile.id, &state).await?;
+    }
+    Ok(())
+}
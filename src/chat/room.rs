@@ -1,19 +1,388 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::Sender;
-use crate::chat::message::{ChatMessage, ErrorMessage, Message};
+use crate::chat::message::{ChatMessage, ErrorMessage, EventMessage, Message, NoticeMessage, SimulationCommand, TurnRequest};
+use crate::chat::world_clock::WorldClock;
 use crate::model::profile::Profile;
+use crate::model::schedule::{self, ScheduleEntry};
+use chrono::{DateTime, Local, NaiveDateTime, TimeDelta};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use crate::model::character_sheet::{CharacterSheet, SheetChange};
+use crate::model::dice::{DiceExpression, DiceRoll};
+use crate::model::mood::Mood;
+use crate::model::profile_state::ProfileState;
+use crate::model::relationship::Relationship;
+use crate::model::game::Game;
+use crate::model::poll::{Poll, PollResult};
+use crate::model::world::{World, WorldAction};
+use crate::model::session::Visibility;
+
+/// A new version of a profile in the room.
+pub enum ProfileChange {
+    Updated(Box<Profile>),
+    /// The profile was changed but can't be loaded anymore.
+    Invalid { id: String, error: String },
+}
+
+/// How the simulated time is shown in the chat and the prompts, e.g. `Friday 2026-05-01 08:30`.
+pub const TIME_FORMAT: &str = "%A %Y-%m-%d %H:%M";
 
 pub struct Room {
-    pub profiles: Vec<Arc<Profile>>,
+    profiles: RwLock<Vec<Arc<Profile>>>,
+    /// Profile of the human participant, if they chat as one.
+    user: RwLock<Option<Arc<Profile>>>,
+    /// What the room is about: the scenario, setting or topic of the conversation.
+    topic: RwLock<Option<String>>,
+    /// Current relationships of each agent with the other participants, by agent id then participant id.
+    relationships: RwLock<HashMap<String, BTreeMap<String, Relationship>>>,
+    /// The relationships that changed during the chat, as (agent id, participant id), to save them at the end.
+    changed_relationships: RwLock<BTreeSet<(String, String)>>,
+    /// Moods of the agents that changed from their usual one, with when they last changed, by agent id.
+    moods: RwLock<HashMap<String, (Mood, DateTime<Local>)>>,
+    clock: Option<RwLock<WorldClock>>,
+    /// Where the participants and the objects are, if the room has a world.
+    world: Option<RwLock<World>>,
+    /// The game played in the room, once it started.
+    game: RwLock<Option<Game>>,
+    /// Dice of the tabletop role-play, if the room plays one.
+    dice: Option<Mutex<StdRng>>,
+    /// Seed the dice were made from, to replay the same rolls.
+    dice_seed: Option<u64>,
+    /// Current character sheets by participant id.
+    sheets: RwLock<BTreeMap<String, CharacterSheet>>,
     sender: Sender<Message>,
 }
 
 impl Room {
-    pub fn new(channel_size: usize, profiles: Vec<Arc<Profile>>) -> Self {
+    pub fn new(channel_size: usize, profiles: Vec<Arc<Profile>>, user: Option<Arc<Profile>>, topic: Option<String>,
+               clock: Option<WorldClock>, world: Option<World>, dice_seed: Option<u64>) -> Self {
         let (tx, _) = broadcast::channel(channel_size);
-        Room { sender: tx , profiles }
+        let relationships = profiles.iter().map(|p| (p.id.clone(), p.relationships.clone())).collect();
+        let sheets = profiles.iter().chain(user.iter())
+            .filter_map(|p| p.sheet.clone().map(|s| (p.id.clone(), s)))
+            .collect();
+        Room {
+            sender: tx,
+            profiles: RwLock::new(profiles),
+            user: RwLock::new(user),
+            topic: RwLock::new(topic),
+            relationships: RwLock::new(relationships),
+            changed_relationships: RwLock::new(BTreeSet::new()),
+            moods: RwLock::new(HashMap::new()),
+            clock: clock.map(RwLock::new),
+            world: world.map(RwLock::new),
+            game: RwLock::new(None),
+            dice: dice_seed.map(|seed| Mutex::new(StdRng::seed_from_u64(seed))),
+            dice_seed,
+            sheets: RwLock::new(sheets),
+        }
+    }
+
+    /// The simulated time, if the room has a clock.
+    pub fn world_time(&self) -> Option<NaiveDateTime> {
+        self.clock.as_ref().map(|c| c.read().expect("clock lock poisoned").now())
+    }
+
+    /// What the profile is doing at the simulated time, if the room has a clock and the profile a schedule for it.
+    pub fn activity(&self, profile: &Profile) -> Option<ScheduleEntry> {
+        let time = self.world_time()?;
+        schedule::activity_at(&profile.schedule, time.time()).cloned()
+    }
+
+    /// Whether the agent can take part in the conversation at the simulated time.
+    pub fn is_available(&self, profile: &Profile) -> bool {
+        self.activity(profile).is_none_or(|a| a.availability.can_talk())
+    }
+
+    pub fn set_clock_running(&self, running: bool) {
+        if let Some(clock) = &self.clock {
+            clock.write().expect("clock lock poisoned").set_running(running);
+        }
+    }
+
+    /// Moves the simulated time forward and announces it.
+    pub fn advance_clock(&self, delta: TimeDelta) -> Result<(), Box<dyn Error>> {
+        let clock = self.clock.as_ref().ok_or("The room has no clock")?;
+        let now = {
+            let mut clock = clock.write().expect("clock lock poisoned");
+            clock.advance(delta)?;
+            clock.now()
+        };
+        self.send_notice(Arc::new(NoticeMessage { msg: format!("Time passes... it is now {}", now.format(TIME_FORMAT)) }))
+    }
+
+    /// A copy of the current state of the world, if the room has one.
+    pub fn world(&self) -> Option<World> {
+        self.world.as_ref().map(|w| w.read().expect("world lock poisoned").clone())
+    }
+
+    /// Name of the location of the participant, if it's somewhere in the world.
+    pub fn location_of(&self, id: &str) -> Option<String> {
+        let world = self.world.as_ref()?.read().expect("world lock poisoned");
+        world.positions.get(id).map(|l| world.location_name(l))
+    }
+
+    /// Who hears what the participant says: the participants at its location.
+    /// Participants who aren't anywhere in the world are heard by everyone.
+    pub fn local_visibility(&self, id: &str) -> Visibility {
+        let Some(world) = &self.world else { return Visibility::Public };
+        let world = world.read().expect("world lock poisoned");
+        match world.positions.get(id) {
+            Some(location) => Visibility::Local {
+                location: world.location_name(location),
+                present: self.witnesses(&world, std::slice::from_ref(location)),
+            },
+            None => Visibility::Public,
+        }
+    }
+
+    /// The participants at the locations. The human sees everything unless they are somewhere in the world.
+    fn witnesses(&self, world: &World, locations: &[String]) -> Vec<String> {
+        let mut present: Vec<String> = Vec::new();
+        for id in locations.iter().flat_map(|l| world.present_at(l)) {
+            if !present.contains(&id) {
+                present.push(id);
+            }
+        }
+        if let Some(user) = self.user()
+            && !world.positions.contains_key(&user.id) {
+            present.push(user.id.clone());
+        }
+        present
+    }
+
+    /// Validates and applies the action of the participant, and shows what happened, or what it failed to do,
+    /// to the participants who could see it.
+    pub fn apply_action(&self, actor: &str, action: &WorldAction) -> Result<(), Box<dyn Error>> {
+        let world = self.world.as_ref().ok_or("The room has no world")?;
+        let event = {
+            let mut world = world.write().expect("world lock poisoned");
+            let (text, locations) = match world.apply(actor, action) {
+                Ok(change) => (change.text, change.locations),
+                Err(reason) => (format!("@{} tries to {}, but {}.", actor, action.describe(), reason),
+                                world.positions.get(actor).cloned().into_iter().collect()),
+            };
+            let location = locations.last().map(|l| world.location_name(l)).unwrap_or_default();
+            EventMessage { text, visibility: Visibility::Local { location, present: self.witnesses(&world, &locations) } }
+        };
+        self.sender.send(Message::WorldChanged(Arc::new(event)))?;
+        Ok(())
+    }
+
+    /// Whether the room plays a tabletop RPG, with dice and character sheets.
+    pub fn plays_rpg(&self) -> bool {
+        self.dice.is_some()
+    }
+
+    /// The seed of the dice, if the room plays a tabletop RPG.
+    pub fn dice_seed(&self) -> Option<u64> {
+        self.dice_seed
+    }
+
+    /// The current character sheets by participant id.
+    pub fn sheets(&self) -> BTreeMap<String, CharacterSheet> {
+        self.sheets.read().expect("sheets lock poisoned").clone()
+    }
+
+    /// Rolls the dice for the participant and shows the result to the participants around.
+    pub fn roll_dice(&self, actor: &str, expression: &str, reason: Option<&str>) -> Result<DiceRoll, Box<dyn Error>> {
+        let dice = self.dice.as_ref().ok_or("The room doesn't play with dice")?;
+        let expression: DiceExpression = expression.parse()?;
+        let roll = expression.roll(&mut *dice.lock().expect("dice lock poisoned"));
+        let reason = reason.filter(|r| !r.trim().is_empty()).map(|r| format!(" for {}", r.trim())).unwrap_or_default();
+        self.sender.send(Message::WorldChanged(Arc::new(EventMessage {
+            text: format!("@{} rolls {}{}", actor, roll.describe(), reason),
+            visibility: self.local_visibility(actor),
+        })))?;
+        Ok(roll)
+    }
+
+    /// Changes the character sheet of a participant and shows the change to the participants around them.
+    pub fn change_sheet(&self, change: &SheetChange) -> Result<(), Box<dyn Error>> {
+        let who = change.who().trim_start_matches('@');
+        let text = {
+            let mut sheets = self.sheets.write().expect("sheets lock poisoned");
+            let sheet = sheets.get_mut(who).ok_or_else(|| format!("@{} has no character sheet", who))?;
+            sheet.apply(change)?
+        };
+        self.sender.send(Message::WorldChanged(Arc::new(EventMessage { text, visibility: self.local_visibility(who) })))?;
+        Ok(())
+    }
+
+    pub fn start_game(&self, game: Game) {
+        *self.game.write().expect("game lock poisoned") = Some(game);
+    }
+
+    /// A copy of the current state of the game, if one is played in the room.
+    pub fn game(&self) -> Option<Game> {
+        self.game.read().expect("game lock poisoned").clone()
+    }
+
+    /// Changes the state of the game, if one is played in the room.
+    pub fn update_game<R>(&self, f: impl FnOnce(&mut Game) -> R) -> Option<R> {
+        self.game.write().expect("game lock poisoned").as_mut().map(f)
+    }
+
+    /// Votes for a player in the current phase of the game.
+    pub fn cast_vote(&self, voter: &str, target: &str) -> Result<(), String> {
+        self.update_game(|g| g.vote(voter, target)).unwrap_or_else(|| Err("No game is played in the room".to_string()))
+    }
+
+    /// Who hears what the participant says. During a game, the werewolves talk among themselves at night,
+    /// and it fails if the participant can't talk at all.
+    pub fn speech_visibility(&self, id: &str) -> Result<Visibility, String> {
+        let listeners = match self.game.read().expect("game lock poisoned").as_ref() {
+            Some(game) => game.listeners(id)?,
+            None => None,
+        };
+        Ok(listeners.map_or_else(|| self.local_visibility(id), Visibility::Whisper))
+    }
+
+    /// Restores what changed about an agent in earlier chats, on top of its profile.
+    pub fn restore_state(&self, id: &str, state: &ProfileState) {
+        let mut relationships = self.relationships.write().expect("relationships lock poisoned");
+        if let Some(current) = relationships.get_mut(id) {
+            current.extend(state.relationships.clone());
+        }
+        drop(relationships);
+        if let (Some(mood), Some(changed_at)) = (&state.mood, state.mood_changed_at) {
+            self.moods.write().expect("moods lock poisoned").insert(id.to_string(), (mood.clone(), changed_at));
+        }
+        if let Some(sheet) = &state.sheet {
+            self.sheets.write().expect("sheets lock poisoned").insert(id.to_string(), sheet.clone());
+        }
+    }
+
+    /// The mood of the agent right now, settling back to its usual mood since it last changed.
+    pub fn mood(&self, id: &str) -> Mood {
+        let usual = self.usual_mood(id);
+        match self.moods.read().expect("moods lock poisoned").get(id) {
+            Some((mood, changed_at)) => mood.decayed(&usual, Local::now() - changed_at),
+            None => usual,
+        }
+    }
+
+    /// Changes the mood of the agent and returns the new mood.
+    pub fn adjust_mood(&self, id: &str, valence: f32, arousal: f32, emotions: &BTreeMap<String, f32>) -> Mood {
+        let mut mood = self.mood(id);
+        mood.adjust(valence, arousal, emotions);
+        self.moods.write().expect("moods lock poisoned").insert(id.to_string(), (mood.clone(), Local::now()));
+        mood
+    }
+
+    /// The mood of the agent when it last changed and when that was, if it ever changed from its usual one.
+    pub fn changed_mood(&self, id: &str) -> Option<(Mood, DateTime<Local>)> {
+        self.moods.read().expect("moods lock poisoned").get(id).cloned()
+    }
+
+    fn usual_mood(&self, id: &str) -> Mood {
+        self.profiles.read().expect("profiles lock poisoned").iter()
+            .find(|p| p.id == id)
+            .and_then(|p| p.mood.clone())
+            .unwrap_or_default()
+    }
+
+    /// The relationships of the agent with the other participants, by participant id.
+    pub fn relationships(&self, id: &str) -> BTreeMap<String, Relationship> {
+        self.relationships.read().expect("relationships lock poisoned").get(id).cloned().unwrap_or_default()
+    }
+
+    /// All the relationships in the room as (agent id, participant id, relationship), sorted by ids.
+    pub fn all_relationships(&self) -> Vec<(String, String, Relationship)> {
+        let relationships = self.relationships.read().expect("relationships lock poisoned");
+        let mut all: Vec<_> = relationships.iter()
+            .flat_map(|(from, r)| r.iter().map(|(to, rel)| (from.clone(), to.clone(), rel.clone())))
+            .collect();
+        all.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        all
+    }
+
+    /// Changes how the agent feels about a participant and returns the new relationship.
+    pub fn adjust_relationship(&self, from: &str, to: &str, affinity: f32, trust: f32, description: Option<String>) -> Relationship {
+        let mut relationships = self.relationships.write().expect("relationships lock poisoned");
+        let relationship = relationships.entry(from.to_string()).or_default().entry(to.to_string()).or_default();
+        relationship.adjust(affinity, trust);
+        if let Some(description) = description.filter(|d| !d.trim().is_empty()) {
+            relationship.description = description.trim().to_string();
+        }
+        self.changed_relationships.write().expect("changed relationships lock poisoned")
+            .insert((from.to_string(), to.to_string()));
+        relationship.clone()
+    }
+
+    /// The relationships of the agent that changed during the chat, by participant id.
+    pub fn changed_relationships(&self, id: &str) -> BTreeMap<String, Relationship> {
+        let current = self.relationships(id);
+        self.changed_relationships.read().expect("changed relationships lock poisoned").iter()
+            .filter(|(from, _)| from == id)
+            .filter_map(|(_, to)| current.get(to).map(|r| (to.clone(), r.clone())))
+            .collect()
+    }
+
+    pub fn topic(&self) -> Option<String> {
+        self.topic.read().expect("topic lock poisoned").clone()
+    }
+
+    /// Changes the topic of the room and announces it. `None` clears the topic.
+    pub fn set_topic(&self, topic: Option<String>) -> Result<(), Box<dyn Error>> {
+        let msg = match &topic {
+            Some(topic) => format!("The topic is now: {}", topic),
+            None => "The topic was cleared".to_string(),
+        };
+        *self.topic.write().expect("topic lock poisoned") = topic;
+        self.send_notice(Arc::new(NoticeMessage { msg }))
+    }
+
+    /// The current version of the profile of the human participant.
+    pub fn user(&self) -> Option<Arc<Profile>> {
+        self.user.read().expect("user lock poisoned").clone()
+    }
+
+    /// The current version of the profiles in the room.
+    pub fn profiles(&self) -> Vec<Arc<Profile>> {
+        self.profiles.read().expect("profiles lock poisoned").clone()
+    }
+
+    /// Swaps in a changed version of a profile in the room and announces it.
+    /// The relationships and the character sheet that didn't change during the chat are reset to the new version.
+    /// Profiles that aren't in the room are ignored.
+    pub fn apply_profile_change(&self, change: ProfileChange) -> Result<(), Box<dyn Error>> {
+        match change {
+            ProfileChange::Updated(profile) => {
+                let profile: Arc<Profile> = Arc::from(profile);
+                let Some(old) = self.profiles().into_iter().chain(self.user()).find(|p| p.id == profile.id) else { return Ok(()) };
+                let changed_fields = old.changed_fields(&profile);
+                if changed_fields.is_empty() {
+                    return Ok(());
+                }
+                if let Some(current) = self.profiles.write().expect("profiles lock poisoned").iter_mut().find(|p| p.id == profile.id) {
+                    *current = profile.clone();
+                } else {
+                    *self.user.write().expect("user lock poisoned") = Some(profile.clone());
+                }
+                if let Some(relationships) = self.relationships.write().expect("relationships lock poisoned").get_mut(&profile.id) {
+                    for to in old.relationships.keys().chain(profile.relationships.keys()) {
+                        reseed(relationships, to, old.relationships.get(to), profile.relationships.get(to));
+                    }
+                }
+                reseed(&mut self.sheets.write().expect("sheets lock poisoned"), &profile.id, old.sheet.as_ref(), profile.sheet.as_ref());
+                self.sender.send(Message::ProfileUpdated(profile.clone()))?;
+                self.send_notice(Arc::new(NoticeMessage {
+                    msg: format!("Profile {}(@{}) reloaded, changed: {}", profile.name, profile.id, changed_fields.join(", ")),
+                }))
+            }
+            ProfileChange::Invalid { id, error } => {
+                if !self.profiles().into_iter().chain(self.user()).any(|p| p.id == id) {
+                    return Ok(());
+                }
+                self.send_error(Arc::new(ErrorMessage {
+                    msg: format!("Failed to reload profile {}, keeping the old version: {}", id, error),
+                }))
+            }
+        }
     }
 
     pub fn send_chat(&self, msg: Arc<ChatMessage>) -> Result<(), Box<dyn Error>> {
@@ -26,7 +395,99 @@ impl Room {
         Ok(())
     }
 
+    pub fn send_notice(&self, msg: Arc<NoticeMessage>) -> Result<(), Box<dyn Error>> {
+        self.sender.send(Message::Notice(msg))?;
+        Ok(())
+    }
+
+    pub fn send_event(&self, msg: Arc<EventMessage>) -> Result<(), Box<dyn Error>> {
+        self.sender.send(Message::Event(msg))?;
+        Ok(())
+    }
+
+    /// Something the participants don't know, kept for the transcript.
+    pub fn send_hidden(&self, msg: Arc<NoticeMessage>) -> Result<(), Box<dyn Error>> {
+        self.sender.send(Message::Hidden(msg))?;
+        Ok(())
+    }
+
+    /// Gives the floor to the agent, which replies with the instruction added to its prompt.
+    pub fn request_turn(&self, profile_id: &str, instruction: &str, max_words: Option<usize>) -> Result<(), Box<dyn Error>> {
+        self.sender.send(Message::Turn(Arc::new(TurnRequest {
+            profile_id: profile_id.to_string(),
+            instruction: instruction.to_string(),
+            max_words,
+        })))?;
+        Ok(())
+    }
+
+    /// Asks every agent to vote on the poll.
+    pub fn start_poll(&self, poll: Poll) -> Result<(), Box<dyn Error>> {
+        self.sender.send(Message::Poll(Arc::new(poll)))?;
+        Ok(())
+    }
+
+    pub fn send_poll_result(&self, result: Arc<PollResult>) -> Result<(), Box<dyn Error>> {
+        self.sender.send(Message::PollResult(result))?;
+        Ok(())
+    }
+
+    pub fn control_simulation(&self, command: SimulationCommand) -> Result<(), Box<dyn Error>> {
+        self.sender.send(Message::Simulation(command))?;
+        Ok(())
+    }
+
     pub fn subscribe(&self) -> broadcast::Receiver<Message> {
         self.sender.subscribe()
     }
-}
\ No newline at end of file
+}
+
+/// Replaces the value from the old version of a profile with the one from the new version,
+/// unless it changed during the chat.
+fn reseed<T: Clone + PartialEq>(current: &mut BTreeMap<String, T>, key: &str, old: Option<&T>, new: Option<&T>) {
+    if current.get(key) != old {
+        return;
+    }
+    match new {
+        Some(value) => current.insert(key.to_string(), value.clone()),
+        None => current.remove(key),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(id: &str, relationships: &[(&str, f32)], hp: i32) -> Profile {
+        Profile {
+            id: id.to_string(),
+            name: id.to_string(),
+            background: "Test".to_string(),
+            relationships: relationships.iter()
+                .map(|(to, affinity)| (to.to_string(), Relationship { affinity: *affinity, ..Relationship::default() }))
+                .collect(),
+            sheet: Some(CharacterSheet { hp, max_hp: 10, ..CharacterSheet::default() }),
+            ..Profile::default()
+        }
+    }
+
+    #[test]
+    fn apply_profile_change_reseeds_what_didnt_change_during_the_chat() {
+        let room = Room::new(64, vec![Arc::new(profile("ann", &[("bob", 0.1), ("cid", 0.2)], 10))],
+            Some(Arc::new(profile("tuser", &[], 10))), None, None, None, None);
+        let _receiver = room.subscribe();
+        room.adjust_relationship("ann", "bob", 0.3, 0.0, None);
+        room.apply_profile_change(ProfileChange::Updated(Box::new(profile("ann", &[("bob", 0.5), ("dan", 0.4)], 8)))).unwrap();
+        let relationships = room.relationships("ann");
+        assert_eq!(relationships.keys().collect::<Vec<_>>(), ["bob", "dan"]);
+        // Changed during the chat, kept
+        assert!((relationships["bob"].affinity - 0.4).abs() < 1e-6);
+        assert!((relationships["dan"].affinity - 0.4).abs() < 1e-6);
+        assert_eq!(room.sheets()["ann"].hp, 8);
+
+        let user = Profile { background: "Changed".to_string(), ..profile("tuser", &[], 6) };
+        room.apply_profile_change(ProfileChange::Updated(Box::new(user))).unwrap();
+        assert_eq!(room.user().unwrap().background, "Changed");
+        assert_eq!(room.sheets()["tuser"].hp, 6);
+    }
+}
@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::sync::Arc;
+use rand::Rng;
+use crate::chat::plan_agent::PlanAgent;
+use crate::chat::room::Room;
+use crate::chat::world_clock::WorldClock;
+use crate::chat::{event_engine, game_engine, mood_evaluator, poll_engine, profile_watcher, protocol_engine, relationship_evaluator};
+use crate::dao::Store;
+use crate::llm::router::LLMRouter;
+use crate::model::event_script::EventScript;
+use crate::model::lorebook::Lorebook;
+use crate::model::profile::Profile;
+use crate::model::room_config::RoomConfig;
+use crate::model::world::World;
+
+/// A room with its agents, evaluators and engines running.
+pub struct OpenRoom {
+    pub room: Arc<Room>,
+    pub llms: Arc<LLMRouter>,
+    /// Profile of the human participant
+    pub user: Arc<Profile>,
+}
+
+/// Builds the room described by the config, restores the state of its agents from the store,
+/// and starts the agents, evaluators, engines and watchers taking part in it.
+pub async fn open_room(config: &RoomConfig, store: &Store) -> Result<OpenRoom, Box<dyn Error>> {
+    if config.game.is_some() && config.protocol.is_some() {
+        return Err("A room can't play a game and follow a turn protocol at the same time".into());
+    }
+    let lorebook = match &config.lorebook {
+        Some(path) => Lorebook::load_from_yaml(path).await?,
+        None => Lorebook::default(),
+    };
+    let events = match &config.events {
+        Some(path) => EventScript::load_from_yaml(path).await?,
+        None => EventScript::default(),
+    };
+    let llms = LLMRouter::load(&config.llm).await
+        .map_err(|e| format!("Failed to load the LLM configs: {}", e))?;
+    let user_id = config.user.as_ref()
+        .ok_or("Choose the profile you chat as, with --as or `user` in the room file")?;
+    let user = Arc::new(store.profiles.get(user_id).await?.ok_or(format!("Profile {} not found", user_id))?);
+    // The human's profile can't be an agent at the same time
+    let mut profiles: Vec<Arc<Profile>> = Vec::new();
+    for id in config.participants.iter().filter(|&id| id != user_id) {
+        let profile = store.profiles.get(id).await?.ok_or(format!("No profile {}", id))?;
+        profiles.push(Arc::new(profile));
+    }
+    let clock = config.clock.as_ref()
+        .map(|c| WorldClock::new(c, config.autonomous.enabled))
+        .transpose()?;
+    let world = match &config.world {
+        Some(path) => {
+            let world = World::load_from_yaml(path).await?;
+            let participants: Vec<String> = profiles.iter().chain([&user]).map(|p| p.id.clone()).collect();
+            let errors = world.validate(&participants);
+            if !errors.is_empty() {
+                return Err(format!("Invalid world file {}:\n{}", path, errors.join("\n")).into());
+            }
+            Some(world)
+        }
+        None => None,
+    };
+    // The seed is drawn when not configured, so the session records it either way
+    let dice_seed = config.rpg.as_ref().map(|r| r.seed.unwrap_or_else(|| rand::rng().random()));
+    let room = Arc::new(Room::new(config.limits.channel_size, profiles, Some(user.clone()), config.topic.clone(), clock, world, dice_seed));
+    // The human keeps its character sheet between role-plays too
+    for profile in room.profiles().iter().chain([&user]) {
+        let state = store.states.get(&profile.id).await?;
+        room.restore_state(&profile.id, &state);
+    }
+    let llms = Arc::new(llms);
+    let plan_agent = PlanAgent::new(llms.clone(), room.clone(), config, lorebook, store.memories.clone());
+    plan_agent.start().await;
+    if config.evaluators.relationships {
+        relationship_evaluator::start(room.clone(), llms.evaluator());
+    }
+    if config.evaluators.mood {
+        mood_evaluator::start(room.clone(), llms.evaluator());
+    }
+    profile_watcher::start(room.clone(), store.profiles.clone())?;
+    event_engine::start(room.clone(), events)?;
+    poll_engine::start(room.clone(), llms.clone());
+    if let Some(game) = config.game.clone() {
+        game_engine::start(room.clone(), llms.clone(), game, user.id.clone())?;
+    }
+    if let Some(protocol) = config.protocol.clone() {
+        protocol_engine::start(room.clone(), protocol, user.id.clone())?;
+    }
+    Ok(OpenRoom { room, llms, user })
+}
@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::sync::Arc;
+use serde::Deserialize;
+use serde_json::json;
+use crate::chat::message::ErrorMessage;
+use crate::chat::room::Room;
+use crate::dao::profile_state_dao::ProfileStateDao;
+use crate::llm::{LLMTool, LLMToolCall};
+use crate::model::character_sheet::SheetChange;
+use crate::model::dice::DiceRoll;
+
+const ROLL: &str = "roll";
+const SHEET_TOOLS: &[&str] = &["change_hp", "add_item", "remove_item"];
+
+#[derive(Deserialize)]
+struct RollArguments {
+    dice: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// The dice tool of the tabletop role-play, and the character sheet tools if `sheets`.
+/// Changing the sheets is left to the narrator.
+pub fn tools(sheets: bool) -> Vec<LLMTool> {
+    let tool = |name: &str, description: &str, parameters: serde_json::Value| LLMTool {
+        name: name.to_string(),
+        description: description.to_string(),
+        parameters,
+    };
+    let string = |description: &str| json!({ "type": "string", "description": description });
+    let who = string("Id of the participant whose character sheet changes, without @");
+    let mut tools = vec![
+        tool(ROLL, "Roll dice for anything left to chance. Never make up the result of a roll.", json!({
+            "type": "object",
+            "properties": {
+                "dice": string("Dice in tabletop notation, e.g. `1d20+2` or `2d6`"),
+                "reason": string("What the roll is for, e.g. `climbing the wall`"),
+            },
+            "required": ["dice"],
+        })),
+    ];
+    if !sheets {
+        return tools;
+    }
+    tools.extend([
+        tool("change_hp", "Heal a character with a positive amount, or hurt it with a negative one.", json!({
+            "type": "object",
+            "properties": { "who": who, "amount": { "type": "integer", "description": "Hit points gained or lost" } },
+            "required": ["who", "amount"],
+        })),
+        tool("add_item", "Put an item in the inventory of a character.", json!({
+            "type": "object",
+            "properties": { "who": who, "item": string("Name of the item") },
+            "required": ["who", "item"],
+        })),
+        tool("remove_item", "Take an item out of the inventory of a character, when it's used up, lost or given away.", json!({
+            "type": "object",
+            "properties": { "who": who, "item": string("Name of the item") },
+            "required": ["who", "item"],
+        })),
+    ]);
+    tools
+}
+
+/// Whether the tool call is one of the tabletop role-play tools.
+pub fn handles(call: &LLMToolCall) -> bool {
+    call.name == ROLL || SHEET_TOOLS.contains(&call.name.as_str())
+}
+
+/// Rolls the dice and changes the sheets of the calls, in order. Each result is shown in the room.
+/// Returns the rolls, for the speaker to react to.
+pub fn perform(room: &Room, actor: &str, calls: &[LLMToolCall]) -> Result<Vec<DiceRoll>, Box<dyn Error>> {
+    let mut rolls = Vec::new();
+    for call in calls {
+        let result = if call.name == ROLL {
+            serde_json::from_str::<RollArguments>(&call.arguments).map_err(|e| e.into())
+                .and_then(|args| room.roll_dice(actor, &args.dice, args.reason.as_deref()))
+                .map(|roll| rolls.push(roll))
+        } else {
+            parse_change(call).map_err(|e| e.into()).and_then(|change| room.change_sheet(&change))
+        };
+        if let Err(e) = result {
+            room.send_error(Arc::new(ErrorMessage {
+                msg: format!("@{} made an invalid `{}` call: {}", actor, call.name, e),
+            }))?;
+        }
+    }
+    Ok(rolls)
+}
+
+/// Tells the speaker what its dice rolled, to write its reply knowing the results.
+pub fn results_prompt(rolls: &[DiceRoll]) -> String {
+    format!("The dice were rolled for this turn: {}. Write the reply knowing these results, \
+        without rolling again or changing them.", rolls.iter().map(|r| r.describe()).collect::<Vec<_>>().join("; "))
+}
+
+fn parse_change(call: &LLMToolCall) -> Result<SheetChange, serde_json::Error> {
+    let mut arguments: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&call.arguments)?;
+    arguments.insert("action".to_string(), json!(call.name));
+    serde_json::from_value(serde_json::Value::Object(arguments))
+}
+
+/// Saves the character sheets at the end of the role-play, so they carry over to the next one.
+pub async fn save(room: &Room, dao: &dyn ProfileStateDao) -> Result<(), Box<dyn Error>> {
+    if !room.plays_rpg() {
+        return Ok(());
+    }
+    for (id, sheet) in room.sheets() {
+        let mut state = dao.get(&id).await?;
+        state.sheet = Some(sheet);
+        dao.save(&id, &state).await?;
+    }
+    Ok(())
+}
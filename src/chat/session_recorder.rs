@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use chrono::{DateTime, Local};
+use rand::Rng;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use crate::chat::message::{ChatMessage, EventMessage, Message};
+use crate::chat::room::Room;
+use crate::model::session::{Session, SessionMessage, Visibility};
+
+/// Records the messages sent in a room as a session transcript.
+pub struct SessionRecorder {
+    room: Arc<Room>,
+    started_at: DateTime<Local>,
+    messages: Arc<Mutex<Vec<Arc<ChatMessage>>>>,
+    hidden: Arc<Mutex<Vec<String>>>,
+    handle: JoinHandle<()>,
+}
+
+impl SessionRecorder {
+    pub fn start(room: Arc<Room>) -> Self {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let mut receiver = room.subscribe();
+        let hidden = Arc::new(Mutex::new(Vec::new()));
+        let recorded = messages.clone();
+        let recorded_hidden = hidden.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(Message::Chat(chat)) => recorded.lock().await.push(chat),
+                    Ok(Message::Event(event)) | Ok(Message::WorldChanged(event)) => recorded.lock().await.push(Arc::new(event.to_chat())),
+                    // The result of a poll is kept as a message from the world
+                    Ok(Message::PollResult(result)) => recorded.lock().await
+                        .push(Arc::new(EventMessage { text: result.describe(), visibility: Visibility::Public }.to_chat())),
+                    Ok(Message::Hidden(notice)) => recorded_hidden.lock().await.push(notice.msg.clone()),
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+        SessionRecorder { room, started_at: Local::now(), messages, hidden, handle }
+    }
+
+    /// Stops recording and returns the transcript. Messages still being streamed
+    /// are recorded with the content received so far.
+    pub async fn finish(self) -> Session {
+        self.handle.abort();
+        let mut messages = Vec::new();
+        for m in self.messages.lock().await.iter() {
+            messages.push(SessionMessage {
+                from_user_id: m.from_user_id.clone(),
+                from_username: m.from_username.clone(),
+                role: m.role.clone(),
+                content: m.current_content().await,
+                visibility: m.visibility.clone(),
+            });
+        }
+        Session {
+            // Chats started in the same second get different ids
+            id: format!("{}-{:08x}", self.started_at.format("%Y%m%d-%H%M%S"), rand::rng().random::<u32>()),
+            started_at: self.started_at,
+            profile_ids: self.room.profiles().iter().map(|p| p.id.clone()).collect(),
+            messages,
+            hidden: self.hidden.lock().await.clone(),
+            dice_seed: self.room.dice_seed(),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use chrono::{NaiveDateTime, TimeDelta};
+use std::error::Error;
+use std::time::Instant;
+use crate::model::room_config::{parse_world_time, ClockConfig};
+
+/// Simulated time of the room. It runs `speed` times faster than the real time while running,
+/// and can be moved forward by hand.
+pub struct WorldClock {
+    /// Simulated time when the clock was last started, stopped or moved
+    anchor_time: NaiveDateTime,
+    /// Real time of the anchor, if the clock is running
+    anchor_instant: Option<Instant>,
+    speed: f64,
+}
+
+impl WorldClock {
+    pub fn new(config: &ClockConfig, running: bool) -> Result<Self, Box<dyn Error>> {
+        Ok(WorldClock {
+            anchor_time: parse_world_time(&config.start)?,
+            anchor_instant: running.then(Instant::now),
+            speed: config.speed,
+        })
+    }
+
+    pub fn now(&self) -> NaiveDateTime {
+        match self.anchor_instant {
+            Some(instant) => {
+                let elapsed = instant.elapsed().as_secs_f64() * self.speed;
+                // The clock stops at the end of time instead of overflowing
+                TimeDelta::try_milliseconds((elapsed * 1000.0) as i64)
+                    .and_then(|delta| self.anchor_time.checked_add_signed(delta))
+                    .unwrap_or(NaiveDateTime::MAX)
+            }
+            None => self.anchor_time,
+        }
+    }
+
+    pub fn set_running(&mut self, running: bool) {
+        self.anchor_time = self.now();
+        self.anchor_instant = running.then(Instant::now);
+    }
+
+    /// Moves the clock forward, unless it would go past the latest representable time.
+    pub fn advance(&mut self, delta: TimeDelta) -> Result<(), String> {
+        self.anchor_time = self.now().checked_add_signed(delta)
+            .ok_or("The clock can't go that far in the future")?;
+        if self.anchor_instant.is_some() {
+            self.anchor_instant = Some(Instant::now());
+        }
+        Ok(())
+    }
+}
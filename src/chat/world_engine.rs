@@ -0,0 +1,64 @@
+use std::error::Error;
+use std::sync::Arc;
+use serde_json::json;
+use crate::chat::message::ErrorMessage;
+use crate::chat::room::Room;
+use crate::llm::{LLMTool, LLMToolCall};
+use crate::model::world::WorldAction;
+
+/// The actions the agents can take in the world, as tools for the LLM.
+pub fn tools() -> Vec<LLMTool> {
+    let tool = |name: &str, description: &str, parameters: serde_json::Value| LLMTool {
+        name: name.to_string(),
+        description: description.to_string(),
+        parameters,
+    };
+    let string = |description: &str| json!({ "type": "string", "description": description });
+    vec![
+        tool("move", "Go to another location, by its id.", json!({
+            "type": "object",
+            "properties": { "to": string("Id of the location to go to") },
+            "required": ["to"],
+        })),
+        tool("take", "Pick up an object at the current location, by its id.", json!({
+            "type": "object",
+            "properties": { "object": string("Id of the object to take") },
+            "required": ["object"],
+        })),
+        tool("give", "Hand an object you hold to a participant at the same location.", json!({
+            "type": "object",
+            "properties": {
+                "object": string("Id of the object to give"),
+                "to": string("Id of the participant, without @"),
+            },
+            "required": ["object", "to"],
+        })),
+        tool("say", "Say something to the participants at the same location.", json!({
+            "type": "object",
+            "properties": { "text": string("What you say") },
+            "required": ["text"],
+        })),
+    ]
+}
+
+/// Applies the tool calls of the agent to the world of the room, in order. Each change is
+/// broadcast to the participants who saw it. Returns what the agent says.
+pub fn perform(room: &Room, actor: &str, calls: &[LLMToolCall]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut speech = Vec::new();
+    for call in calls {
+        match parse_call(call) {
+            Ok(WorldAction::Say { text }) => speech.push(text),
+            Ok(action) => room.apply_action(actor, &action)?,
+            Err(e) => room.send_error(Arc::new(ErrorMessage {
+                msg: format!("@{} made an invalid `{}` call: {}", actor, call.name, e),
+            }))?,
+        }
+    }
+    Ok(speech)
+}
+
+fn parse_call(call: &LLMToolCall) -> Result<WorldAction, serde_json::Error> {
+    let mut arguments: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&call.arguments)?;
+    arguments.insert("action".to_string(), json!(call.name));
+    serde_json::from_value(serde_json::Value::Object(arguments))
+}
@@ -0,0 +1,317 @@
+use crate::model::profile::Profile;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Keyword of the PNG `tEXt` chunk holding the base64 encoded card JSON.
+const CARD_KEYWORD: &str = "chara";
+const SPEC_V2: &str = "chara_card_v2";
+const EXAMPLE_SEPARATOR: &str = "<START>";
+
+/// A community character card in the Character Card V2 format.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CharacterCard {
+    pub spec: String,
+    pub spec_version: String,
+    pub data: CardData,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CardData {
+    pub name: String,
+    pub description: String,
+    pub personality: String,
+    pub scenario: String,
+    pub first_mes: String,
+    pub mes_example: String,
+    pub creator_notes: String,
+    pub system_prompt: String,
+    pub post_history_instructions: String,
+    pub alternate_greetings: Vec<String>,
+    pub character_book: Option<serde_json::Value>,
+    pub tags: Vec<String>,
+    pub creator: String,
+    pub character_version: String,
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Result of importing a character card.
+pub struct ImportedCard {
+    pub profile: Profile,
+    /// Card fields with content that has no place in a profile.
+    pub unmapped_fields: Vec<&'static str>,
+}
+
+/// Parses a card from JSON, or from a PNG image with the card embedded.
+pub fn parse_card(bytes: &[u8]) -> Result<CardData, Box<dyn Error>> {
+    let json = if bytes.starts_with(PNG_SIGNATURE) {
+        let encoded = read_png_text(bytes, CARD_KEYWORD)?
+            .ok_or("no character card found in the PNG image")?;
+        STANDARD.decode(encoded.trim())?
+    } else {
+        bytes.to_vec()
+    };
+    let value: serde_json::Value = serde_json::from_slice(&json)?;
+    // V1 cards have the fields at the top level instead of under `data`
+    match value.get("data") {
+        Some(data) if value.get("spec").and_then(|s| s.as_str()) == Some(SPEC_V2) => Ok(serde_json::from_value(data.clone())?),
+        _ => Ok(serde_json::from_value(value)?),
+    }
+}
+
+pub fn card_to_profile(id: &str, card: &CardData) -> ImportedCard {
+    let mut background = vec![card.description.trim().to_string()];
+    if !card.personality.trim().is_empty() {
+        background.push(format!("Personality: {}", card.personality.trim()));
+    }
+    if !card.scenario.trim().is_empty() {
+        background.push(format!("Scenario: {}", card.scenario.trim()));
+    }
+
+    let mut conversation_examples = Vec::new();
+    if !card.first_mes.trim().is_empty() {
+        conversation_examples.push(card.first_mes.trim().to_string());
+    }
+    conversation_examples.extend(card.mes_example.split(EXAMPLE_SEPARATOR)
+        .map(|example| example.trim().to_string())
+        .filter(|example| !example.is_empty()));
+    let conversation_examples = conversation_examples.into_iter()
+        .map(|example| example.replace("{{char}}", &card.name).replace("{{user}}", "User"))
+        .collect();
+
+    let unmapped_fields = [
+        ("creator_notes", card.creator_notes.trim().is_empty()),
+        ("system_prompt", card.system_prompt.trim().is_empty()),
+        ("post_history_instructions", card.post_history_instructions.trim().is_empty()),
+        ("alternate_greetings", card.alternate_greetings.is_empty()),
+        ("character_book", card.character_book.is_none()),
+        ("tags", card.tags.is_empty()),
+        ("creator", card.creator.trim().is_empty()),
+        ("character_version", card.character_version.trim().is_empty()),
+        ("extensions", card.extensions.is_empty()),
+    ].into_iter()
+        .filter(|(_, empty)| !empty)
+        .map(|(field, _)| field)
+        .collect();
+
+    ImportedCard {
+        profile: Profile {
+            id: id.to_string(),
+            name: card.name.clone(),
+            background: background.into_iter().filter(|s| !s.is_empty()).collect::<Vec<_>>().join("\n\n"),
+            conversation_examples,
+            ..Default::default()
+        },
+        unmapped_fields,
+    }
+}
+
+pub fn profile_to_card(profile: &Profile) -> CharacterCard {
+    let mes_example = profile.conversation_examples.iter()
+        .map(|example| format!("{}\n{}", EXAMPLE_SEPARATOR, example))
+        .collect::<Vec<_>>()
+        .join("\n");
+    CharacterCard {
+        spec: SPEC_V2.to_string(),
+        spec_version: "2.0".to_string(),
+        data: CardData {
+            name: profile.name.clone(),
+            description: profile.background.clone(),
+            mes_example,
+            ..Default::default()
+        },
+    }
+}
+
+pub fn card_to_json(card: &CharacterCard) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(serde_json::to_vec_pretty(card)?)
+}
+
+/// Embeds the card into a PNG image, replacing any card already in it.
+/// A blank 1x1 image is used if no image is given.
+pub fn card_to_png(card: &CharacterCard, image: Option<&[u8]>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let image = match image {
+        Some(image) => image.to_vec(),
+        None => blank_png(),
+    };
+    if !image.starts_with(PNG_SIGNATURE) {
+        return Err("image is not a PNG file".into());
+    }
+    let mut text = CARD_KEYWORD.as_bytes().to_vec();
+    text.push(0);
+    text.extend(STANDARD.encode(serde_json::to_vec(card)?).into_bytes());
+
+    let mut result = PNG_SIGNATURE.to_vec();
+    for (chunk_type, data) in png_chunks(&image)? {
+        if chunk_type == b"tEXt" && data.starts_with(&text[..CARD_KEYWORD.len() + 1]) {
+            continue;
+        }
+        if chunk_type == b"IEND" {
+            write_png_chunk(&mut result, b"tEXt", &text);
+        }
+        write_png_chunk(&mut result, chunk_type, data);
+    }
+    Ok(result)
+}
+
+fn read_png_text(png: &[u8], keyword: &str) -> Result<Option<String>, Box<dyn Error>> {
+    for (chunk_type, data) in png_chunks(png)? {
+        if chunk_type != b"tEXt" {
+            continue;
+        }
+        if let Some(pos) = data.iter().position(|b| *b == 0)
+            && &data[..pos] == keyword.as_bytes() {
+            return Ok(Some(String::from_utf8_lossy(&data[pos + 1..]).to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// A PNG chunk as (chunk type, chunk data).
+type PngChunk<'a> = (&'a [u8], &'a [u8]);
+
+fn png_chunks(png: &[u8]) -> Result<Vec<PngChunk<'_>>, Box<dyn Error>> {
+    let mut chunks = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < png.len() {
+        if pos + 12 > png.len() {
+            return Err("truncated PNG chunk".into());
+        }
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into()?) as usize;
+        let data_end = pos + 8 + len;
+        if data_end + 4 > png.len() {
+            return Err("truncated PNG chunk".into());
+        }
+        let (chunk_type, data) = (&png[pos + 4..pos + 8], &png[pos + 8..data_end]);
+        if png[data_end..data_end + 4] != chunk_crc(chunk_type, data).to_be_bytes() {
+            return Err(format!("PNG chunk {} has a bad CRC", String::from_utf8_lossy(chunk_type)).into());
+        }
+        chunks.push((chunk_type, data));
+        pos = data_end + 4;
+    }
+    Ok(chunks)
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, chunk_type: &[u8], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend(chunk_type);
+    out.extend(data);
+    out.extend(chunk_crc(chunk_type, data).to_be_bytes());
+}
+
+fn chunk_crc(chunk_type: &[u8], data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+fn blank_png() -> Vec<u8> {
+    // 1x1 RGBA image with a single transparent pixel
+    let header = [0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0];
+    let pixels = [0x78, 0x9c, 0x63, 0x60, 0x00, 0x02, 0x00, 0x00, 0x05, 0x00, 0x01];
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_png_chunk(&mut png, b"IHDR", &header);
+    write_png_chunk(&mut png, b"IDAT", &pixels);
+    write_png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V2_CARD: &str = r#"{
+        "spec": "chara_card_v2",
+        "spec_version": "2.0",
+        "data": {
+            "name": "Seraphina",
+            "description": "A forest guardian.",
+            "personality": "Kind and protective",
+            "scenario": "{{user}} wakes up in her glade.",
+            "first_mes": "*{{char}} smiles.* You're awake, {{user}}.",
+            "mes_example": "<START>\n{{user}}: Who are you?\n{{char}}: I'm {{char}}.\n<START>\n{{char}}: Rest now.",
+            "tags": ["fantasy"]
+        }
+    }"#;
+
+    fn card_profile(bytes: &[u8]) -> Profile {
+        card_to_profile("seraphina", &parse_card(bytes).unwrap()).profile
+    }
+
+    #[test]
+    fn maps_v2_json_to_a_profile() {
+        let imported = card_to_profile("seraphina", &parse_card(V2_CARD.as_bytes()).unwrap());
+        let profile = imported.profile;
+        assert_eq!(profile.id, "seraphina");
+        assert_eq!(profile.name, "Seraphina");
+        assert_eq!(profile.background,
+            "A forest guardian.\n\nPersonality: Kind and protective\n\nScenario: {{user}} wakes up in her glade.");
+        assert_eq!(profile.conversation_examples.len(), 3);
+        assert_eq!(imported.unmapped_fields, ["tags"]);
+    }
+
+    #[test]
+    fn reads_v1_cards_with_top_level_fields() {
+        let profile = card_profile(br#"{"name": "Bob", "description": "A baker.", "first_mes": "Fresh bread!"}"#);
+        assert_eq!(profile.name, "Bob");
+        assert_eq!(profile.background, "A baker.");
+        assert_eq!(profile.conversation_examples, ["Fresh bread!"]);
+    }
+
+    #[test]
+    fn substitutes_char_and_user_in_examples() {
+        let profile = card_profile(V2_CARD.as_bytes());
+        assert_eq!(profile.conversation_examples, [
+            "*Seraphina smiles.* You're awake, User.",
+            "User: Who are you?\nSeraphina: I'm Seraphina.",
+            "Seraphina: Rest now.",
+        ]);
+    }
+
+    #[test]
+    fn round_trips_a_profile_through_png() {
+        let profile = Profile {
+            id: "ann".to_string(),
+            name: "Ann".to_string(),
+            background: "A sailor.".to_string(),
+            conversation_examples: vec!["Ahoy!".to_string(), "Hoist the sails.\nNow!".to_string()],
+            ..Profile::default()
+        };
+        let png = card_to_png(&profile_to_card(&profile), None).unwrap();
+        let imported = card_to_profile("ann", &parse_card(&png).unwrap());
+        assert_eq!(imported.profile, profile);
+        assert!(imported.unmapped_fields.is_empty());
+    }
+
+    #[test]
+    fn replaces_the_card_already_in_the_png() {
+        let first = Profile { name: "First".to_string(), background: "One.".to_string(), ..Profile::default() };
+        let second = Profile { name: "Second".to_string(), background: "Two.".to_string(), ..Profile::default() };
+        let png = card_to_png(&profile_to_card(&first), None).unwrap();
+        let png = card_to_png(&profile_to_card(&second), Some(&png)).unwrap();
+        let cards = png_chunks(&png).unwrap().into_iter().filter(|(chunk_type, _)| *chunk_type == b"tEXt").count();
+        assert_eq!(cards, 1);
+        assert_eq!(card_profile(&png).name, "Second");
+    }
+
+    #[test]
+    fn rejects_broken_pngs() {
+        let png = card_to_png(&profile_to_card(&Profile { name: "Ann".to_string(), ..Profile::default() }), None).unwrap();
+
+        let truncated = &png[..png.len() - 6];
+        assert_eq!(parse_card(truncated).unwrap_err().to_string(), "truncated PNG chunk");
+
+        let mut corrupted = png.clone();
+        let text_start = png.windows(4).position(|w| w == b"tEXt").unwrap();
+        corrupted[text_start + 10] ^= 0xff;
+        assert_eq!(parse_card(&corrupted).unwrap_err().to_string(), "PNG chunk tEXt has a bad CRC");
+
+        assert_eq!(parse_card(&blank_png()).unwrap_err().to_string(), "no character card found in the PNG image");
+        assert_eq!(card_to_png(&profile_to_card(&Profile::default()), Some(b"GIF89a")).unwrap_err().to_string(),
+            "image is not a PNG file");
+    }
+}
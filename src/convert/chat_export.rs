@@ -0,0 +1,294 @@
+use crate::llm::LLM;
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{rng, SeedableRng};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Max characters of a participant's messages sent to the LLM for the background summary.
+const SUMMARY_INPUT_LIMIT: usize = 12000;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ChatExportFormat {
+    /// Detect the format from the file extension and content
+    Auto,
+    /// `result.json` from Telegram Desktop's chat export
+    Telegram,
+    /// `.txt` file from WhatsApp's "Export chat"
+    Whatsapp,
+    /// Plain text log with one `speaker: text` message per line
+    Log,
+}
+
+#[derive(Debug)]
+pub struct ExportedMessage {
+    pub speaker: String,
+    pub text: String,
+}
+
+/// Controls which messages of a participant end up as conversation examples.
+pub struct ExampleFilter {
+    pub min_len: usize,
+    pub max_len: usize,
+    /// Max number of examples to keep, picked randomly while keeping the original order.
+    pub sample: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TelegramExport {
+    messages: Vec<TelegramMessage>,
+}
+
+#[derive(Deserialize)]
+struct TelegramMessage {
+    #[serde(rename = "type")]
+    message_type: String,
+    from: Option<String>,
+    #[serde(default)]
+    text: TelegramText,
+}
+
+/// Telegram stores formatted text as a list of plain strings and entity objects.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TelegramText {
+    Plain(String),
+    Entities(Vec<TelegramTextPart>),
+}
+
+impl Default for TelegramText {
+    fn default() -> Self {
+        TelegramText::Plain(String::new())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TelegramTextPart {
+    Plain(String),
+    Entity { text: String },
+}
+
+pub fn parse_chat_export(format: ChatExportFormat, file_name: &str, contents: &str) -> Result<Vec<ExportedMessage>, Box<dyn Error>> {
+    match format {
+        ChatExportFormat::Auto => {
+            let format = if file_name.to_lowercase().ends_with(".json") {
+                ChatExportFormat::Telegram
+            } else if contents.lines().take(5).any(|line| whatsapp_line_regex().is_match(trim_marks(line))) {
+                ChatExportFormat::Whatsapp
+            } else {
+                ChatExportFormat::Log
+            };
+            parse_chat_export(format, file_name, contents)
+        }
+        ChatExportFormat::Telegram => parse_telegram(contents),
+        ChatExportFormat::Whatsapp => Ok(parse_lines(contents, &whatsapp_line_regex(), Some(&whatsapp_system_regex()))),
+        ChatExportFormat::Log => Ok(parse_lines(contents, &Regex::new(r"^(?P<speaker>[^:]{1,64}):\s?(?P<text>.*)$")?, None)),
+    }
+}
+
+fn parse_telegram(contents: &str) -> Result<Vec<ExportedMessage>, Box<dyn Error>> {
+    let export: TelegramExport = serde_json::from_str(contents)?;
+    Ok(export.messages.into_iter()
+        .filter(|m| m.message_type == "message")
+        .filter_map(|m| {
+            let text = match m.text {
+                TelegramText::Plain(text) => text,
+                TelegramText::Entities(parts) => parts.into_iter()
+                    .map(|part| match part {
+                        TelegramTextPart::Plain(text) => text,
+                        TelegramTextPart::Entity { text } => text,
+                    })
+                    .collect(),
+            };
+            Some(ExportedMessage { speaker: m.from?, text })
+        })
+        .collect())
+}
+
+/// Timestamp starting each WhatsApp line, in both the Android (`12/31/20, 10:15 PM - `)
+/// and the iOS (`[31/12/2020, 22:15:03] `) export formats.
+const WHATSAPP_TIMESTAMP: &str = r"^\[?\d{1,4}[./-]\d{1,2}[./-]\d{1,4},? \d{1,2}:\d{2}(?::\d{2})?(?:\s?[APap]\.?[Mm]\.?)?\]?(?: -)? ";
+
+/// Matches a WhatsApp message, e.g. `12/31/20, 10:15 PM - Alice: hi`.
+fn whatsapp_line_regex() -> Regex {
+    Regex::new(&format!("{WHATSAPP_TIMESTAMP}(?P<speaker>[^:]+): (?P<text>.*)$")).expect("invalid WhatsApp line regex")
+}
+
+/// Matches the timestamped lines that aren't messages, e.g. `12/31/20, 10:15 PM - Bob added Carol`.
+fn whatsapp_system_regex() -> Regex {
+    Regex::new(WHATSAPP_TIMESTAMP).expect("invalid WhatsApp system line regex")
+}
+
+/// Parses line based exports. Lines not matching the message pattern continue the previous message,
+/// unless they are system lines, which are skipped along with their own continuation lines.
+fn parse_lines(contents: &str, line_regex: &Regex, system_regex: Option<&Regex>) -> Vec<ExportedMessage> {
+    let mut messages: Vec<ExportedMessage> = Vec::new();
+    let mut in_message = false;
+    for line in contents.lines() {
+        let line = trim_marks(line);
+        if let Some(captures) = line_regex.captures(line) {
+            messages.push(ExportedMessage {
+                speaker: captures["speaker"].trim().to_string(),
+                text: captures["text"].to_string(),
+            });
+            in_message = true;
+        } else if system_regex.is_some_and(|r| r.is_match(line)) {
+            in_message = false;
+        } else if in_message && let Some(last) = messages.last_mut() {
+            last.text.push('\n');
+            last.text.push_str(line);
+        }
+    }
+    messages
+}
+
+/// Selects the messages of one participant as conversation examples, after filtering
+/// by length and removing duplicates and media placeholders.
+pub fn select_examples(messages: &[ExportedMessage], speaker: &str, filter: &ExampleFilter) -> Result<Vec<String>, Box<dyn Error>> {
+    let speaker_messages: Vec<&ExportedMessage> = messages.iter().filter(|m| m.speaker == speaker).collect();
+    if speaker_messages.is_empty() {
+        let mut speakers: Vec<&str> = messages.iter().map(|m| m.speaker.as_str()).collect();
+        speakers.sort();
+        speakers.dedup();
+        return Err(format!("No messages from `{}` found. Participants: {}", speaker, speakers.join(", ")).into());
+    }
+
+    let mut seen = HashSet::new();
+    let examples: Vec<String> = speaker_messages.into_iter()
+        .map(|m| m.text.trim().to_string())
+        .filter(|text| !is_placeholder(text))
+        .filter(|text| (filter.min_len..=filter.max_len).contains(&text.chars().count()))
+        .filter(|text| seen.insert(text.to_lowercase()))
+        .collect();
+
+    match filter.sample {
+        Some(n) if n < examples.len() => {
+            let mut rng: StdRng = match filter.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_rng(&mut rng()),
+            };
+            let mut indices = rand::seq::index::sample(&mut rng, examples.len(), n).into_vec();
+            indices.sort();
+            Ok(indices.into_iter().map(|i| examples[i].clone()).collect())
+        }
+        _ => Ok(examples),
+    }
+}
+
+/// WhatsApp prefixes the file and some lines with invisible byte order and direction marks.
+fn trim_marks(line: &str) -> &str {
+    line.trim_start_matches(['\u{feff}', '\u{200e}'])
+}
+
+fn is_placeholder(text: &str) -> bool {
+    text.is_empty()
+        || text == "<Media omitted>"
+        || text == "This message was deleted"
+        || text.ends_with("omitted") && text.split_whitespace().count() <= 3
+}
+
+/// Drafts a profile background from what the participant wrote.
+pub async fn summarize_background(llm: &dyn LLM, speaker: &str, messages: &[ExportedMessage]) -> Result<String, Box<dyn Error>> {
+    let mut transcript = String::new();
+    for m in messages.iter().filter(|m| m.speaker == speaker && !is_placeholder(m.text.trim())) {
+        if transcript.len() + m.text.len() > SUMMARY_INPUT_LIMIT {
+            break;
+        }
+        transcript.push_str(&format!("- {}\n", m.text.trim()));
+    }
+    let prompt = format!("Here are messages written by {speaker} in a group chat:\n\
+        {transcript}\n\
+        Based on these messages, write a background for a profile that simulates {speaker} in a chat: \
+        who they are, what they care about, their personality and the way they talk. \
+        Write it in third person as plain prose of one or two paragraphs. \
+        Only describe what the messages support and output nothing else.");
+    Ok(llm.single_chat(Arc::new(prompt)).await?.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(format: ChatExportFormat, file_name: &str, contents: &str) -> Vec<(String, String)> {
+        parse_chat_export(format, file_name, contents).unwrap().into_iter().map(|m| (m.speaker, m.text)).collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected.iter().map(|(speaker, text)| (speaker.to_string(), text.to_string())).collect()
+    }
+
+    #[test]
+    fn parses_whatsapp_android_and_ios_exports() {
+        let android = "\u{feff}12/31/20, 10:15 PM - Messages and calls are end-to-end encrypted.\n\
+            12/31/20, 10:15 PM - Alice: Happy new year\n\
+            and see you soon!\n\
+            12/31/20, 10:16 PM - Alice added Bob\n\
+            12/31/20, 10:17 PM - Bob: <Media omitted>\n";
+        assert_eq!(parse(ChatExportFormat::Auto, "chat.txt", android), pairs(&[
+            ("Alice", "Happy new year\nand see you soon!"),
+            ("Bob", "<Media omitted>"),
+        ]));
+
+        let ios = "[31/12/2020, 22:15:03] Alice: Hi\n\u{200e}[31/12/2020, 22:15:09] Bob: Hello: there";
+        assert_eq!(parse(ChatExportFormat::Whatsapp, "chat.txt", ios), pairs(&[("Alice", "Hi"), ("Bob", "Hello: there")]));
+    }
+
+    #[test]
+    fn parses_telegram_exports() {
+        let export = r#"{"messages": [
+            {"type": "service", "actor": "Alice", "action": "create_group"},
+            {"type": "message", "from": "Alice", "text": "First line\nsecond line"},
+            {"type": "message", "from": "Bob", "text": ["See ", {"type": "link", "text": "example.com"}, "!"]},
+            {"type": "message", "from": null, "text": "From a deleted account"},
+            {"type": "message", "from": "Bob", "photo": "photos/1.jpg"}
+        ]}"#;
+        assert_eq!(parse(ChatExportFormat::Auto, "result.json", export), pairs(&[
+            ("Alice", "First line\nsecond line"),
+            ("Bob", "See example.com!"),
+            ("Bob", ""),
+        ]));
+    }
+
+    #[test]
+    fn parses_plain_logs() {
+        let log = "Transcript of the meeting\nAlice: Hello\nhow are you?\nBob:Fine, thanks: and you?";
+        assert_eq!(parse(ChatExportFormat::Auto, "meeting.log", log), pairs(&[
+            ("Alice", "Hello\nhow are you?"),
+            ("Bob", "Fine, thanks: and you?"),
+        ]));
+    }
+
+    fn messages(lines: &[(&str, &str)]) -> Vec<ExportedMessage> {
+        lines.iter().map(|(speaker, text)| ExportedMessage { speaker: speaker.to_string(), text: text.to_string() }).collect()
+    }
+
+    #[test]
+    fn selects_examples_of_the_speaker() {
+        let messages = messages(&[
+            ("Alice", "Hi"),
+            ("Bob", "Hello Alice"),
+            ("Alice", "  How was the trip?  "),
+            ("Alice", "<Media omitted>"),
+            ("Alice", "how was the trip?"),
+            ("Alice", "This message was deleted"),
+            ("Alice", "We should go hiking again"),
+        ]);
+        let filter = ExampleFilter { min_len: 3, max_len: 20, sample: None, seed: None };
+        assert_eq!(select_examples(&messages, "Alice", &filter).unwrap(), ["How was the trip?"]);
+
+        let filter = ExampleFilter { min_len: 1, max_len: 100, sample: Some(2), seed: Some(7) };
+        let sampled = select_examples(&messages, "Alice", &filter).unwrap();
+        assert_eq!(sampled, select_examples(&messages, "Alice", &filter).unwrap());
+        assert_eq!(sampled.len(), 2);
+        let all = ["Hi", "How was the trip?", "We should go hiking again"];
+        assert!(all.iter().position(|e| *e == sampled[0]) < all.iter().position(|e| *e == sampled[1]));
+
+        let error = select_examples(&messages, "Carol", &filter).unwrap_err();
+        assert_eq!(error.to_string(), "No messages from `Carol` found. Participants: Alice, Bob");
+    }
+}
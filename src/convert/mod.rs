@@ -0,0 +1,3 @@
+pub mod character_card;
+pub mod chat_export;
+pub mod profile_generator;
@@ -0,0 +1,81 @@
+use crate::llm::json::extract_json_array;
+use crate::llm::LLM;
+use crate::model::profile::Profile;
+use log::info;
+use serde::Deserialize;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Number of LLM calls to get enough valid profiles before giving up.
+const MAX_ATTEMPTS: usize = 3;
+
+#[derive(Deserialize)]
+struct GeneratedProfile {
+    name: String,
+    background: String,
+    #[serde(default)]
+    conversation_examples: Vec<String>,
+}
+
+/// Generates a profile for each of the ids from a one-line brief.
+///
+/// Profiles whose name collides with `taken_names` or with each other are regenerated.
+pub async fn generate_profiles(llm: &dyn LLM, brief: &str, ids: &[String], taken_names: &[String]) -> Result<Vec<Profile>, Box<dyn Error>> {
+    let mut taken_names: Vec<String> = taken_names.to_vec();
+    let mut profiles: Vec<Profile> = Vec::new();
+    for attempt in 1..=MAX_ATTEMPTS {
+        let remaining = ids.len() - profiles.len();
+        let response = llm.single_chat(Arc::new(get_prompt(brief, remaining, &taken_names))).await?;
+        let generated: Vec<GeneratedProfile> = match serde_json::from_str(extract_json_array(&response)) {
+            Ok(generated) => generated,
+            Err(e) => {
+                info!("Attempt {} generated invalid JSON: {}", attempt, e);
+                continue;
+            }
+        };
+        for g in generated.into_iter().take(remaining) {
+            let profile = Profile {
+                id: ids[profiles.len()].clone(),
+                name: g.name.trim().to_string(),
+                background: g.background.trim().to_string(),
+                conversation_examples: g.conversation_examples,
+                ..Default::default()
+            };
+            let mut errors = profile.validate(&profile.id);
+            if profile.conversation_examples.is_empty() {
+                errors.push("no conversation examples".to_string());
+            }
+            if taken_names.iter().any(|name| name.eq_ignore_ascii_case(&profile.name)) {
+                errors.push(format!("name `{}` is already taken", profile.name));
+            }
+            if !errors.is_empty() {
+                info!("Attempt {} generated an invalid profile: {}", attempt, errors.join(", "));
+                continue;
+            }
+            taken_names.push(profile.name.clone());
+            profiles.push(profile);
+        }
+        if profiles.len() == ids.len() {
+            return Ok(profiles);
+        }
+    }
+    Err(format!("Only {} of {} profiles were generated after {} attempts", profiles.len(), ids.len(), MAX_ATTEMPTS).into())
+}
+
+fn get_prompt(brief: &str, count: usize, taken_names: &[String]) -> String {
+    let taken = if taken_names.is_empty() {
+        "none".to_string()
+    } else {
+        taken_names.join(", ")
+    };
+    format!("Create {count} character profiles for simulating people in a group chat, based on this brief: {brief}\n\
+        \n\
+        Each profile needs:\n\
+        * `name`: the display name of the character.\n\
+        * `background`: who they are, their history, personality, goals and the way they talk, in plain prose.\n\
+        * `conversation_examples`: 3 to 6 chat messages written in the character's voice.\n\
+        \n\
+        Make the characters clearly different from each other in personality, age, background and way of talking. \
+        Don't use any of these names: {taken}.\n\
+        Output a JSON array of objects with the fields `name`, `background` and `conversation_examples` and nothing else.")
+}
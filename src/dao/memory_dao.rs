@@ -0,0 +1,13 @@
+use std::error::Error;
+use async_trait::async_trait;
+use crate::model::memory::Memory;
+
+#[async_trait]
+pub trait MemoryDao: Send + Sync {
+    /// The memories of the profile, oldest first. Empty if it has none.
+    async fn get(&self, profile_id: &str) -> Result<Vec<Memory>, Box<dyn Error>>;
+    /// Replaces all the memories of the profile.
+    async fn save(&self, profile_id: &str, memories: &[Memory]) -> Result<(), Box<dyn Error>>;
+    /// Ids of the profiles with memories.
+    async fn list_profile_ids(&self) -> Result<Vec<String>, Box<dyn Error>>;
+}
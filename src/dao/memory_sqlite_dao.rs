@@ -0,0 +1,64 @@
+use crate::dao::memory_dao::MemoryDao;
+use crate::dao::sqlite::SqliteDb;
+use crate::model::memory::Memory;
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use rusqlite::params;
+use std::error::Error;
+
+/// Stores the memories in the `memories` table.
+pub struct MemorySqliteDao {
+    db: SqliteDb,
+}
+
+pub(crate) fn new(db: SqliteDb) -> MemorySqliteDao {
+    MemorySqliteDao { db }
+}
+
+#[async_trait]
+impl MemoryDao for MemorySqliteDao {
+    async fn get(&self, profile_id: &str) -> Result<Vec<Memory>, Box<dyn Error>> {
+        let profile_id = profile_id.to_string();
+        let rows = self.db.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT kind, content, session_id, created_at FROM memories WHERE profile_id = ?1 ORDER BY seq")?;
+            stmt.query_map(params![profile_id], |r| Ok((r.get::<_, String>(0)?, r.get(1)?, r.get(2)?, r.get::<_, String>(3)?)))?
+                .collect::<rusqlite::Result<Vec<(String, String, Option<String>, String)>>>()
+        }).await?;
+        let mut memories = Vec::new();
+        for (kind, content, session_id, created_at) in rows {
+            memories.push(Memory {
+                kind: serde_yaml::from_str(&kind)?,
+                content,
+                session_id,
+                created_at: DateTime::parse_from_rfc3339(&created_at)?.with_timezone(&Local),
+            });
+        }
+        Ok(memories)
+    }
+
+    async fn save(&self, profile_id: &str, memories: &[Memory]) -> Result<(), Box<dyn Error>> {
+        let profile_id = profile_id.to_string();
+        let mut rows = Vec::new();
+        for m in memories {
+            rows.push((serde_yaml::to_string(&m.kind)?.trim().to_string(), m.content.clone(), m.session_id.clone(), m.created_at.to_rfc3339()));
+        }
+        self.db.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM memories WHERE profile_id = ?1", params![profile_id])?;
+            for (seq, (kind, content, session_id, created_at)) in rows.iter().enumerate() {
+                tx.execute("INSERT INTO memories (profile_id, seq, kind, content, session_id, created_at) \
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                           params![profile_id, seq, kind, content, session_id, created_at])?;
+            }
+            tx.commit()
+        }).await
+    }
+
+    async fn list_profile_ids(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT DISTINCT profile_id FROM memories ORDER BY profile_id")?;
+            stmt.query_map([], |r| r.get(0))?.collect()
+        }).await
+    }
+}
@@ -1,2 +1,88 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use crate::dao::memory_dao::MemoryDao;
+use crate::dao::profile_dao::ProfileDao;
+use crate::dao::profile_state_dao::ProfileStateDao;
+use crate::dao::session_dao::SessionDao;
+
 pub mod profile_dao;
-pub mod profile_yaml_dao;
\ No newline at end of file
+pub mod profile_document;
+pub mod profile_yaml_dao;
+pub mod profile_sqlite_dao;
+pub mod session_dao;
+pub mod session_yaml_dao;
+pub mod session_sqlite_dao;
+pub mod memory_dao;
+pub mod memory_yaml_dao;
+pub mod memory_sqlite_dao;
+pub mod profile_state_dao;
+pub mod profile_state_yaml_dao;
+pub mod profile_state_sqlite_dao;
+pub mod sqlite;
+
+/// The DAOs of one storage backend.
+pub struct Store {
+    pub profiles: Arc<dyn ProfileDao>,
+    pub sessions: Arc<dyn SessionDao>,
+    pub memories: Arc<dyn MemoryDao>,
+    pub states: Arc<dyn ProfileStateDao>,
+}
+
+/// Opens the store of a URL like `yaml:./profiles` or `sqlite:./vworld.db`.
+/// A plain path is a YAML store.
+pub async fn open_store(url: &str, strict: bool) -> Result<Store, Box<dyn Error>> {
+    let (scheme, path) = match url.split_once(':') {
+        // A single letter is the drive of a Windows path
+        Some((scheme, path)) if scheme.len() > 1 => (scheme, path),
+        _ => ("yaml", url),
+    };
+    match scheme {
+        "yaml" => Ok(Store {
+            profiles: Arc::new(profile_yaml_dao::new(path.to_string(), strict).await?),
+            sessions: Arc::new(session_yaml_dao::new(Path::new(path).join("sessions")).await?),
+            memories: Arc::new(memory_yaml_dao::new(Path::new(path).join("memories")).await?),
+            states: Arc::new(profile_state_yaml_dao::new(Path::new(path).join("states")).await?),
+        }),
+        "sqlite" => {
+            let db = sqlite::open(path.to_string()).await?;
+            Ok(Store {
+                profiles: Arc::new(profile_sqlite_dao::new(db.clone(), strict)),
+                sessions: Arc::new(session_sqlite_dao::new(db.clone())),
+                memories: Arc::new(memory_sqlite_dao::new(db.clone())),
+                states: Arc::new(profile_state_sqlite_dao::new(db)),
+            })
+        }
+        _ => Err(format!("Unknown store type `{}`, expected `yaml` or `sqlite`", scheme).into()),
+    }
+}
+
+/// Copies all the profiles, fragments, sessions, memories and profile states, overwriting the ones with the same id in the target.
+/// Returns the number of profiles and sessions copied.
+pub async fn copy_store(from: &Store, to: &Store) -> Result<(usize, usize), Box<dyn Error>> {
+    // Copy the documents as they are to keep the comments, unknown fields and inheritance
+    for name in from.profiles.list_fragments().await? {
+        let document = from.profiles.get_fragment(&name).await?
+            .ok_or(format!("Fragment {} disappeared while copying", name))?;
+        to.profiles.save_fragment(&name, &document).await?;
+    }
+    let profiles = from.profiles.list().await?;
+    for profile in profiles.iter() {
+        let document = from.profiles.get_document(&profile.id).await?
+            .ok_or(format!("Profile {} disappeared while copying", profile.id))?;
+        to.profiles.save_document(&profile.id, &document).await?;
+    }
+    let sessions = from.sessions.list().await?;
+    for session in sessions.iter() {
+        to.sessions.save(session).await?;
+    }
+    for id in from.memories.list_profile_ids().await? {
+        let memories = from.memories.get(&id).await?;
+        to.memories.save(&id, &memories).await?;
+    }
+    for id in from.states.list_profile_ids().await? {
+        let state = from.states.get(&id).await?;
+        to.states.save(&id, &state).await?;
+    }
+    Ok((profiles.len(), sessions.len()))
+}
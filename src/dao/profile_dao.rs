@@ -1,7 +1,44 @@
+use std::any::Any;
 use std::error::Error;
+use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedReceiver;
 use crate::model::profile::Profile;
 
-pub trait ProfileDao {
+pub struct ProfileWatch {
+    /// Notified with the name of every changed profile or fragment document.
+    pub changes: UnboundedReceiver<String>,
+    /// Changes are reported as long as the watcher is alive.
+    pub watcher: Box<dyn Any + Send>,
+}
+
+#[async_trait]
+pub trait ProfileDao: Send + Sync {
     async fn create(&self, profile: &Profile) -> Result<bool, Box<dyn Error>>;
-    async fn get(&self, id: &String) -> Result<Option<Profile>, Box<dyn Error>>;
+    /// Creates the profile or overwrites the existing one with the same id.
+    async fn save(&self, profile: &Profile) -> Result<(), Box<dyn Error>>;
+    /// The profile with its inheritance resolved.
+    async fn get(&self, id: &str) -> Result<Option<Profile>, Box<dyn Error>>;
+    /// Every profile with its inheritance resolved. The ones that fail to resolve are skipped with a warning.
+    async fn list(&self) -> Result<Vec<Profile>, Box<dyn Error>>;
+
+    /// The stored YAML document of the profile, comments included.
+    async fn get_document(&self, id: &str) -> Result<Option<String>, Box<dyn Error>>;
+    /// Stores the YAML document of the profile as it is. The caller validates it.
+    async fn save_document(&self, id: &str, document: &str) -> Result<(), Box<dyn Error>>;
+
+    /// The YAML document of a shared fragment profiles can include.
+    async fn get_fragment(&self, name: &str) -> Result<Option<String>, Box<dyn Error>>;
+    async fn save_fragment(&self, name: &str, document: &str) -> Result<(), Box<dyn Error>>;
+    async fn list_fragments(&self) -> Result<Vec<String>, Box<dyn Error>>;
+
+    /// Rewrites every profile and fragment document with an outdated schema, keeping a backup of the original.
+    /// Returns the outdated documents, named like `profile ann` or `fragment pirate`, with the version
+    /// each was migrated from or why it couldn't be. A document that fails to migrate doesn't stop the others.
+    async fn migrate_all(&self) -> Result<Vec<(String, Result<u32, String>)>, Box<dyn Error>>;
+
+    /// Starts watching the stored profiles and fragments for changes made outside of the DAO.
+    /// Returns `None` if the store doesn't support it.
+    fn watch(&self) -> Result<Option<ProfileWatch>, Box<dyn Error>> {
+        Ok(None)
+    }
 }
@@ -0,0 +1,497 @@
+//! Profile documents are the YAML text profiles are stored as, in every backend.
+//! This module upgrades old documents to the current schema, resolves their
+//! inheritance and validates them.
+
+use crate::dao::profile_dao::ProfileDao;
+use crate::model::profile::{Profile, CURRENT_SCHEMA_VERSION, PROFILE_FIELD_GUIDE};
+use futures::future::BoxFuture;
+use serde_yaml::{Mapping, Value};
+use std::error::Error;
+
+const SCHEMA_VERSION_FIELD: &str = "schema_version";
+const EXTENDS_FIELD: &str = "extends";
+const INCLUDE_FIELD: &str = "include";
+const REPLACE_FIELD: &str = "replace";
+const ID_FIELD: &str = "id";
+
+/// Fragments came with schema version 1, so a fragment without a version is at it
+/// and the migrations from before never apply to fragments.
+const FIRST_FRAGMENT_SCHEMA_VERSION: u32 = 1;
+
+/// Upgrades a profile document by one version. Indexed by the version it upgrades from.
+/// Fragments are upgraded by the migrations from their own version on, so a migration must
+/// only touch the fields the document has.
+type Migration = fn(&mut Mapping);
+
+const MIGRATIONS: &[Migration] = &[
+    migrate_v0_to_v1,
+];
+
+/// Documents before versioning had no schema version and required the LLM fields.
+/// Documents inheriting from others are left alone, since empty fields would override the inherited ones.
+fn migrate_v0_to_v1(mapping: &mut Mapping) {
+    if mapping.contains_key(EXTENDS_FIELD) || mapping.contains_key(INCLUDE_FIELD) {
+        return;
+    }
+    for field in ["llm_provider", "llm_model"] {
+        if !mapping.contains_key(field) {
+            mapping.insert(field.into(), "".into());
+        }
+    }
+}
+
+fn schema_version(mapping: &Mapping) -> u32 {
+    mapping.get(SCHEMA_VERSION_FIELD)
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .unwrap_or(0)
+}
+
+fn fragment_schema_version(mapping: &Mapping) -> u32 {
+    mapping.get(SCHEMA_VERSION_FIELD)
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .unwrap_or_default()
+        .max(FIRST_FRAGMENT_SCHEMA_VERSION)
+}
+
+/// Upgrades the document in memory from the given version to the current schema version.
+fn migrate(mapping: &mut Mapping, version: u32) -> Result<(), Box<dyn Error>> {
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(format!("profile schema version {} is newer than the supported version {}",
+                           version, CURRENT_SCHEMA_VERSION).into());
+    }
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(mapping);
+    }
+    // Keep the version as the first field of the document
+    mapping.shift_remove(SCHEMA_VERSION_FIELD);
+    let mut versioned = Mapping::new();
+    versioned.insert(SCHEMA_VERSION_FIELD.into(), CURRENT_SCHEMA_VERSION.into());
+    versioned.extend(std::mem::take(mapping));
+    *mapping = versioned;
+    Ok(())
+}
+
+fn unknown_fields(mapping: &Mapping) -> Vec<String> {
+    mapping.keys()
+        .map(|key| key.as_str().unwrap_or_default())
+        .filter(|key| !PROFILE_FIELD_GUIDE.iter().any(|(field, _)| field == key))
+        .map(|key| format!("unknown field `{}`", key))
+        .collect()
+}
+
+fn check_strict(mapping: &Mapping, strict: bool, name: &str) -> Result<(), Box<dyn Error>> {
+    if strict {
+        let unknown = unknown_fields(mapping);
+        if !unknown.is_empty() {
+            return Err(format!("profile {}: {}", name, unknown.join(", ")).into());
+        }
+    }
+    Ok(())
+}
+
+fn parse_mapping(document: &str, name: &str) -> Result<Value, Box<dyn Error>> {
+    let value: Value = serde_yaml::from_str(document)?;
+    if !value.is_mapping() {
+        return Err(format!("profile {} is not a YAML mapping", name).into());
+    }
+    Ok(value)
+}
+
+/// A document taking part in the resolution of a profile.
+#[derive(PartialEq)]
+enum DocumentRef {
+    Profile(String),
+    Fragment(String),
+}
+
+impl std::fmt::Display for DocumentRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DocumentRef::Profile(id) => write!(f, "profile {}", id),
+            DocumentRef::Fragment(name) => write!(f, "fragment {}", name),
+        }
+    }
+}
+
+/// Loads the profile of a document of any schema version, resolving the profile it
+/// extends and the fragments it includes from the DAO.
+///
+/// The extended profile is merged first, then each included fragment, then the document itself.
+/// Strings and other scalars of later documents override earlier ones, mappings are merged
+/// field by field and lists are appended, unless the later document names the field in `replace`.
+///
+/// The id is never inherited: a document without one gets the requested id.
+///
+/// With `strict`, documents with fields unknown to the current schema are rejected.
+pub(crate) async fn resolve_document(dao: &dyn ProfileDao, id: &str, document: &str, strict: bool) -> Result<Profile, Box<dyn Error>> {
+    let mut stack = vec![DocumentRef::Profile(id.to_string())];
+    let mut mapping = resolve_mapping(dao, document, strict, &mut stack).await?;
+    if !mapping.contains_key(ID_FIELD) {
+        mapping.insert(ID_FIELD.into(), id.into());
+    }
+    Ok(serde_yaml::from_value(Value::Mapping(mapping))?)
+}
+
+fn resolve_mapping<'a>(dao: &'a dyn ProfileDao, document: &'a str, strict: bool, stack: &'a mut Vec<DocumentRef>) -> BoxFuture<'a, Result<Mapping, Box<dyn Error>>> {
+    Box::pin(async move {
+        let name = stack.last().expect("stack is never empty").to_string();
+        let mut value = parse_mapping(document, &name)?;
+        let mapping = value.as_mapping_mut().expect("checked to be a mapping");
+        match stack.last() {
+            Some(DocumentRef::Profile(_)) => migrate(mapping, schema_version(mapping))?,
+            _ => {
+                // Fragments are shared, so they are only upgraded by migrate-profiles
+                let version = fragment_schema_version(mapping);
+                if version != CURRENT_SCHEMA_VERSION {
+                    return Err(format!("{} has schema version {} instead of {}, run migrate-profiles to upgrade it",
+                                       name, version, CURRENT_SCHEMA_VERSION).into());
+                }
+                mapping.shift_remove(SCHEMA_VERSION_FIELD);
+            }
+        }
+        check_strict(mapping, strict, &name)?;
+
+        let extends = mapping.shift_remove(EXTENDS_FIELD)
+            .map(serde_yaml::from_value::<Option<String>>).transpose()?.flatten();
+        let include = mapping.shift_remove(INCLUDE_FIELD)
+            .map(serde_yaml::from_value::<Vec<String>>).transpose()?.unwrap_or_default();
+        let replace = mapping.shift_remove(REPLACE_FIELD)
+            .map(serde_yaml::from_value::<Vec<String>>).transpose()?.unwrap_or_default();
+
+        let mut parents = Vec::new();
+        parents.extend(extends.map(DocumentRef::Profile));
+        parents.extend(include.into_iter().map(DocumentRef::Fragment));
+
+        let mut resolved = Mapping::new();
+        for parent in parents {
+            if stack.contains(&parent) {
+                let cycle: Vec<String> = stack.iter().chain([&parent]).map(|r| r.to_string()).collect();
+                return Err(format!("inheritance cycle: {}", cycle.join(" -> ")).into());
+            }
+            let parent_document = match &parent {
+                DocumentRef::Profile(id) => dao.get_document(id).await?,
+                DocumentRef::Fragment(name) => dao.get_fragment(name).await?,
+            }.ok_or(format!("{} used by {} not found", parent, name))?;
+            stack.push(parent);
+            let mut parent_mapping = resolve_mapping(dao, &parent_document, strict, stack).await?;
+            stack.pop();
+            parent_mapping.shift_remove(ID_FIELD);
+            merge(&mut resolved, parent_mapping, &[]);
+        }
+        let mapping = std::mem::take(mapping);
+        merge(&mut resolved, mapping, &replace);
+        Ok(resolved)
+    })
+}
+
+/// Merges the overlay into the base: lists are appended to, unless their path is in `replace`,
+/// mappings are merged field by field and other values are overwritten.
+/// Paths of nested lists are dotted, e.g. `sheet.inventory`.
+fn merge(base: &mut Mapping, overlay: Mapping, replace: &[String]) {
+    for (key, value) in overlay {
+        let name = key.as_str().unwrap_or_default();
+        let replaced = replace.iter().any(|r| r == name);
+        match (base.get_mut(&key), value) {
+            (Some(Value::Sequence(items)), Value::Sequence(more)) if !replaced => items.extend(more),
+            (Some(Value::Mapping(fields)), Value::Mapping(more)) => {
+                let nested: Vec<String> = replace.iter()
+                    .filter_map(|r| r.strip_prefix(name)?.strip_prefix('.'))
+                    .map(str::to_string)
+                    .collect();
+                merge(fields, more, &nested)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Upgrades a document to the current schema version.
+///
+/// Returns `None` if it's already up to date, otherwise the original version and the
+/// upgraded document. Fields unknown to the schema are kept as they are, in the same order,
+/// but the document is written again with the field guide as comments, losing its own comments.
+/// The document may rely on the profiles it extends, see [`upgrade_document`] to check it resolves.
+pub(crate) fn migrate_document(document: &str, strict: bool, name: &str) -> Result<Option<(u32, String)>, Box<dyn Error>> {
+    migrate_with_version(document, strict, name, schema_version)
+}
+
+/// Upgrades a fragment to the current schema version like [`migrate_document`].
+pub(crate) fn migrate_fragment(document: &str, strict: bool, name: &str) -> Result<Option<(u32, String)>, Box<dyn Error>> {
+    migrate_with_version(document, strict, name, fragment_schema_version)
+}
+
+fn migrate_with_version(document: &str, strict: bool, name: &str, version_of: fn(&Mapping) -> u32) -> Result<Option<(u32, String)>, Box<dyn Error>> {
+    let mut value = parse_mapping(document, name)?;
+    let mapping = value.as_mapping_mut().expect("checked to be a mapping");
+    let version = version_of(mapping);
+    if version == CURRENT_SCHEMA_VERSION {
+        return Ok(None);
+    }
+    migrate(mapping, version)?;
+    check_strict(mapping, strict, name)?;
+    Ok(Some((version, commented_yaml(mapping)?)))
+}
+
+/// Upgrades the document of a profile like [`migrate_document`], and makes sure it still
+/// resolves to a valid profile with the profiles it extends and the fragments it includes.
+pub(crate) async fn upgrade_document(dao: &dyn ProfileDao, id: &str, document: &str, strict: bool) -> Result<Option<(u32, String)>, Box<dyn Error>> {
+    let Some((version, upgraded)) = migrate_document(document, strict, id)? else { return Ok(None) };
+    resolve_document(dao, id, &upgraded, strict).await?;
+    Ok(Some((version, upgraded)))
+}
+
+/// Sets fields of a profile document as they are, without resolving it,
+/// so it keeps the profile it extends and the fragments it includes.
+pub(crate) fn set_document_fields(document: &str, name: &str, fields: Vec<(&str, Value)>) -> Result<String, Box<dyn Error>> {
+    let mut value = parse_mapping(document, name)?;
+    let mapping = value.as_mapping_mut().expect("checked to be a mapping");
+    for (field, value) in fields {
+        mapping.insert(field.into(), value);
+    }
+    commented_yaml(mapping)
+}
+
+/// Serializes the profile with the field guidance as comments above each field.
+pub(crate) fn to_commented_yaml(profile: &Profile) -> Result<String, Box<dyn Error>> {
+    let value = serde_yaml::to_value(profile)?;
+    commented_yaml(value.as_mapping().ok_or("profile is not serialized as a mapping")?)
+}
+
+fn commented_yaml(mapping: &Mapping) -> Result<String, Box<dyn Error>> {
+    let mut result = String::new();
+    for (key, value) in mapping {
+        let key_str = key.as_str().unwrap_or_default();
+        if let Some((_, guide)) = PROFILE_FIELD_GUIDE.iter().find(|(field, _)| *field == key_str) {
+            for line in guide.lines() {
+                result.push_str(&format!("# {}\n", line.trim()));
+            }
+        }
+        let mut field = Mapping::new();
+        field.insert(key.clone(), value.clone());
+        result.push_str(&serde_yaml::to_string(&field)?);
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Validates a profile document and the profile it resolves to, collecting every problem found
+/// instead of silently ignoring unknown fields like a plain deserialization would.
+pub(crate) async fn validate_document(dao: &dyn ProfileDao, id: &str, document: &str) -> Result<Profile, Vec<String>> {
+    let mut errors = {
+        let value: Value = serde_yaml::from_str(document).map_err(|e| vec![e.to_string()])?;
+        let mapping = value.as_mapping().ok_or_else(|| vec!["profile must be a YAML mapping".to_string()])?;
+        unknown_fields(mapping)
+    };
+    let resolved = resolve_document(dao, id, document, false).await.map_err(|e| e.to_string());
+    match resolved {
+        Ok(profile) => {
+            errors.extend(profile.validate(id));
+            if errors.is_empty() { Ok(profile) } else { Err(errors) }
+        }
+        Err(e) => {
+            errors.push(e);
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+
+    /// Serves the documents given to it, for the parts of the DAO used to resolve profiles.
+    #[derive(Default)]
+    struct MemoryDao {
+        profiles: HashMap<String, String>,
+        fragments: HashMap<String, String>,
+    }
+
+    impl MemoryDao {
+        fn with_profile(mut self, id: &str, document: &str) -> Self {
+            self.profiles.insert(id.to_string(), document.to_string());
+            self
+        }
+
+        fn with_fragment(mut self, name: &str, document: &str) -> Self {
+            self.fragments.insert(name.to_string(), document.to_string());
+            self
+        }
+    }
+
+    /// Error of the parts of the DAO that resolving profiles doesn't use.
+    fn not_supported<T>() -> Result<T, Box<dyn Error>> {
+        Err("not supported by MemoryDao".into())
+    }
+
+    #[async_trait]
+    impl ProfileDao for MemoryDao {
+        async fn create(&self, _profile: &Profile) -> Result<bool, Box<dyn Error>> { not_supported() }
+        async fn save(&self, _profile: &Profile) -> Result<(), Box<dyn Error>> { not_supported() }
+        async fn get(&self, _id: &str) -> Result<Option<Profile>, Box<dyn Error>> { not_supported() }
+        async fn list(&self) -> Result<Vec<Profile>, Box<dyn Error>> { not_supported() }
+        async fn get_document(&self, id: &str) -> Result<Option<String>, Box<dyn Error>> {
+            Ok(self.profiles.get(id).cloned())
+        }
+        async fn save_document(&self, _id: &str, _document: &str) -> Result<(), Box<dyn Error>> { not_supported() }
+        async fn get_fragment(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+            Ok(self.fragments.get(name).cloned())
+        }
+        async fn save_fragment(&self, _name: &str, _document: &str) -> Result<(), Box<dyn Error>> { not_supported() }
+        async fn list_fragments(&self) -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(self.fragments.keys().cloned().collect())
+        }
+        async fn migrate_all(&self) -> Result<Vec<(String, Result<u32, String>)>, Box<dyn Error>> { not_supported() }
+    }
+
+    const V0_DOCUMENT: &str = "id: ann\nname: Ann\nbackground: A sailor\nconversation_examples:\n- Ahoy\n";
+
+    fn mapping(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn migrate_document_upgrades_v0() {
+        let (version, upgraded) = migrate_document(V0_DOCUMENT, true, "ann").unwrap().unwrap();
+        assert_eq!(version, 0);
+        let upgraded = mapping(&upgraded);
+        assert_eq!(upgraded.keys().next(), Some(&Value::from(SCHEMA_VERSION_FIELD)));
+        assert_eq!(schema_version(&upgraded), CURRENT_SCHEMA_VERSION);
+        assert_eq!(upgraded.get("llm_provider"), Some(&Value::from("")));
+        assert_eq!(upgraded.get("llm_model"), Some(&Value::from("")));
+        assert_eq!(upgraded.get("name"), Some(&Value::from("Ann")));
+    }
+
+    #[test]
+    fn migrate_document_keeps_current_and_rejects_newer() {
+        let (_, upgraded) = migrate_document(V0_DOCUMENT, true, "ann").unwrap().unwrap();
+        assert!(migrate_document(&upgraded, true, "ann").unwrap().is_none());
+
+        let newer = format!("schema_version: {}\n{}", CURRENT_SCHEMA_VERSION + 1, V0_DOCUMENT);
+        let error = migrate_document(&newer, false, "ann").unwrap_err().to_string();
+        assert!(error.contains("newer than the supported version"), "{}", error);
+    }
+
+    #[test]
+    fn migrate_document_checks_unknown_fields_when_strict() {
+        let document = format!("{}nickname: Annie\n", V0_DOCUMENT);
+        let error = migrate_document(&document, true, "ann").unwrap_err().to_string();
+        assert!(error.contains("unknown field `nickname`"), "{}", error);
+
+        let (_, upgraded) = migrate_document(&document, false, "ann").unwrap().unwrap();
+        assert_eq!(mapping(&upgraded).get("nickname"), Some(&Value::from("Annie")));
+    }
+
+    #[test]
+    fn merge_appends_lists_unless_replaced() {
+        let mut base = mapping("examples: [a, b]\ntags: [x]\nname: Base\n");
+        merge(&mut base, mapping("examples: [c]\ntags: [y]\nname: Child\n"), &["tags".to_string()]);
+        assert_eq!(base, mapping("examples: [a, b, c]\ntags: [y]\nname: Child\n"));
+    }
+
+    #[test]
+    fn merge_merges_mappings_field_by_field() {
+        let mut base = mapping("sheet: {hp: 10, skills: [swim], inventory: [rope]}\n");
+        merge(&mut base, mapping("sheet: {max_hp: 12, skills: [climb], inventory: [torch]}\n"), &["sheet.inventory".to_string()]);
+        assert_eq!(base, mapping("sheet: {hp: 10, skills: [swim, climb], inventory: [torch], max_hp: 12}\n"));
+    }
+
+    #[tokio::test]
+    async fn resolve_document_merges_extends_then_includes_then_itself() {
+        let dao = MemoryDao::default()
+            .with_profile("base", "id: base\nname: Base\nbackground: Base\nconversation_examples: [from base]\n")
+            .with_fragment("first", "background: First\nconversation_examples: [from first]\n")
+            .with_fragment("second", "background: Second\nconversation_examples: [from second]\n");
+        let document = "extends: base\ninclude: [first, second]\nconversation_examples: [from child]\n";
+        let profile = resolve_document(&dao, "child", document, true).await.unwrap();
+        assert_eq!(profile.background, "Second");
+        assert_eq!(profile.conversation_examples, ["from base", "from first", "from second", "from child"]);
+    }
+
+    #[tokio::test]
+    async fn resolve_document_replaces_listed_fields() {
+        let dao = MemoryDao::default()
+            .with_profile("base", "id: base\nname: Base\nbackground: Base\nconversation_examples: [from base]\n");
+        let document = "extends: base\nreplace: [conversation_examples]\nconversation_examples: [from child]\n";
+        let profile = resolve_document(&dao, "child", document, true).await.unwrap();
+        assert_eq!(profile.conversation_examples, ["from child"]);
+    }
+
+    #[tokio::test]
+    async fn resolve_document_never_inherits_the_id() {
+        let dao = MemoryDao::default()
+            .with_profile("base", "id: base\nname: Base\nbackground: Base\nconversation_examples: []\n");
+        let profile = resolve_document(&dao, "child", "extends: base\n", true).await.unwrap();
+        assert_eq!(profile.id, "child");
+        assert_eq!(profile.name, "Base");
+        assert_eq!(profile.schema_version, CURRENT_SCHEMA_VERSION);
+    }
+
+    #[tokio::test]
+    async fn resolve_document_inherits_the_llm_fields_of_unversioned_children() {
+        let dao = MemoryDao::default()
+            .with_profile("base", "id: base\nname: Base\nbackground: Base\nconversation_examples: []\nllm_provider: openai\nllm_model: gpt-4o\n")
+            .with_fragment("shared", "background: Shared\n");
+        let profile = resolve_document(&dao, "child", "extends: base\nname: Child\n", true).await.unwrap();
+        assert_eq!(profile.llm_provider, "openai");
+        assert_eq!(profile.llm_model, "gpt-4o");
+
+        let (_, upgraded) = migrate_document("extends: base\nname: Child\n", true, "child").unwrap().unwrap();
+        assert!(!mapping(&upgraded).contains_key("llm_provider"));
+        let profile = resolve_document(&dao, "child", &upgraded, true).await.unwrap();
+        assert_eq!(profile.llm_model, "gpt-4o");
+
+        let profile = resolve_document(&dao, "child", "id: child\nname: Child\nconversation_examples: []\ninclude: [shared]\n", true).await.unwrap();
+        assert_eq!(profile.llm_provider, "");
+    }
+
+    #[tokio::test]
+    async fn resolve_document_detects_cycles() {
+        let dao = MemoryDao::default()
+            .with_profile("a", "id: a\nextends: b\n")
+            .with_profile("b", "id: b\ninclude: [shared]\n")
+            .with_fragment("shared", "include: [shared]\n");
+        let error = resolve_document(&dao, "a", "id: a\nextends: b\n", false).await.unwrap_err().to_string();
+        assert_eq!(error, "inheritance cycle: profile a -> profile b -> fragment shared -> fragment shared");
+
+        let dao = MemoryDao::default().with_profile("b", "id: b\nextends: a\n");
+        let error = resolve_document(&dao, "a", "id: a\nextends: b\n", false).await.unwrap_err().to_string();
+        assert_eq!(error, "inheritance cycle: profile a -> profile b -> profile a");
+    }
+
+    #[tokio::test]
+    async fn resolve_document_reports_missing_parents() {
+        let dao = MemoryDao::default();
+        let error = resolve_document(&dao, "a", "id: a\ninclude: [missing]\n", false).await.unwrap_err().to_string();
+        assert_eq!(error, "fragment missing used by profile a not found");
+    }
+
+    #[tokio::test]
+    async fn resolve_document_rejects_outdated_fragments() {
+        let dao = MemoryDao::default()
+            .with_fragment("current", "background: Current\n")
+            .with_fragment("newer", &format!("schema_version: {}\nbackground: Newer\n", CURRENT_SCHEMA_VERSION + 1));
+        let profile = resolve_document(&dao, "a", "id: a\nname: A\nconversation_examples: []\ninclude: [current]\n", true).await.unwrap();
+        assert_eq!(profile.background, "Current");
+
+        let error = resolve_document(&dao, "a", "id: a\ninclude: [newer]\n", true).await.unwrap_err().to_string();
+        assert_eq!(error, format!("fragment newer has schema version {} instead of {}, run migrate-profiles to upgrade it",
+                                  CURRENT_SCHEMA_VERSION + 1, CURRENT_SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn migrate_fragment_skips_the_migrations_before_fragments() {
+        assert!(migrate_fragment("background: Current\n", true, "current").unwrap().is_none());
+        // The upgrade to version 1 adds profile fields, which would override the ones of the profile
+        assert!(migrate_fragment("schema_version: 0\nbackground: Old\n", true, "old").unwrap().is_none());
+
+        let newer = format!("schema_version: {}\nbackground: Newer\n", CURRENT_SCHEMA_VERSION + 1);
+        let error = migrate_fragment(&newer, true, "newer").unwrap_err().to_string();
+        assert!(error.contains("newer than the supported version"), "{}", error);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::dao::profile_dao::ProfileDao;
+use crate::dao::profile_document::{migrate_fragment, resolve_document, to_commented_yaml, upgrade_document};
+use crate::dao::sqlite::SqliteDb;
+use crate::model::profile::{Profile, CURRENT_SCHEMA_VERSION};
+use async_trait::async_trait;
+use log::{info, warn};
+use rusqlite::{params, OptionalExtension};
+use std::error::Error;
+
+/// Stores the profile documents in the `profiles` table and the fragments in `profile_fragments`.
+pub struct ProfileSqliteDao {
+    db: SqliteDb,
+    /// Reject documents with fields that are unknown to the current schema.
+    strict: bool,
+}
+
+pub(crate) fn new(db: SqliteDb, strict: bool) -> ProfileSqliteDao {
+    ProfileSqliteDao { db, strict }
+}
+
+impl ProfileSqliteDao {
+    /// Upgrades the outdated fragments, keeping the originals in the `profile_fragment_backups` table.
+    async fn migrate_fragments(&self) -> Result<Vec<(String, Result<u32, String>)>, Box<dyn Error>> {
+        let rows = self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT name, document FROM profile_fragments ORDER BY name")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        }).await?;
+        let mut results = Vec::new();
+        let mut upgrades = Vec::new();
+        for (name, document) in rows {
+            match migrate_fragment(&document, self.strict, &name) {
+                Ok(Some((version, upgraded))) => {
+                    results.push((format!("fragment {}", name), Ok(version)));
+                    upgrades.push((name, version, document, upgraded));
+                }
+                Ok(None) => {}
+                Err(e) => results.push((format!("fragment {}", name), Err(e.to_string()))),
+            }
+        }
+        let created_at = chrono::Local::now().to_rfc3339();
+        self.db.call(move |conn| {
+            let tx = conn.transaction()?;
+            for (name, version, original, upgraded) in upgrades {
+                tx.execute("INSERT INTO profile_fragment_backups (name, schema_version, document, created_at) VALUES (?1, ?2, ?3, ?4)",
+                           params![name, version, original, created_at])?;
+                tx.execute("UPDATE profile_fragments SET document = ?2 WHERE name = ?1", params![name, upgraded])?;
+                info!("Migrated fragment {} from schema version {} to {}", name, version, CURRENT_SCHEMA_VERSION);
+            }
+            tx.commit()
+        }).await?;
+        Ok(results)
+    }
+}
+
+#[async_trait]
+impl ProfileDao for ProfileSqliteDao {
+    async fn create(&self, profile: &Profile) -> Result<bool, Box<dyn Error>> {
+        let id = profile.id.clone();
+        let document = to_commented_yaml(profile)?;
+        let inserted = self.db.call(move |conn| {
+            conn.execute("INSERT OR IGNORE INTO profiles (id, document) VALUES (?1, ?2)", params![id, document])
+        }).await?;
+        Ok(inserted > 0)
+    }
+
+    async fn save(&self, profile: &Profile) -> Result<(), Box<dyn Error>> {
+        let document = to_commented_yaml(profile)?;
+        self.save_document(&profile.id, &document).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Profile>, Box<dyn Error>> {
+        let document = self.get_document(id).await?;
+        match document {
+            Some(document) => Ok(Some(resolve_document(self, id, &document, self.strict).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<Profile>, Box<dyn Error>> {
+        let rows = self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT id, document FROM profiles ORDER BY id")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        }).await?;
+        let mut profiles = Vec::new();
+        for (id, document) in rows {
+            match resolve_document(self, &id, &document, self.strict).await {
+                Ok(profile) => profiles.push(profile),
+                Err(e) => warn!("Skipping profile {} that can't be loaded: {}", id, e),
+            }
+        }
+        Ok(profiles)
+    }
+
+    async fn get_document(&self, id: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let id = id.to_string();
+        self.db.call(move |conn| {
+            conn.query_row("SELECT document FROM profiles WHERE id = ?1", params![id], |row| row.get(0)).optional()
+        }).await
+    }
+
+    async fn save_document(&self, id: &str, document: &str) -> Result<(), Box<dyn Error>> {
+        let id = id.to_string();
+        let document = document.to_string();
+        self.db.call(move |conn| {
+            conn.execute("INSERT OR REPLACE INTO profiles (id, document) VALUES (?1, ?2)", params![id, document])
+        }).await?;
+        Ok(())
+    }
+
+    async fn get_fragment(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let name = name.to_string();
+        self.db.call(move |conn| {
+            conn.query_row("SELECT document FROM profile_fragments WHERE name = ?1", params![name], |row| row.get(0)).optional()
+        }).await
+    }
+
+    async fn save_fragment(&self, name: &str, document: &str) -> Result<(), Box<dyn Error>> {
+        let name = name.to_string();
+        let document = document.to_string();
+        self.db.call(move |conn| {
+            conn.execute("INSERT OR REPLACE INTO profile_fragments (name, document) VALUES (?1, ?2)", params![name, document])
+        }).await?;
+        Ok(())
+    }
+
+    async fn list_fragments(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT name FROM profile_fragments ORDER BY name")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<Vec<String>>>()
+        }).await
+    }
+
+    /// The original documents are kept in the `profile_backups` table.
+    async fn migrate_all(&self) -> Result<Vec<(String, Result<u32, String>)>, Box<dyn Error>> {
+        // Fragments first, so the profiles including them resolve once upgraded
+        let mut results = self.migrate_fragments().await?;
+        let rows = self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT id, document FROM profiles ORDER BY id")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        }).await?;
+        let mut upgrades = Vec::new();
+        for (id, document) in rows {
+            match upgrade_document(self, &id, &document, self.strict).await {
+                Ok(Some((version, upgraded))) => {
+                    results.push((format!("profile {}", id), Ok(version)));
+                    upgrades.push((id, version, document, upgraded));
+                }
+                Ok(None) => {}
+                Err(e) => results.push((format!("profile {}", id), Err(e.to_string()))),
+            }
+        }
+        let created_at = chrono::Local::now().to_rfc3339();
+        self.db.call(move |conn| {
+            let tx = conn.transaction()?;
+            for (id, version, original, upgraded) in upgrades {
+                tx.execute("INSERT INTO profile_backups (id, schema_version, document, created_at) VALUES (?1, ?2, ?3, ?4)",
+                           params![id, version, original, created_at])?;
+                tx.execute("UPDATE profiles SET document = ?2 WHERE id = ?1", params![id, upgraded])?;
+                info!("Migrated profile {} from schema version {} to {}", id, version, CURRENT_SCHEMA_VERSION);
+            }
+            tx.commit()
+        }).await?;
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dao::sqlite;
+
+    async fn dao() -> ProfileSqliteDao {
+        new(sqlite::open(":memory:".to_string()).await.unwrap(), true)
+    }
+
+    fn profile(id: &str, name: &str) -> Profile {
+        Profile {
+            id: id.to_string(),
+            name: name.to_string(),
+            background: format!("{} is a sailor", name),
+            conversation_examples: vec!["Ahoy".to_string()],
+            ..Profile::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn create_get_and_list_profiles() {
+        let dao = dao().await;
+        assert!(dao.create(&profile("bob", "Bob")).await.unwrap());
+        assert!(dao.create(&profile("ann", "Ann")).await.unwrap());
+        // An existing profile is left as it is
+        assert!(!dao.create(&profile("ann", "Other Ann")).await.unwrap());
+
+        let ann = dao.get("ann").await.unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&ann).unwrap(), serde_json::to_value(profile("ann", "Ann")).unwrap());
+        assert!(dao.get("missing").await.unwrap().is_none());
+        let ids: Vec<String> = dao.list().await.unwrap().into_iter().map(|p| p.id).collect();
+        assert_eq!(ids, ["ann", "bob"]);
+
+        dao.save(&profile("ann", "Annie")).await.unwrap();
+        assert_eq!(dao.get("ann").await.unwrap().unwrap().name, "Annie");
+
+        // A broken profile doesn't hide the others
+        dao.save_document("cid", "id: cid\nname: Cid\nextends: missing\n").await.unwrap();
+        let ids: Vec<String> = dao.list().await.unwrap().into_iter().map(|p| p.id).collect();
+        assert_eq!(ids, ["ann", "bob"]);
+    }
+
+    #[tokio::test]
+    async fn documents_and_fragments_are_stored_as_they_are() {
+        let dao = dao().await;
+        dao.save_fragment("sailor", "# Shared by the crew\nbackground: A sailor\n").await.unwrap();
+        let document = "# The captain\nid: cap\nname: Cap\ninclude: [sailor]\nconversation_examples: []\n";
+        dao.save_document("cap", document).await.unwrap();
+
+        assert_eq!(dao.get_document("cap").await.unwrap().as_deref(), Some(document));
+        assert_eq!(dao.get_fragment("sailor").await.unwrap().as_deref(), Some("# Shared by the crew\nbackground: A sailor\n"));
+        assert!(dao.get_fragment("missing").await.unwrap().is_none());
+        assert_eq!(dao.list_fragments().await.unwrap(), ["sailor"]);
+        let cap = dao.get("cap").await.unwrap().unwrap();
+        assert_eq!(cap.background, "A sailor");
+    }
+
+    #[tokio::test]
+    async fn migrate_all_upgrades_outdated_documents_and_keeps_backups() {
+        let dao = dao().await;
+        let old_profile = "id: ann\nname: Ann\nbackground: A sailor\nconversation_examples: []\n";
+        // Fragments came with the first schema version, so this one is up to date
+        let fragment = "schema_version: 0\nbackground: Old\n";
+        dao.save_document("ann", old_profile).await.unwrap();
+        dao.save(&profile("bob", "Bob")).await.unwrap();
+        dao.save_fragment("old", fragment).await.unwrap();
+
+        let results = dao.migrate_all().await.unwrap();
+        assert_eq!(results, [("profile ann".to_string(), Ok(0))]);
+        assert!(dao.migrate_all().await.unwrap().is_empty());
+
+        let ann = dao.get_document("ann").await.unwrap().unwrap();
+        assert!(ann.contains(&format!("schema_version: {}", CURRENT_SCHEMA_VERSION)), "{}", ann);
+        assert_eq!(dao.get_fragment("old").await.unwrap().as_deref(), Some(fragment));
+        let backups: Vec<(String, u32, String)> = dao.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT id, schema_version, document FROM profile_backups")?;
+            stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?.collect()
+        }).await.unwrap();
+        assert_eq!(backups, [("ann".to_string(), 0, old_profile.to_string())]);
+    }
+}
@@ -0,0 +1,12 @@
+use std::error::Error;
+use async_trait::async_trait;
+use crate::model::profile_state::ProfileState;
+
+#[async_trait]
+pub trait ProfileStateDao: Send + Sync {
+    /// The state of the profile. Empty if it never changed.
+    async fn get(&self, profile_id: &str) -> Result<ProfileState, Box<dyn Error>>;
+    async fn save(&self, profile_id: &str, state: &ProfileState) -> Result<(), Box<dyn Error>>;
+    /// Ids of the profiles with a saved state.
+    async fn list_profile_ids(&self) -> Result<Vec<String>, Box<dyn Error>>;
+}
@@ -0,0 +1,47 @@
+use crate::dao::profile_state_dao::ProfileStateDao;
+use crate::dao::sqlite::SqliteDb;
+use crate::model::profile_state::ProfileState;
+use async_trait::async_trait;
+use rusqlite::{params, OptionalExtension};
+use std::error::Error;
+
+/// Stores the states in the `profile_states` table as YAML documents.
+pub struct ProfileStateSqliteDao {
+    db: SqliteDb,
+}
+
+pub(crate) fn new(db: SqliteDb) -> ProfileStateSqliteDao {
+    ProfileStateSqliteDao { db }
+}
+
+#[async_trait]
+impl ProfileStateDao for ProfileStateSqliteDao {
+    async fn get(&self, profile_id: &str) -> Result<ProfileState, Box<dyn Error>> {
+        let profile_id = profile_id.to_string();
+        let document: Option<String> = self.db.call(move |conn| {
+            conn.query_row("SELECT document FROM profile_states WHERE profile_id = ?1", params![profile_id], |r| r.get(0))
+                .optional()
+        }).await?;
+        match document {
+            Some(document) => Ok(serde_yaml::from_str(&document)?),
+            None => Ok(ProfileState::default()),
+        }
+    }
+
+    async fn save(&self, profile_id: &str, state: &ProfileState) -> Result<(), Box<dyn Error>> {
+        let profile_id = profile_id.to_string();
+        let document = serde_yaml::to_string(state)?;
+        self.db.call(move |conn| {
+            conn.execute("INSERT OR REPLACE INTO profile_states (profile_id, document) VALUES (?1, ?2)",
+                         params![profile_id, document])
+        }).await?;
+        Ok(())
+    }
+
+    async fn list_profile_ids(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT profile_id FROM profile_states ORDER BY profile_id")?;
+            stmt.query_map([], |r| r.get(0))?.collect()
+        }).await
+    }
+}
@@ -0,0 +1,54 @@
+use crate::dao::profile_state_dao::ProfileStateDao;
+use crate::model::profile_state::ProfileState;
+use async_trait::async_trait;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, create_dir_all, try_exists};
+
+/// Stores the state of each profile as a YAML file in a directory.
+pub struct ProfileStateYamlDao {
+    db_path: PathBuf,
+}
+
+pub(crate) async fn new(db_path: PathBuf) -> Result<ProfileStateYamlDao, Box<dyn Error>> {
+    if !try_exists(&db_path).await? {
+        create_dir_all(&db_path).await?;
+    }
+    Ok(ProfileStateYamlDao { db_path })
+}
+
+impl ProfileStateYamlDao {
+    fn state_path(&self, profile_id: &str) -> PathBuf {
+        Path::new(&self.db_path).join(profile_id).with_extension("yaml")
+    }
+}
+
+#[async_trait]
+impl ProfileStateDao for ProfileStateYamlDao {
+    async fn get(&self, profile_id: &str) -> Result<ProfileState, Box<dyn Error>> {
+        let path = self.state_path(profile_id);
+        if !try_exists(&path).await? {
+            return Ok(ProfileState::default());
+        }
+        Ok(serde_yaml::from_str(&fs::read_to_string(path).await?)?)
+    }
+
+    async fn save(&self, profile_id: &str, state: &ProfileState) -> Result<(), Box<dyn Error>> {
+        fs::write(self.state_path(profile_id), serde_yaml::to_string(state)?).await?;
+        Ok(())
+    }
+
+    async fn list_profile_ids(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut ids = Vec::new();
+        let mut entries = fs::read_dir(&self.db_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "yaml")
+                && let Some(stem) = path.file_stem() {
+                ids.push(stem.to_string_lossy().to_string());
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+}
@@ -1,45 +1,194 @@
-use crate::dao::profile_dao::ProfileDao;
-use crate::model::profile::Profile;
+use crate::dao::profile_dao::{ProfileDao, ProfileWatch};
+use crate::dao::profile_document::{migrate_fragment, resolve_document, to_commented_yaml, upgrade_document};
+use crate::model::profile::{Profile, CURRENT_SCHEMA_VERSION};
+use async_trait::async_trait;
+use log::{info, warn};
+use notify::{RecursiveMode, Watcher};
 use std::error::Error;
-use std::path::Path;
-use tokio::fs::{create_dir, try_exists, File};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, create_dir_all, try_exists};
+use tokio::sync::mpsc;
+
+const FRAGMENTS_DIR: &str = "fragments";
 
 pub struct ProfileYamlDao {
     db_path: String,
+    /// Reject documents with fields that are unknown to the current schema.
+    strict: bool,
 }
 
-pub(crate) async fn new(db_path: String) -> Result<ProfileYamlDao, Box<dyn Error>> {
-    let path = Path::new(&db_path);
-    if !try_exists(&path).await? {
-        create_dir(&path).await?;
+pub(crate) async fn new(db_path: String, strict: bool) -> Result<ProfileYamlDao, Box<dyn Error>> {
+    let fragments_path = Path::new(&db_path).join(FRAGMENTS_DIR);
+    if !try_exists(&fragments_path).await? {
+        create_dir_all(&fragments_path).await?;
     }
-    Ok(ProfileYamlDao { db_path })
+    Ok(ProfileYamlDao { db_path, strict })
 }
 
+impl ProfileYamlDao {
+    pub fn profile_path(&self, id: &str) -> PathBuf {
+        Path::new(&self.db_path).join(id).with_extension("yaml")
+    }
+
+    fn fragment_path(&self, name: &str) -> PathBuf {
+        Path::new(&self.db_path).join(FRAGMENTS_DIR).join(name).with_extension("yaml")
+    }
+
+    async fn list_yaml_files(dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut names = Vec::new();
+        let mut entries = fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "yaml") {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    async fn list_ids(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Self::list_yaml_files(Path::new(&self.db_path)).await
+    }
+
+    /// Migrates the file of the profile if it's outdated. Returns the version it was migrated from.
+    async fn migrate_file(&self, id: &str) -> Result<Option<u32>, Box<dyn Error>> {
+        let path = self.profile_path(id);
+        let document = fs::read_to_string(&path).await?;
+        let upgrade = upgrade_document(self, id, &document, self.strict).await?;
+        Self::replace_file(&path, upgrade).await
+    }
+
+    /// Migrates the file of the fragment if it's outdated. Returns the version it was migrated from.
+    async fn migrate_fragment_file(&self, name: &str) -> Result<Option<u32>, Box<dyn Error>> {
+        let path = self.fragment_path(name);
+        let document = fs::read_to_string(&path).await?;
+        let upgrade = migrate_fragment(&document, self.strict, name)?;
+        Self::replace_file(&path, upgrade).await
+    }
+
+    async fn replace_file(path: &Path, upgrade: Option<(u32, String)>) -> Result<Option<u32>, Box<dyn Error>> {
+        let Some((version, upgraded)) = upgrade else { return Ok(None) };
+        fs::copy(path, path.with_extension(format!("yaml.v{}.bak", version))).await?;
+        fs::write(path, upgraded).await?;
+        info!("Migrated {} from schema version {} to {}", path.display(), version, CURRENT_SCHEMA_VERSION);
+        Ok(Some(version))
+    }
+}
+
+async fn read_if_exists(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    if !try_exists(path).await? {
+        return Ok(None)
+    }
+    Ok(Some(fs::read_to_string(path).await?))
+}
+
+#[async_trait]
 impl ProfileDao for ProfileYamlDao {
 
     async fn create(&self, profile: &Profile) -> Result<bool, Box<dyn Error>> {
-        let output_path = Path::new(&self.db_path).join(&profile.id).with_extension("yaml");
-        if try_exists(&output_path).await? {
+        if try_exists(self.profile_path(&profile.id)).await? {
             return Ok(false);
         }
-        let mut file = File::create(output_path).await?;
-        let yaml_content = serde_yaml::to_string(&profile)?;
-        file.write_all(yaml_content.as_bytes()).await?;
+        self.save(profile).await?;
         Ok(true)
     }
 
-    async fn get(&self, id: &String) -> Result<Option<Profile>, Box<dyn Error>> {
-        let yaml_file = Path::new(&self.db_path).join(&id).with_extension("yaml");
-        if !try_exists(&yaml_file).await? {
-            return Ok(None)
+    async fn save(&self, profile: &Profile) -> Result<(), Box<dyn Error>> {
+        let document = to_commented_yaml(profile)?;
+        self.save_document(&profile.id, &document).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Profile>, Box<dyn Error>> {
+        let document = self.get_document(id).await?;
+        match document {
+            Some(document) => Ok(Some(resolve_document(self, id, &document, self.strict).await?)),
+            None => Ok(None),
         }
-        let mut f = File::open(yaml_file).await?;
-        let mut contents = String::new();
-        f.read_to_string(&mut contents).await?;
-        Ok(Some(serde_yaml::from_str(&contents)?))
     }
 
-}
+    async fn list(&self) -> Result<Vec<Profile>, Box<dyn Error>> {
+        let mut profiles = Vec::new();
+        let ids = self.list_ids().await?;
+        for id in ids {
+            match self.get(&id).await {
+                Ok(Some(profile)) => profiles.push(profile),
+                Ok(None) => {}
+                Err(e) => warn!("Skipping profile {} that can't be loaded: {}", id, e),
+            }
+        }
+        Ok(profiles)
+    }
+
+    async fn get_document(&self, id: &str) -> Result<Option<String>, Box<dyn Error>> {
+        read_if_exists(&self.profile_path(id)).await
+    }
 
+    async fn save_document(&self, id: &str, document: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(self.profile_path(id), document).await?;
+        Ok(())
+    }
+
+    async fn get_fragment(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        read_if_exists(&self.fragment_path(name)).await
+    }
+
+    async fn save_fragment(&self, name: &str, document: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(self.fragment_path(name), document).await?;
+        Ok(())
+    }
+
+    async fn list_fragments(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Self::list_yaml_files(&Path::new(&self.db_path).join(FRAGMENTS_DIR)).await
+    }
+
+    /// The original file is kept next to it with a `.v<version>.bak` suffix.
+    async fn migrate_all(&self) -> Result<Vec<(String, Result<u32, String>)>, Box<dyn Error>> {
+        let mut results = Vec::new();
+        // Fragments first, so the profiles including them resolve once upgraded
+        let names = self.list_fragments().await?;
+        for name in names {
+            match self.migrate_fragment_file(&name).await {
+                Ok(Some(version)) => results.push((format!("fragment {}", name), Ok(version))),
+                Ok(None) => {}
+                Err(e) => results.push((format!("fragment {}", name), Err(e.to_string()))),
+            }
+        }
+        let ids = self.list_ids().await?;
+        for id in ids {
+            match self.migrate_file(&id).await {
+                Ok(Some(version)) => results.push((format!("profile {}", id), Ok(version))),
+                Ok(None) => {}
+                Err(e) => results.push((format!("profile {}", id), Err(e.to_string()))),
+            }
+        }
+        Ok(results)
+    }
+
+    fn watch(&self) -> Result<Option<ProfileWatch>, Box<dyn Error>> {
+        let (sender, changes) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => event,
+                Ok(_) => return,
+                Err(e) => {
+                    warn!("Error when watching profiles: {}", e);
+                    return;
+                }
+            };
+            for path in event.paths.iter().filter(|p| p.extension().is_some_and(|ext| ext == "yaml")) {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+                    && sender.send(name.to_string()).is_err() {
+                    return;
+                }
+            }
+        })?;
+        watcher.watch(Path::new(&self.db_path), RecursiveMode::NonRecursive)?;
+        watcher.watch(&Path::new(&self.db_path).join(FRAGMENTS_DIR), RecursiveMode::NonRecursive)?;
+        Ok(Some(ProfileWatch { changes, watcher: Box::new(watcher) }))
+    }
+
+}
@@ -0,0 +1,12 @@
+use std::error::Error;
+use async_trait::async_trait;
+use crate::model::session::Session;
+
+#[async_trait]
+pub trait SessionDao: Send + Sync {
+    /// Creates the session or overwrites the existing one with the same id.
+    async fn save(&self, session: &Session) -> Result<(), Box<dyn Error>>;
+    async fn get(&self, id: &str) -> Result<Option<Session>, Box<dyn Error>>;
+    /// All the sessions, ordered by their start time.
+    async fn list(&self) -> Result<Vec<Session>, Box<dyn Error>>;
+}
@@ -0,0 +1,173 @@
+use crate::dao::session_dao::SessionDao;
+use crate::dao::sqlite::SqliteDb;
+use crate::model::session::{Session, SessionMessage, Visibility};
+use async_trait::async_trait;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::error::Error;
+
+/// Stores the sessions in the `sessions` and `session_messages` tables.
+pub struct SessionSqliteDao {
+    db: SqliteDb,
+}
+
+pub(crate) fn new(db: SqliteDb) -> SessionSqliteDao {
+    SessionSqliteDao { db }
+}
+
+/// A `sessions` row, before the messages are loaded.
+struct SessionRow {
+    id: String,
+    /// In UTC with a fixed number of digits, so the text sorts in time order
+    started_at: String,
+    profile_ids: String,
+    hidden: Option<String>,
+    /// The seed as an INTEGER, which is signed in SQLite
+    dice_seed: Option<i64>,
+}
+
+fn session_row(r: &rusqlite::Row) -> rusqlite::Result<SessionRow> {
+    Ok(SessionRow { id: r.get(0)?, started_at: r.get(1)?, profile_ids: r.get(2)?, hidden: r.get(3)?, dice_seed: r.get(4)? })
+}
+
+fn load_session(conn: &Connection, row: SessionRow) -> Result<Session, Box<dyn Error + Send + Sync>> {
+    let mut stmt = conn.prepare(
+        "SELECT from_user_id, from_username, role, content, visibility FROM session_messages WHERE session_id = ?1 ORDER BY seq")?;
+    let rows = stmt.query_map(params![row.id], |r| Ok((SessionMessage {
+        from_user_id: r.get(0)?,
+        from_username: r.get(1)?,
+        role: r.get(2)?,
+        content: r.get(3)?,
+        visibility: Visibility::Public,
+    }, r.get::<_, Option<String>>(4)?)))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut messages = Vec::new();
+    for (mut message, visibility) in rows {
+        message.visibility = visibility.map(|v| serde_json::from_str(&v)).transpose()?.unwrap_or_default();
+        messages.push(message);
+    }
+    Ok(Session {
+        id: row.id,
+        started_at: DateTime::parse_from_rfc3339(&row.started_at)?.with_timezone(&Local),
+        profile_ids: serde_json::from_str(&row.profile_ids)?,
+        messages,
+        hidden: row.hidden.map(|h| serde_json::from_str(&h)).transpose()?.unwrap_or_default(),
+        dice_seed: row.dice_seed.map(|seed| seed as u64),
+    })
+}
+
+fn to_sqlite_error(e: Box<dyn Error + Send + Sync>) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(e)
+}
+
+#[async_trait]
+impl SessionDao for SessionSqliteDao {
+    async fn save(&self, session: &Session) -> Result<(), Box<dyn Error>> {
+        let session = session.clone();
+        let profile_ids = serde_json::to_string(&session.profile_ids)?;
+        let hidden = if session.hidden.is_empty() { None } else { Some(serde_json::to_string(&session.hidden)?) };
+        let started_at = session.started_at.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Micros, true);
+        self.db.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("INSERT OR REPLACE INTO sessions (id, started_at, profile_ids, hidden, dice_seed) VALUES (?1, ?2, ?3, ?4, ?5)",
+                       params![session.id, started_at, profile_ids, hidden, session.dice_seed.map(|seed| seed as i64)])?;
+            tx.execute("DELETE FROM session_messages WHERE session_id = ?1", params![session.id])?;
+            for (seq, m) in session.messages.iter().enumerate() {
+                let visibility = (!m.visibility.is_public())
+                    .then(|| serde_json::to_string(&m.visibility).map_err(|e| to_sqlite_error(e.into())))
+                    .transpose()?;
+                tx.execute("INSERT INTO session_messages (session_id, seq, from_user_id, from_username, role, content, visibility) \
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                           params![session.id, seq, m.from_user_id, m.from_username, m.role, m.content, visibility])?;
+            }
+            tx.commit()
+        }).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, Box<dyn Error>> {
+        let id = id.to_string();
+        self.db.call(move |conn| {
+            let row = conn.query_row("SELECT id, started_at, profile_ids, hidden, dice_seed FROM sessions WHERE id = ?1", params![id], session_row)
+                .optional()?;
+            row.map(|row| load_session(conn, row).map_err(to_sqlite_error)).transpose()
+        }).await
+    }
+
+    async fn list(&self) -> Result<Vec<Session>, Box<dyn Error>> {
+        self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT id, started_at, profile_ids, hidden, dice_seed FROM sessions ORDER BY started_at")?;
+            let rows = stmt.query_map([], session_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            rows.into_iter()
+                .map(|row| load_session(conn, row).map_err(to_sqlite_error))
+                .collect()
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dao::sqlite;
+
+    fn message(from: &str, content: &str, visibility: Visibility) -> SessionMessage {
+        SessionMessage {
+            from_user_id: from.to_string(),
+            from_username: from.to_uppercase(),
+            role: "assistant".to_string(),
+            content: content.to_string(),
+            visibility,
+        }
+    }
+
+    fn session(id: &str, started_at: &str) -> Session {
+        Session {
+            id: id.to_string(),
+            started_at: DateTime::parse_from_rfc3339(started_at).unwrap().with_timezone(&Local),
+            profile_ids: vec!["ann".to_string(), "bob".to_string()],
+            messages: vec![
+                message("ann", "Hello", Visibility::Public),
+                message("bob", "Psst", Visibility::Whisper(vec!["ann".to_string()])),
+                message("ann", "In here", Visibility::Local { location: "kitchen".to_string(), present: vec!["bob".to_string()] }),
+            ],
+            hidden: vec!["@bob is a werewolf".to_string()],
+            dice_seed: Some(u64::MAX),
+        }
+    }
+
+    #[tokio::test]
+    async fn save_and_get_round_trip() {
+        let dao = new(sqlite::open(":memory:".to_string()).await.unwrap());
+        let saved = session("s1", "2024-05-01T12:00:00+02:00");
+        dao.save(&saved).await.unwrap();
+        let loaded = dao.get("s1").await.unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&saved).unwrap());
+        assert!(dao.get("missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn save_replaces_the_messages_of_the_session() {
+        let dao = new(sqlite::open(":memory:".to_string()).await.unwrap());
+        let mut saved = session("s1", "2024-05-01T12:00:00+02:00");
+        dao.save(&saved).await.unwrap();
+        saved.messages.truncate(1);
+        saved.hidden.clear();
+        saved.dice_seed = None;
+        dao.save(&saved).await.unwrap();
+        let loaded = dao.get("s1").await.unwrap().unwrap();
+        assert_eq!(loaded.messages.len(), 1);
+        assert!(loaded.hidden.is_empty());
+        assert_eq!(loaded.dice_seed, None);
+    }
+
+    #[tokio::test]
+    async fn list_orders_the_sessions_by_start() {
+        let dao = new(sqlite::open(":memory:".to_string()).await.unwrap());
+        dao.save(&session("later", "2024-05-01T11:00:00+00:00")).await.unwrap();
+        dao.save(&session("earlier", "2024-05-01T12:30:00+02:00")).await.unwrap();
+        let ids: Vec<String> = dao.list().await.unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, ["earlier", "later"]);
+        let stored: String = dao.db.call(|conn| conn.query_row("SELECT started_at FROM sessions WHERE id = 'earlier'", [], |row| row.get(0)))
+            .await.unwrap();
+        assert_eq!(stored, "2024-05-01T10:30:00.000000Z");
+    }
+}
@@ -0,0 +1,53 @@
+use crate::dao::session_dao::SessionDao;
+use crate::model::session::Session;
+use async_trait::async_trait;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, create_dir_all, try_exists};
+
+/// Stores each session as a YAML file in a directory.
+pub struct SessionYamlDao {
+    db_path: PathBuf,
+}
+
+pub(crate) async fn new(db_path: PathBuf) -> Result<SessionYamlDao, Box<dyn Error>> {
+    if !try_exists(&db_path).await? {
+        create_dir_all(&db_path).await?;
+    }
+    Ok(SessionYamlDao { db_path })
+}
+
+impl SessionYamlDao {
+    fn session_path(&self, id: &str) -> PathBuf {
+        Path::new(&self.db_path).join(id).with_extension("yaml")
+    }
+}
+
+#[async_trait]
+impl SessionDao for SessionYamlDao {
+    async fn save(&self, session: &Session) -> Result<(), Box<dyn Error>> {
+        fs::write(self.session_path(&session.id), serde_yaml::to_string(session)?).await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, Box<dyn Error>> {
+        let path = self.session_path(id);
+        if !try_exists(&path).await? {
+            return Ok(None);
+        }
+        Ok(Some(serde_yaml::from_str(&fs::read_to_string(path).await?)?))
+    }
+
+    async fn list(&self) -> Result<Vec<Session>, Box<dyn Error>> {
+        let mut sessions = Vec::new();
+        let mut entries = fs::read_dir(&self.db_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "yaml") {
+                sessions.push(serde_yaml::from_str::<Session>(&fs::read_to_string(path).await?)?);
+            }
+        }
+        sessions.sort_by_key(|s| s.started_at);
+        Ok(sessions)
+    }
+}
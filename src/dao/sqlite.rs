@@ -0,0 +1,112 @@
+use rusqlite::Connection;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+/// Statements upgrading the database schema. The index of the last applied
+/// statement plus one is stored as the `user_version` of the database.
+const SCHEMA_MIGRATIONS: &[&str] = &[
+    "CREATE TABLE profiles (
+        id TEXT PRIMARY KEY,
+        document TEXT NOT NULL
+    );
+    CREATE TABLE profile_backups (
+        id TEXT NOT NULL,
+        schema_version INTEGER NOT NULL,
+        document TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE profile_fragments (
+        name TEXT PRIMARY KEY,
+        document TEXT NOT NULL
+    );
+    CREATE TABLE profile_fragment_backups (
+        name TEXT NOT NULL,
+        schema_version INTEGER NOT NULL,
+        document TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        started_at TEXT NOT NULL,
+        profile_ids TEXT NOT NULL,
+        hidden TEXT,
+        dice_seed INTEGER
+    );
+    CREATE TABLE session_messages (
+        session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+        seq INTEGER NOT NULL,
+        from_user_id TEXT NOT NULL,
+        from_username TEXT NOT NULL,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        visibility TEXT,
+        PRIMARY KEY (session_id, seq)
+    );
+    CREATE TABLE memories (
+        profile_id TEXT NOT NULL,
+        seq INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        content TEXT NOT NULL,
+        session_id TEXT,
+        created_at TEXT NOT NULL,
+        PRIMARY KEY (profile_id, seq)
+    );
+    CREATE TABLE profile_states (
+        profile_id TEXT PRIMARY KEY,
+        document TEXT NOT NULL
+    );",
+];
+
+/// A SQLite connection shared by the SQLite DAOs. Queries run on the blocking thread pool.
+#[derive(Clone)]
+pub struct SqliteDb {
+    conn: Arc<Mutex<Connection>>,
+}
+
+pub(crate) async fn open(path: String) -> Result<SqliteDb, Box<dyn Error>> {
+    let conn = tokio::task::spawn_blocking(move || -> rusqlite::Result<Connection> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(conn)
+    }).await??;
+    Ok(SqliteDb { conn: Arc::new(Mutex::new(conn)) })
+}
+
+/// Applies the schema migrations the database doesn't have yet.
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let tx = conn.transaction()?;
+    for (i, migration) in SCHEMA_MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+    }
+    tx.commit()
+}
+
+impl SqliteDb {
+    pub async fn call<T, F>(&self, f: F) -> Result<T, Box<dyn Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().expect("sqlite connection lock poisoned");
+            f(&mut conn)
+        }).await??;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn open_applies_every_migration() {
+        let db = open(":memory:".to_string()).await.unwrap();
+        let version: usize = db.call(|conn| conn.query_row("PRAGMA user_version", [], |row| row.get(0))).await.unwrap();
+        assert_eq!(version, SCHEMA_MIGRATIONS.len());
+    }
+}
@@ -0,0 +1,17 @@
+//! Helpers to read the JSON output by LLMs, which tend to wrap it in markdown code fences or add text around it.
+
+/// The JSON array in the response, without the text or code fences around it.
+pub(crate) fn extract_json_array(response: &str) -> &str {
+    match (response.find('['), response.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &response[start..=end],
+        _ => response,
+    }
+}
+
+/// The JSON object in the response, without the text or code fences around it.
+pub(crate) fn extract_json_object(response: &str) -> &str {
+    match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &response[start..=end],
+        _ => response,
+    }
+}
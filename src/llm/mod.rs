@@ -6,25 +6,55 @@ use tokio_stream::Stream;
 use tokio_stream::StreamExt;
 
 pub mod openai;
+pub mod router;
+pub mod json;
 
 pub const ROLE_USER: &str = "user";
 pub const ROLE_SYSTEM: &str = "system";
 pub const ROLE_ASSISTANT: &str = "assistant";
 
+pub type LLMStream = Pin<Box<dyn Stream<Item=Result<String, Box<dyn Error + Send>>> + Send>>;
+
 pub struct LLMConversation {
     pub role: String,
     pub content: Arc<String>,
 }
 
+/// A function the LLM can call, with its parameters as a JSON schema.
+pub struct LLMTool {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+/// A call of a tool by the LLM, with its arguments as a JSON object.
+#[derive(Debug)]
+pub struct LLMToolCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+/// The reply of the LLM when it's given tools: some text, the tool calls, or both.
+#[derive(Debug, Default)]
+pub struct LLMToolResponse {
+    pub content: String,
+    pub tool_calls: Vec<LLMToolCall>,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[async_trait]
 pub trait LLM: Send + Sync {
     async fn load_from_yaml(path: String) -> Result<Self, Box<dyn Error>> where Self: Sized;
 
-    fn complete(&self, system_prompt: &String, conversation: &Vec<LLMConversation>) -> Pin<Box<dyn Stream<Item=Result<String, Box<dyn Error + Send>>> + Send>>;
+    fn complete(&self, system_prompt: &str, conversation: &[LLMConversation]) -> LLMStream;
+
+    /// Completes the conversation without streaming, letting the LLM call the tools.
+    async fn complete_with_tools(&self, system_prompt: &str, conversation: &[LLMConversation], tools: &[LLMTool])
+        -> Result<LLMToolResponse, Box<dyn Error>>;
 
-    fn single_chat_stream(&self, prompt: Arc<String>) -> Pin<Box<dyn Stream<Item=Result<String, Box<dyn Error + Send>>> + Send>> {
-        self.complete(&"".to_string(),
-                      &vec!(LLMConversation{role: ROLE_USER.to_string(), content: prompt}))
+    fn single_chat_stream(&self, prompt: Arc<String>) -> LLMStream {
+        self.complete("",
+                      &[LLMConversation{role: ROLE_USER.to_string(), content: prompt}])
     }
 
     async fn single_chat(&self, prompt: Arc<String>) -> Result<String, Box<dyn Error>> {
@@ -1,11 +1,9 @@
-use super::{LLMConversation, LLM, ROLE_SYSTEM};
+use super::{LLMConversation, LLMStream, LLMTool, LLMToolCall, LLMToolResponse, LLM, ROLE_SYSTEM};
 use async_trait::async_trait;
 use futures::stream::StreamExt;
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::pin::Pin;
-use tokio_stream::Stream;
 
 #[derive(Debug, Deserialize)]
 pub struct OpenAIConfig {
@@ -35,6 +33,21 @@ struct ChatCompletionRequest {
     model: String,
     messages: Vec<ChatMessage>,
     stream: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool>,
+}
+
+#[derive(Debug, Serialize)]
+struct Tool {
+    r#type: &'static str,
+    function: FunctionSpec,
+}
+
+#[derive(Debug, Serialize)]
+struct FunctionSpec {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
 }
 
 #[derive(Debug, Deserialize)]
@@ -45,6 +58,7 @@ struct ChatCompletionResponse {
 #[derive(Debug, Deserialize)]
 struct Choice {
     delta: Option<Delta>,
+    message: Option<ResponseMessage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,6 +66,45 @@ struct Delta {
     content: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+struct ResponseMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ResponseToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseToolCall {
+    function: FunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionCall {
+    name: String,
+    arguments: String,
+}
+
+fn to_messages(system_prompt: &str, conversation: &[LLMConversation]) -> Vec<ChatMessage> {
+    let mut messages = Vec::new();
+
+    // Add system prompt if provided
+    if !system_prompt.is_empty() {
+        messages.push(ChatMessage {
+            role: ROLE_SYSTEM.to_string(),
+            content: system_prompt.to_string(),
+        });
+    }
+
+    // Add conversation history
+    for conv in conversation {
+        messages.push(ChatMessage {
+            role: conv.role.clone(),
+            content: conv.content.as_ref().clone(),
+        });
+    }
+    messages
+}
+
 #[async_trait]
 impl LLM for OpenAI {
     async fn load_from_yaml(path: String) -> Result<Self, Box<dyn Error>> {
@@ -63,29 +116,12 @@ impl LLM for OpenAI {
         Ok(OpenAI { config, client })
     }
 
-    fn complete(&self, system_prompt: &String, conversation: &Vec<LLMConversation>) -> Pin<Box<dyn Stream<Item=Result<String, Box<dyn Error + Send>>> + Send>> {
-        let mut messages = Vec::new();
-
-        // Add system prompt if provided
-        if !system_prompt.is_empty() {
-            messages.push(ChatMessage {
-                role: ROLE_SYSTEM.to_string(),
-                content: system_prompt.clone(),
-            });
-        }
-
-        // Add conversation history
-        for conv in conversation {
-            messages.push(ChatMessage {
-                role: conv.role.clone(),
-                content: conv.content.as_ref().clone(),
-            });
-        }
-
+    fn complete(&self, system_prompt: &str, conversation: &[LLMConversation]) -> LLMStream {
         let request = ChatCompletionRequest {
             model: self.config.model.clone(),
-            messages,
+            messages: to_messages(system_prompt, conversation),
             stream: true,
+            tools: Vec::new(),
         };
 
         // Log the request
@@ -115,7 +151,7 @@ impl LLM for OpenAI {
                 let status = response.status();
                 let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
                 info!("OpenAI API Error: status={}, error={}", status, error_text);
-                yield Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("OpenAI API error: {}", error_text))) as Box<dyn Error + Send>);
+                yield Err(Box::new(std::io::Error::other(format!("OpenAI API error: {}", error_text))) as Box<dyn Error + Send>);
                 return;
             }
 
@@ -164,4 +200,45 @@ impl LLM for OpenAI {
 
         Box::pin(stream)
     }
+
+    async fn complete_with_tools(&self, system_prompt: &str, conversation: &[LLMConversation], tools: &[LLMTool])
+        -> Result<LLMToolResponse, Box<dyn Error>> {
+        let request = ChatCompletionRequest {
+            model: self.config.model.clone(),
+            messages: to_messages(system_prompt, conversation),
+            stream: false,
+            tools: tools.iter().map(|t| Tool {
+                r#type: "function",
+                function: FunctionSpec { name: t.name.clone(), description: t.description.clone(), parameters: t.parameters.clone() },
+            }).collect(),
+        };
+        info!("OpenAI API Request with tools to model: {}", self.config.model);
+        debug!("Request payload: {:?}", request);
+
+        let response = self.client
+            .post(format!("{}/chat/completions", self.config.base_url))
+            .header("Authorization", format!("Bearer {}", self.config.api_key))
+            .header("Content-Type", "application/json")
+            .json(&request)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            info!("OpenAI API Error: status={}, error={}", status, error_text);
+            return Err(format!("OpenAI API error: {}", error_text).into());
+        }
+        let text = response.text().await?;
+        debug!("Response payload: {:?}", text);
+        let message = serde_json::from_str::<ChatCompletionResponse>(&text)?
+            .choices.into_iter().next()
+            .and_then(|choice| choice.message)
+            .ok_or("OpenAI API returned no message")?;
+        Ok(LLMToolResponse {
+            content: message.content.unwrap_or_default(),
+            tool_calls: message.tool_calls.into_iter()
+                .map(|call| LLMToolCall { name: call.function.name, arguments: call.function.arguments })
+                .collect(),
+        })
+    }
 }
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use crate::llm::LLM;
+use crate::llm::openai::OpenAI;
+use crate::model::room_config::LLMRouting;
+
+/// Picks the LLM for each role in a room.
+pub struct LLMRouter {
+    default: Arc<dyn LLM>,
+    planner: Option<Arc<dyn LLM>>,
+    evaluator: Option<Arc<dyn LLM>>,
+    narrator: Option<Arc<dyn LLM>>,
+    agents: HashMap<String, Arc<dyn LLM>>,
+}
+
+impl LLMRouter {
+    pub async fn load(routing: &LLMRouting) -> Result<Self, Box<dyn Error>> {
+        let default = routing.default.clone().ok_or("No default LLM config given")?;
+        let planner = match &routing.planner {
+            Some(path) => Some(Arc::new(OpenAI::load_from_yaml(path.clone()).await?) as Arc<dyn LLM>),
+            None => None,
+        };
+        let evaluator = match &routing.evaluator {
+            Some(path) => Some(Arc::new(OpenAI::load_from_yaml(path.clone()).await?) as Arc<dyn LLM>),
+            None => None,
+        };
+        let narrator = match &routing.narrator {
+            Some(path) => Some(Arc::new(OpenAI::load_from_yaml(path.clone()).await?) as Arc<dyn LLM>),
+            None => None,
+        };
+        let mut agents: HashMap<String, Arc<dyn LLM>> = HashMap::new();
+        for (id, path) in routing.agents.iter() {
+            agents.insert(id.clone(), Arc::new(OpenAI::load_from_yaml(path.clone()).await?));
+        }
+        Ok(LLMRouter {
+            default: Arc::new(OpenAI::load_from_yaml(default).await?),
+            planner,
+            evaluator,
+            narrator,
+            agents,
+        })
+    }
+
+    pub fn default(&self) -> Arc<dyn LLM> {
+        self.default.clone()
+    }
+
+    pub fn planner(&self) -> Arc<dyn LLM> {
+        self.planner.clone().unwrap_or_else(|| self.default.clone())
+    }
+
+    pub fn evaluator(&self) -> Arc<dyn LLM> {
+        self.evaluator.clone().unwrap_or_else(|| self.default.clone())
+    }
+
+    pub fn narrator(&self) -> Arc<dyn LLM> {
+        self.narrator.clone().unwrap_or_else(|| self.default.clone())
+    }
+
+    pub fn agent(&self, profile_id: &str) -> Arc<dyn LLM> {
+        self.agents.get(profile_id).cloned().unwrap_or_else(|| self.default.clone())
+    }
+}
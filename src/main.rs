@@ -1,25 +1,37 @@
 use std::sync::Arc;
 use clap::{Parser, Subcommand};
-use crate::dao::profile_dao::ProfileDao;
 use crate::model::profile::Profile;
-use tokio_stream::{self as stream, StreamExt};
-use crate::chat::plan_agent::PlanAgent;
-use crate::chat::room::Room;
+use crate::chat::session_recorder::SessionRecorder;
+use crate::chat::room_builder::{self, OpenRoom};
+use crate::chat::{memory_extractor, mood_evaluator, relationship_evaluator, rpg_engine};
+use crate::chat::message::WORLD_ID;
 use crate::llm::LLM;
 use crate::llm::openai::OpenAI;
+use crate::model::room_config::{GameConfig, NarratorConfig, RoomConfig, RpgConfig, SpeakerSelection};
+use crate::model::session::Visibility;
 use crate::ui::cli_ui::CliUI;
+use crate::ui::{memory_editor, profile_editor};
+use crate::convert::character_card;
+use crate::convert::chat_export::{self, ChatExportFormat, ExampleFilter};
+use crate::convert::profile_generator;
+use crate::dao::profile_document::{set_document_fields, to_commented_yaml};
 
 mod model;
 mod dao;
 mod chat;
 mod llm;
 mod ui;
+mod convert;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    #[arg(short, long)]
-    profile_path: String,
+    /// Where profiles and sessions are stored: `yaml:<dir>` or `sqlite:<file>`. A plain path is a YAML directory
+    #[arg(short, long, alias = "profile-path", short_alias = 'p')]
+    store: String,
+    /// Reject profiles with fields unknown to the current schema
+    #[arg(long)]
+    strict: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -30,11 +42,161 @@ enum Commands {
         #[arg(short, long)]
         id: String,
     },
+    /// Open the profile in $EDITOR and validate it on save
+    EditProfile {
+        #[arg(short, long)]
+        id: String,
+    },
+    /// Print the profile document
+    ShowProfile {
+        #[arg(short, long)]
+        id: String,
+        /// Print the profile with the inherited profiles and included fragments merged in
+        #[arg(long)]
+        resolved: bool,
+    },
+    /// Import a profile from a character card in JSON or PNG format
+    ImportProfile {
+        #[arg(short, long)]
+        id: String,
+        #[arg(short, long)]
+        file: String,
+        /// Overwrite the profile if it already exists
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Export a profile as a character card. The format is decided by the output extension (.json or .png)
+    ExportProfile {
+        #[arg(short, long)]
+        id: String,
+        #[arg(short, long)]
+        output: String,
+        /// PNG image to embed the card into. A blank image is used if not given
+        #[arg(long)]
+        image: Option<String>,
+    },
+    /// Build a profile from one participant of a chat export
+    ImportChat {
+        #[arg(short, long)]
+        id: String,
+        #[arg(short, long)]
+        file: String,
+        #[arg(long, value_enum, default_value_t = ChatExportFormat::Auto)]
+        format: ChatExportFormat,
+        /// Name of the participant as it appears in the export
+        #[arg(short, long)]
+        speaker: String,
+        /// Max number of messages to keep as conversation examples
+        #[arg(long)]
+        sample: Option<usize>,
+        /// Seed for sampling the messages
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Skip messages shorter than this number of characters
+        #[arg(long, default_value_t = 10)]
+        min_len: usize,
+        /// Skip messages longer than this number of characters
+        #[arg(long, default_value_t = 500)]
+        max_len: usize,
+        /// Draft the background with an LLM summary of the participant's messages
+        #[arg(long, requires = "llm_config")]
+        summarize: bool,
+        #[arg(short, long)]
+        llm_config: Option<String>,
+    },
+    /// Generate profiles with the LLM from a one-line brief
+    GenerateProfile {
+        /// Id of the profile. With `--count` above 1, profiles get the ids `<id>-1`, `<id>-2`...
+        #[arg(short, long)]
+        id: String,
+        #[arg(short, long)]
+        brief: String,
+        /// Number of different profiles to generate
+        #[arg(short, long, default_value_t = 1)]
+        count: usize,
+        #[arg(short, long)]
+        llm_config: String,
+    },
+    /// Upgrade all profiles and fragments to the current schema version, keeping backups.
+    /// The upgraded documents get the field guide as comments instead of their own comments.
+    MigrateProfiles,
+    /// Print the transcript of a recorded chat session
+    ShowSession {
+        #[arg(short, long)]
+        id: String,
+        /// Also print what the participants didn't know, e.g. the secret roles and votes of a game
+        #[arg(long)]
+        hidden: bool,
+    },
+    /// Print what the profile remembers from past sessions
+    ShowMemories {
+        #[arg(short, long)]
+        id: String,
+    },
+    /// Open the memories of the profile in $EDITOR
+    EditMemories {
+        #[arg(short, long)]
+        id: String,
+    },
+    /// Extract the memories of the agents of a recorded session again
+    ExtractMemories {
+        /// Id of the session
+        #[arg(short, long)]
+        session: String,
+        #[arg(short, long)]
+        llm_config: String,
+    },
+    /// Copy all profiles and sessions from another store into this one
+    ImportStore {
+        #[arg(short, long)]
+        from: String,
+    },
+    /// Copy all profiles and sessions from this store into another one
+    ExportStore {
+        #[arg(short, long)]
+        to: String,
+    },
+    /// Start a chat room. Options given on the command line override the ones in the room file
     NewChat {
+        /// Room definition file with participants, LLM routing, topic and limits
+        #[arg(short, long)]
+        room: Option<String>,
         #[arg(short, long)]
         profile_ids: Vec<String>,
         #[arg(short, long)]
-        llm_config: String,
+        llm_config: Option<String>,
+        /// Id of the profile the human chats as. Required unless the room file has a `user`
+        #[arg(long = "as")]
+        user_profile_id: Option<String>,
+        #[arg(long, value_enum)]
+        speaker_selection: Option<SpeakerSelection>,
+        /// What the room is about. Can be changed in the chat with `/topic`
+        #[arg(short, long)]
+        topic: Option<String>,
+        /// Lorebook file with the world knowledge shared by all agents
+        #[arg(long)]
+        lorebook: Option<String>,
+        /// Event script with the scheduled and random world events
+        #[arg(long)]
+        events: Option<String>,
+        /// World file with the locations and objects, and where everyone is
+        #[arg(long)]
+        world: Option<String>,
+        /// Let the agents keep the conversation going on their own
+        #[arg(long)]
+        autonomous: bool,
+        /// Add a narrator describing the scene between the character turns
+        #[arg(long)]
+        narrator: bool,
+        /// Play a game of Werewolf with hidden roles instead of chatting freely
+        #[arg(long)]
+        game: bool,
+        /// Play a tabletop RPG with dice and the character sheets of the profiles
+        #[arg(long)]
+        rpg: bool,
+        /// Don't extract memories from the session when the chat ends
+        #[arg(long)]
+        no_memory: bool,
     }
 }
 
@@ -44,11 +206,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     log4rs::init_file("log4rs.yaml", Default::default())?;
 
     let cli = Cli::parse();
-    let profile_dao = Arc::new(dao::profile_yaml_dao::new(cli.profile_path).await?);
+    let store = dao::open_store(&cli.store, cli.strict).await?;
+    let profile_dao = store.profiles.clone();
     match cli.command {
         Commands::CreateProfile { id} => {
-            let mut p = Profile::default();
-            p.id = id;
+            let p = Profile { id, ..Default::default() };
             let created = profile_dao.create(&p).await?;
             if created {
                 println!("Profile template file created successfully");
@@ -56,30 +218,234 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("Profile template file already exists");
             }
         }
-        Commands::NewChat {profile_ids, llm_config} => {
+        Commands::EditProfile { id } => {
+            if profile_editor::edit_profile(profile_dao.as_ref(), &id).await? {
+                println!("Profile saved successfully");
+            } else {
+                println!("Profile editing aborted");
+            }
+        }
+        Commands::ShowProfile { id, resolved } => {
+            if resolved {
+                let profile = profile_dao.get(&id).await?.ok_or(format!("Profile {} not found", id))?;
+                print!("{}", serde_yaml::to_string(&profile)?);
+            } else {
+                let document = profile_dao.get_document(&id).await?.ok_or(format!("Profile {} not found", id))?;
+                print!("{}", document);
+            }
+        }
+        Commands::ImportProfile { id, file, overwrite } => {
+            let card = character_card::parse_card(&tokio::fs::read(&file).await?)?;
+            let imported = character_card::card_to_profile(&id, &card);
+            if overwrite {
+                profile_dao.save(&imported.profile).await?;
+            } else if !profile_dao.create(&imported.profile).await? {
+                return Err(format!("Profile {} already exists, use --overwrite to replace it", id).into());
+            }
+            println!("Profile {} imported successfully", id);
+            if !imported.unmapped_fields.is_empty() {
+                println!("Fields that could not be mapped: {}", imported.unmapped_fields.join(", "));
+            }
+        }
+        Commands::ExportProfile { id, output, image } => {
+            let profile = profile_dao.get(&id).await?.ok_or(format!("Profile {} not found", id))?;
+            let card = character_card::profile_to_card(&profile);
+            let bytes = if output.to_lowercase().ends_with(".png") {
+                let image = match image {
+                    Some(path) => Some(tokio::fs::read(path).await?),
+                    None => None,
+                };
+                character_card::card_to_png(&card, image.as_deref())?
+            } else {
+                character_card::card_to_json(&card)?
+            };
+            tokio::fs::write(&output, bytes).await?;
+            println!("Profile {} exported to {}", id, output);
+        }
+        Commands::ImportChat { id, file, format, speaker, sample, seed, min_len, max_len, summarize, llm_config } => {
+            let contents = tokio::fs::read_to_string(&file).await?;
+            let messages = chat_export::parse_chat_export(format, &file, &contents)?;
+            let filter = ExampleFilter { min_len, max_len, sample, seed };
+            let examples = chat_export::select_examples(&messages, &speaker, &filter)?;
+            // Update the document as it is, to keep the profile it extends and the fragments it includes
+            let document = match profile_dao.get_document(&id).await? {
+                Some(document) => document,
+                None => to_commented_yaml(&Profile { id: id.clone(), name: speaker.clone(), ..Default::default() })?,
+            };
+            println!("Imported {} conversation examples", examples.len());
+            let mut fields = vec![("conversation_examples", serde_yaml::to_value(&examples)?)];
+            if summarize && let Some(llm_config) = llm_config {
+                let llm = OpenAI::load_from_yaml(llm_config).await?;
+                let background = chat_export::summarize_background(&llm, &speaker, &messages).await?;
+                fields.push(("background", background.into()));
+            }
+            profile_dao.save_document(&id, &set_document_fields(&document, &id, fields)?).await?;
+            println!("Profile {} saved successfully", id);
+            let profile = profile_dao.get(&id).await?.ok_or(format!("Profile {} not found", id))?;
+            if profile.background.trim().is_empty() {
+                println!("The background is empty, use edit-profile to write it");
+            }
+        }
+        Commands::GenerateProfile { id, brief, count, llm_config } => {
+            if count == 0 {
+                return Err("--count must be at least 1".into());
+            }
+            let ids: Vec<String> = if count == 1 {
+                vec![id]
+            } else {
+                (1..=count).map(|n| format!("{}-{}", id, n)).collect()
+            };
+            let existing = profile_dao.list().await?;
+            if let Some(p) = existing.iter().find(|p| ids.contains(&p.id)) {
+                return Err(format!("Profile {} already exists", p.id).into());
+            }
+            let taken_names: Vec<String> = existing.into_iter().map(|p| p.name).collect();
+            let llm = OpenAI::load_from_yaml(llm_config).await?;
+            let profiles = profile_generator::generate_profiles(&llm, &brief, &ids, &taken_names).await?;
+            for p in profiles {
+                profile_dao.create(&p).await?;
+                println!("Profile {} ({}) created", p.id, p.name);
+            }
+        }
+        Commands::MigrateProfiles => {
+            let results = profile_dao.migrate_all().await?;
+            if results.is_empty() {
+                println!("All profiles and fragments are up to date");
+            }
+            for (name, result) in results {
+                match result {
+                    Ok(version) => println!("Migrated {} from schema version {}", name, version),
+                    Err(e) => eprintln!("Failed to migrate {}: {}", name, e),
+                }
+            }
+        }
+        Commands::ShowSession { id, hidden } => {
+            let session = store.sessions.get(&id).await?.ok_or(format!("Session {} not found", id))?;
+            println!("Session {} started at {}", session.id, session.started_at.format("%Y-%m-%d %H:%M:%S"));
+            if let Some(seed) = session.dice_seed {
+                println!("Dice seed: {}", seed);
+            }
+            for m in session.messages {
+                if m.from_user_id == WORLD_ID {
+                    match m.visibility {
+                        Visibility::Whisper(to) => println!("\n* (to @{}) {}", to.join(", @"), m.content),
+                        _ => println!("\n* {}", m.content),
+                    }
+                    continue;
+                }
+                let label = m.visibility.label().map(|l| format!(" ({})", l)).unwrap_or_default();
+                println!("\n{}(@{}){}:\n{}", m.from_username, m.from_user_id, label, m.content);
+            }
+            if hidden && !session.hidden.is_empty() {
+                println!("\nHidden from the participants:");
+                for line in session.hidden {
+                    println!("- {}", line);
+                }
+            }
+        }
+        Commands::ShowMemories { id } => {
+            let memories = store.memories.get(&id).await?;
+            if memories.is_empty() {
+                println!("Profile {} doesn't remember anything yet", id);
+            }
+            for (i, m) in memories.iter().enumerate() {
+                println!("{}. [{:?}] {} ({})", i + 1, m.kind, m.content, m.created_at.format("%Y-%m-%d %H:%M"));
+            }
+        }
+        Commands::EditMemories { id } => {
+            if memory_editor::edit_memories(store.memories.as_ref(), &id).await? {
+                println!("Memories saved successfully");
+            } else {
+                println!("Memories editing aborted");
+            }
+        }
+        Commands::ExtractMemories { session, llm_config } => {
+            let session = store.sessions.get(&session).await?.ok_or(format!("Session {} not found", session))?;
             let llm = OpenAI::load_from_yaml(llm_config).await?;
-            let profiles: Vec<Arc<Profile>> = stream::iter(profile_ids)
-                .then(|id| {
-                    let dao = profile_dao.clone();
-                    async move { dao.get(&id).await }
-                })
-                .filter_map(|result| {
+            for (id, result) in memory_extractor::remember_session(&llm, &store, &session).await {
+                match result {
+                    Ok(count) => println!("Profile {} remembers {} new things", id, count),
+                    Err(e) => eprintln!("Failed to extract the memories of {}: {}", id, e),
+                }
+            }
+        }
+        Commands::ImportStore { from } => {
+            let source = dao::open_store(&from, cli.strict).await?;
+            let (profiles, sessions) = dao::copy_store(&source, &store).await?;
+            println!("Imported {} profiles and {} sessions", profiles, sessions);
+        }
+        Commands::ExportStore { to } => {
+            let target = dao::open_store(&to, cli.strict).await?;
+            let (profiles, sessions) = dao::copy_store(&store, &target).await?;
+            println!("Exported {} profiles and {} sessions", profiles, sessions);
+        }
+        Commands::NewChat {room, profile_ids, llm_config, user_profile_id, speaker_selection, topic, lorebook, events, world, autonomous, narrator, game, rpg, no_memory} => {
+            let mut config = match room {
+                Some(path) => RoomConfig::load_from_yaml(&path).await?,
+                None => RoomConfig::default(),
+            };
+            if !profile_ids.is_empty() {
+                config.participants = profile_ids;
+            }
+            if llm_config.is_some() {
+                config.llm.default = llm_config;
+            }
+            if user_profile_id.is_some() {
+                config.user = user_profile_id;
+            }
+            if topic.is_some() {
+                config.topic = topic;
+            }
+            if autonomous {
+                config.autonomous.enabled = true;
+            }
+            if lorebook.is_some() {
+                config.lorebook = lorebook;
+            }
+            if events.is_some() {
+                config.events = events;
+            }
+            if world.is_some() {
+                config.world = world;
+            }
+            if narrator && config.narrator.is_none() {
+                config.narrator = Some(NarratorConfig::default());
+            }
+            if game && config.game.is_none() {
+                config.game = Some(GameConfig::default());
+            }
+            if rpg && config.rpg.is_none() {
+                config.rpg = Some(RpgConfig::default());
+            }
+            if let Some(speaker_selection) = speaker_selection {
+                config.speaker_selection = speaker_selection;
+            }
+            let OpenRoom { room, llms, user } = room_builder::open_room(&config, &store).await?;
+            let recorder = SessionRecorder::start(room.clone());
+            let ui = CliUI::new(room.clone(), Arc::new(user.id.clone()), Arc::new(user.name.clone()));
+            let result = ui.start();
+            let session = recorder.finish().await;
+            store.sessions.save(&session).await?;
+            println!("Session {} saved", session.id);
+            if let Err(e) = relationship_evaluator::save(&room, store.states.as_ref()).await {
+                eprintln!("Failed to save the relationships: {}", e);
+            }
+            if let Err(e) = mood_evaluator::save(&room, store.states.as_ref()).await {
+                eprintln!("Failed to save the moods: {}", e);
+            }
+            if let Err(e) = rpg_engine::save(&room, store.states.as_ref()).await {
+                eprintln!("Failed to save the character sheets: {}", e);
+            }
+            if !no_memory && !session.messages.is_empty() {
+                println!("Extracting memories...");
+                for (id, result) in memory_extractor::remember_session(llms.default().as_ref(), &store, &session).await {
                     match result {
-                        Ok(Some(p)) => Some(Arc::new(p)),
-                        Ok(None) => None,
-                        Err(e) => {
-                            eprintln!("Error when get profile: {}", e);
-                            None
-                        }
+                        Ok(count) => println!("Profile {} remembers {} new things", id, count),
+                        Err(e) => eprintln!("Failed to extract the memories of {}: {}", id, e),
                     }
-                })
-                .collect()
-                .await;
-            let room = Arc::new(Room::new(100, profiles));
-            let plan_agent = PlanAgent::new(Arc::new(llm), room.clone());
-            plan_agent.start().await;
-            let ui = CliUI::new(room.clone(), Arc::new("tuser".into()), Arc::new("Test User".into()));
-            ui.start()?
+                }
+            }
+            result?
         }
     }
     Ok(())
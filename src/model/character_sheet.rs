@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Game statistics of a character for tabletop role-play.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CharacterSheet {
+    /// Attributes and skills by name, e.g. `strength: 14`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stats: BTreeMap<String, i32>,
+    /// Current hit points
+    #[serde(default)]
+    pub hp: i32,
+    /// Hit points the character can heal up to
+    #[serde(default)]
+    pub max_hp: i32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inventory: Vec<String>,
+}
+
+/// A change to a character sheet, issued by the agents as a tool call.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SheetChange {
+    /// Heal with a positive amount, take damage with a negative one
+    ChangeHp { who: String, amount: i32 },
+    AddItem { who: String, item: String },
+    RemoveItem { who: String, item: String },
+}
+
+impl SheetChange {
+    /// Id of the participant whose sheet changes.
+    pub fn who(&self) -> &str {
+        match self {
+            SheetChange::ChangeHp { who, .. } | SheetChange::AddItem { who, .. } | SheetChange::RemoveItem { who, .. } =>
+                who.trim_start_matches('@'),
+        }
+    }
+}
+
+impl CharacterSheet {
+    /// Applies the change and describes it, e.g. `@bob loses 3 HP (7/10)`.
+    pub fn apply(&mut self, change: &SheetChange) -> Result<String, String> {
+        let who = change.who();
+        match change {
+            SheetChange::ChangeHp { amount, .. } => {
+                let before = self.hp;
+                self.hp = self.hp.saturating_add(*amount).min(self.max_hp.max(self.hp));
+                let delta = self.hp.saturating_sub(before);
+                let verb = if delta >= 0 { "gains" } else { "loses" };
+                let fallen = if self.hp <= 0 { ", and falls" } else { "" };
+                Ok(format!("@{} {} {} HP ({}/{}){}", who, verb, delta.unsigned_abs(), self.hp, self.max_hp, fallen))
+            }
+            SheetChange::AddItem { item, .. } => {
+                self.inventory.push(item.clone());
+                Ok(format!("@{} gets {}", who, item))
+            }
+            SheetChange::RemoveItem { item, .. } => {
+                let index = self.inventory.iter().position(|i| i.eq_ignore_ascii_case(item))
+                    .ok_or_else(|| format!("@{} has no {}", who, item))?;
+                let removed = self.inventory.remove(index);
+                Ok(format!("@{} loses {}", who, removed))
+            }
+        }
+    }
+
+    /// The sheet in one line for the prompts, e.g. `HP 7/10, strength 14, inventory: rope, torch`.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("HP {}/{}", self.hp, self.max_hp)];
+        parts.extend(self.stats.iter().map(|(name, value)| format!("{} {}", name, value)));
+        let inventory = if self.inventory.is_empty() { "nothing".to_string() } else { self.inventory.join(", ") };
+        parts.push(format!("inventory: {}", inventory));
+        parts.join(", ")
+    }
+}
@@ -0,0 +1,192 @@
+use rand::Rng;
+use std::str::FromStr;
+
+/// Most dice a single expression can roll, to keep the results readable.
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+/// Largest sum of the constants of an expression, so rolls never overflow.
+const MAX_MODIFIER: i64 = 1_000_000;
+
+/// A dice expression in the usual tabletop notation, e.g. `2d6+3`, `d20` or `1d8+1d4-1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiceExpression {
+    /// The expression as written, normalized
+    pub text: String,
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Dice { count: u32, sides: u32, negative: bool },
+    Constant(i64),
+}
+
+/// The outcome of rolling a dice expression.
+#[derive(Debug, Clone)]
+pub struct DiceRoll {
+    pub expression: String,
+    /// Each die rolled, in order
+    pub rolls: Vec<u32>,
+    /// Sum of the constants of the expression
+    pub modifier: i64,
+    pub total: i64,
+}
+
+impl FromStr for DiceExpression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text: String = s.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+        let invalid = |reason: &str| format!("invalid dice expression `{}`: {}", s.trim(), reason);
+        if text.is_empty() {
+            return Err(invalid("it's empty"));
+        }
+        let mut terms = Vec::new();
+        let mut dice_count: u32 = 0;
+        let mut modifier: i64 = 0;
+        let too_many_dice = || invalid(&format!("at most {} dice can be rolled at once", MAX_DICE));
+        let mut rest = text.as_str();
+        let mut negative = false;
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = &rest[..end];
+            match term.split_once('d') {
+                Some((count, sides)) => {
+                    let count: u32 = if count.is_empty() { 1 } else { count.parse().map_err(|_| invalid("bad number of dice"))? };
+                    let sides: u32 = sides.parse().map_err(|_| invalid("bad number of sides"))?;
+                    if count == 0 || !(2..=MAX_SIDES).contains(&sides) {
+                        return Err(invalid(&format!("dice need 1 or more rolls and 2 to {} sides", MAX_SIDES)));
+                    }
+                    dice_count = dice_count.checked_add(count).filter(|n| *n <= MAX_DICE).ok_or_else(too_many_dice)?;
+                    terms.push(Term::Dice { count, sides, negative });
+                }
+                None => {
+                    let value: i64 = term.parse().map_err(|_| invalid("expected dice like `2d6` or a number"))?;
+                    let value = if negative { -value } else { value };
+                    modifier = modifier.checked_add(value).filter(|m| m.abs() <= MAX_MODIFIER)
+                        .ok_or_else(|| invalid(&format!("the numbers must add up to at most {}", MAX_MODIFIER)))?;
+                    terms.push(Term::Constant(value));
+                }
+            }
+            if end == rest.len() {
+                break;
+            }
+            negative = rest[end..].starts_with('-');
+            rest = &rest[end + 1..];
+        }
+        if dice_count == 0 {
+            return Err(invalid("there are no dice to roll"));
+        }
+        Ok(DiceExpression { text, terms })
+    }
+}
+
+impl DiceExpression {
+    pub fn roll(&self, rng: &mut impl Rng) -> DiceRoll {
+        let mut rolls = Vec::new();
+        let mut modifier = 0;
+        let mut total = 0;
+        for term in self.terms.iter() {
+            match term {
+                Term::Dice { count, sides, negative } => for _ in 0..*count {
+                    let roll = rng.random_range(1..=*sides);
+                    rolls.push(roll);
+                    total += if *negative { -i64::from(roll) } else { i64::from(roll) };
+                },
+                Term::Constant(value) => {
+                    modifier += value;
+                    total += value;
+                }
+            }
+        }
+        DiceRoll { expression: self.text.clone(), rolls, modifier, total }
+    }
+}
+
+impl DiceRoll {
+    /// The roll with the value of each die, e.g. `2d6+3: [4, 2] + 3 = 9`.
+    pub fn describe(&self) -> String {
+        let rolls = self.rolls.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(", ");
+        let modifier = match self.modifier {
+            0 => String::new(),
+            m if m > 0 => format!(" + {}", m),
+            m => format!(" - {}", -m),
+        };
+        format!("{}: [{}]{} = {}", self.expression, rolls, modifier, self.total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn parse_error(text: &str) -> String {
+        text.parse::<DiceExpression>().unwrap_err()
+    }
+
+    #[test]
+    fn parses_the_usual_notation() {
+        let expression: DiceExpression = " 2D6 + 3 - d4 ".parse().unwrap();
+        assert_eq!(expression.text, "2d6+3-d4");
+        assert_eq!(expression.terms, [
+            Term::Dice { count: 2, sides: 6, negative: false },
+            Term::Constant(3),
+            Term::Dice { count: 1, sides: 4, negative: true },
+        ]);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(parse_error("").contains("it's empty"));
+        assert!(parse_error("3+4").contains("no dice"));
+        assert!(parse_error("0d6").contains("1 or more rolls"));
+        assert!(parse_error("1d1").contains("2 to 1000 sides"));
+        assert!(parse_error("1d1001").contains("2 to 1000 sides"));
+        assert!(parse_error("2x6").contains("expected dice"));
+    }
+
+    #[test]
+    fn limits_the_number_of_dice() {
+        assert!("100d6".parse::<DiceExpression>().is_ok());
+        assert!("60d6+40d4".parse::<DiceExpression>().is_ok());
+        assert!(parse_error("101d6").contains("at most 100 dice"));
+        assert!(parse_error("60d6+41d4").contains("at most 100 dice"));
+        // Counts that don't fit the sum are rejected as soon as they are parsed
+        assert!(parse_error("4294967295d6+1d6").contains("at most 100 dice"));
+        assert!(parse_error("99999999999d6").contains("bad number of dice"));
+    }
+
+    #[test]
+    fn limits_the_modifier() {
+        assert!("1d6+1000000".parse::<DiceExpression>().is_ok());
+        assert!("1d6-1000000".parse::<DiceExpression>().is_ok());
+        assert!(parse_error("1d6+1000001").contains("at most 1000000"));
+        assert!(parse_error("1d6+600000+600000").contains("at most 1000000"));
+        assert!(parse_error("1d6+9223372036854775807+1").contains("at most 1000000"));
+    }
+
+    #[test]
+    fn rolls_within_bounds() {
+        let expression: DiceExpression = "3d6+2".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let roll = expression.roll(&mut rng);
+            assert_eq!(roll.rolls.len(), 3);
+            assert!(roll.rolls.iter().all(|r| (1..=6).contains(r)));
+            assert_eq!(roll.modifier, 2);
+            assert_eq!(roll.total, roll.rolls.iter().map(|r| i64::from(*r)).sum::<i64>() + 2);
+        }
+    }
+
+    #[test]
+    fn rolls_are_deterministic_with_a_seed() {
+        let expression: DiceExpression = "4d20-d8+1".parse().unwrap();
+        let first = expression.roll(&mut StdRng::seed_from_u64(42));
+        let second = expression.roll(&mut StdRng::seed_from_u64(42));
+        assert_eq!(first.rolls, second.rolls);
+        assert_eq!(first.total, second.total);
+        assert_eq!(first.describe(), second.describe());
+    }
+}
@@ -0,0 +1,107 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// World events happening in a room, usually loaded from a file next to the room file.
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EventScript {
+    /// Seed for the events with a probability, to replay the same run
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub events: Vec<ScriptedEvent>,
+}
+
+/// A scripted event, written with its trigger as a field, e.g. `{ text: The power goes out., after_messages: 10 }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptedEvent {
+    /// Narration of what happens, e.g. `The power goes out.`
+    pub text: String,
+    #[serde(flatten)]
+    pub trigger: EventTrigger,
+    /// Fire the event each time the trigger matches instead of only once
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+/// When an event fires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventTrigger {
+    /// At a simulated time of the room clock, as `YYYY-MM-DD HH:MM`
+    AtSimTime(#[serde(with = "world_time")] NaiveDateTime),
+    /// After a number of real seconds since the chat started
+    AfterSecs(f64),
+    /// After a number of chat messages in the room
+    AfterMessages(usize),
+    /// With a probability from 0 to 1 after each chat message
+    Probability(f64),
+}
+
+/// Simulated times written as `YYYY-MM-DD HH:MM`, rejecting anything else when loading.
+mod world_time {
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use crate::model::room_config::parse_world_time;
+
+    pub fn serialize<S: Serializer>(time: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time.format("%Y-%m-%d %H:%M"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDateTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        parse_world_time(&time).map_err(serde::de::Error::custom)
+    }
+}
+
+impl EventScript {
+    pub async fn load_from_yaml(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let script: EventScript = serde_yaml::from_str(&content).map_err(|e| format!("Invalid event script {}: {}", path, e))?;
+        let errors = script.validate();
+        if !errors.is_empty() {
+            return Err(format!("Invalid event script {}:\n{}", path, errors.join("\n")).into());
+        }
+        Ok(script)
+    }
+
+    /// Problems with the triggers of the events, which can't be caught when parsing.
+    pub fn validate(&self) -> Vec<String> {
+        self.events.iter()
+            .filter_map(|event| match event.trigger {
+                EventTrigger::Probability(p) if !(0.0..=1.0).contains(&p) =>
+                    Some(format!("event `{}` has the probability {}, which isn't from 0 to 1", event.text, p)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_probabilities_outside_0_to_1() {
+        let script: EventScript = serde_yaml::from_str("events:\n\
+            - { text: Fine., probability: 0.5 }\n\
+            - { text: Never., probability: .nan }\n\
+            - { text: Too likely., probability: 1.5 }\n\
+            - { text: Negative., probability: -0.1 }\n").unwrap();
+        assert_eq!(script.validate(), [
+            "event `Never.` has the probability NaN, which isn't from 0 to 1",
+            "event `Too likely.` has the probability 1.5, which isn't from 0 to 1",
+            "event `Negative.` has the probability -0.1, which isn't from 0 to 1",
+        ]);
+    }
+
+    #[test]
+    fn parses_the_simulated_times_when_loading() {
+        let script: EventScript = serde_yaml::from_str("events:\n- { text: Noon., at_sim_time: 2024-05-01 12:00 }\n").unwrap();
+        let noon = NaiveDateTime::parse_from_str("2024-05-01 12:00", "%Y-%m-%d %H:%M").unwrap();
+        assert!(matches!(script.events[0].trigger, EventTrigger::AtSimTime(at) if at == noon));
+        let error = serde_yaml::from_str::<EventScript>("events:\n- { text: Noon., at_sim_time: noon }\n").unwrap_err();
+        assert!(error.to_string().contains("invalid time `noon`"), "{}", error);
+    }
+}
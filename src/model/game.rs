@@ -0,0 +1,309 @@
+use rand::Rng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use std::collections::BTreeMap;
+use crate::model::room_config::GameConfig;
+
+/// Secret role of a player in a game of Werewolf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Villager,
+    /// Kills a villager each night with the other werewolves
+    Werewolf,
+    /// A villager who learns the role of a player each night
+    Seer,
+}
+
+impl Role {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Role::Villager => "a villager",
+            Role::Werewolf => "a werewolf",
+            Role::Seer => "the seer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The werewolves choose their victim and the seer a player to inspect
+    Night,
+    /// Everyone discusses and votes for a player to lynch
+    Day,
+}
+
+/// State of a game of Werewolf between the participants of a room.
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub roles: BTreeMap<String, Role>,
+    /// Ids of the players still alive, in seating order
+    pub alive: Vec<String>,
+    pub phase: Phase,
+    pub round: usize,
+    /// Votes of the current phase by voter id. At night, the vote of the seer is the player it inspects
+    pub votes: BTreeMap<String, String>,
+    /// What the seer learnt so far, as (player id, role)
+    pub inspections: Vec<(String, Role)>,
+    /// The human, if they watch the game instead of playing
+    pub spectator: Option<String>,
+    /// Set once a side won, after which the room goes back to a normal chat
+    pub winner: Option<Role>,
+}
+
+impl Game {
+    /// Deals the roles at random to the players.
+    pub fn new(players: &[String], config: &GameConfig, spectator: Option<String>, rng: &mut impl Rng) -> Result<Self, String> {
+        if config.werewolves == 0 {
+            return Err("a game needs at least one werewolf".to_string());
+        }
+        let specials = config.werewolves + usize::from(config.seer);
+        if config.werewolves * 2 >= players.len() || specials > players.len() {
+            return Err(format!("{} players are too few for {} werewolves", players.len(), config.werewolves));
+        }
+        let mut dealt = players.to_vec();
+        dealt.shuffle(rng);
+        let roles = dealt.into_iter().enumerate()
+            .map(|(i, id)| {
+                let role = if i < config.werewolves {
+                    Role::Werewolf
+                } else if i < specials {
+                    Role::Seer
+                } else {
+                    Role::Villager
+                };
+                (id, role)
+            })
+            .collect();
+        Ok(Game {
+            roles,
+            alive: players.to_vec(),
+            phase: Phase::Night,
+            round: 1,
+            votes: BTreeMap::new(),
+            inspections: Vec::new(),
+            spectator,
+            winner: None,
+        })
+    }
+
+    pub fn is_alive(&self, id: &str) -> bool {
+        self.alive.iter().any(|a| a == id)
+    }
+
+    /// Ids of the werewolves still alive.
+    pub fn werewolves(&self) -> Vec<String> {
+        self.alive.iter().filter(|id| self.roles.get(*id) == Some(&Role::Werewolf)).cloned().collect()
+    }
+
+    /// Id of the seer, if it's still alive.
+    pub fn seer(&self) -> Option<String> {
+        self.alive.iter().find(|id| self.roles.get(*id) == Some(&Role::Seer)).cloned()
+    }
+
+    /// Who votes in the current phase: everyone alive by day, the werewolves and the seer by night.
+    pub fn voters(&self) -> Vec<String> {
+        match self.phase {
+            Phase::Day => self.alive.clone(),
+            Phase::Night => self.werewolves().into_iter().chain(self.seer()).collect(),
+        }
+    }
+
+    /// Who the voter can vote for in the current phase.
+    pub fn candidates(&self, voter: &str) -> Vec<String> {
+        let wolf = self.roles.get(voter) == Some(&Role::Werewolf);
+        self.alive.iter()
+            .filter(|id| *id != voter)
+            .filter(|id| self.phase == Phase::Day || !wolf || self.roles.get(*id) != Some(&Role::Werewolf))
+            .cloned()
+            .collect()
+    }
+
+    pub fn vote(&mut self, voter: &str, target: &str) -> Result<(), String> {
+        let target = target.trim().trim_start_matches('@');
+        if !self.voters().iter().any(|v| v == voter) {
+            return Err(format!("@{} doesn't vote now", voter));
+        }
+        if !self.candidates(voter).iter().any(|c| c == target) {
+            return Err(format!("@{} can't vote for @{}", voter, target));
+        }
+        self.votes.insert(voter.to_string(), target.to_string());
+        Ok(())
+    }
+
+    /// The player with the most votes, ties broken at random. Only the werewolves' votes count at night.
+    pub fn tally(&self, rng: &mut impl Rng) -> Option<String> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for (voter, target) in self.votes.iter() {
+            if self.phase == Phase::Day || self.roles.get(voter) == Some(&Role::Werewolf) {
+                *counts.entry(target.as_str()).or_default() += 1;
+            }
+        }
+        let max = counts.values().max()?;
+        let tied: Vec<&str> = counts.iter().filter(|(_, c)| *c == max).map(|(id, _)| *id).collect();
+        tied.choose(rng).map(|id| id.to_string())
+    }
+
+    pub fn eliminate(&mut self, id: &str) {
+        self.alive.retain(|a| a != id);
+    }
+
+    /// The winning side, if the game is decided: the villagers once all werewolves are dead,
+    /// the werewolves once they are as many as the others.
+    pub fn check_winner(&self) -> Option<Role> {
+        let wolves = self.werewolves().len();
+        if wolves == 0 {
+            Some(Role::Villager)
+        } else if wolves * 2 >= self.alive.len() {
+            Some(Role::Werewolf)
+        } else {
+            None
+        }
+    }
+
+    /// Who hears the player: everyone by day, the other werewolves by night.
+    /// Fails if the player can't talk now.
+    pub fn listeners(&self, id: &str) -> Result<Option<Vec<String>>, String> {
+        if self.winner.is_some() {
+            return Ok(None);
+        }
+        if self.spectator.as_deref() == Some(id) {
+            return Err("spectators don't take part in the game".to_string());
+        }
+        if !self.is_alive(id) {
+            return Err("dead players can't talk".to_string());
+        }
+        match self.phase {
+            Phase::Day => Ok(None),
+            Phase::Night if self.roles.get(id) == Some(&Role::Werewolf) => Ok(Some(self.werewolves().into_iter()
+                .filter(|w| w != id)
+                .chain(self.spectator.clone())
+                .collect())),
+            Phase::Night => Err("only the werewolves talk at night".to_string()),
+        }
+    }
+
+    /// Everyone's role, e.g. `@alice: a werewolf, @bob: the seer`.
+    pub fn describe_roles(&self) -> String {
+        self.roles.iter().map(|(id, role)| format!("@{}: {}", id, role.describe())).collect::<Vec<_>>().join(", ")
+    }
+
+    /// What the player knows about the game, for its prompt.
+    pub fn briefing(&self, id: &str) -> Option<String> {
+        let role = self.roles.get(id)?;
+        let list = |ids: Vec<String>| if ids.is_empty() {
+            "none".to_string()
+        } else {
+            ids.iter().map(|i| format!("@{}", i)).collect::<Vec<_>>().join(", ")
+        };
+        let mut lines = vec![format!("The profile plays a game of Werewolf. Its secret role is {}.", role.describe())];
+        match role {
+            Role::Werewolf => lines.push(format!("The other werewolves are {}. Werewolves win once they are as many as the others: \
+                kill a villager each night, and pretend to be a villager during the day.",
+                list(self.roles.iter().filter(|(w, r)| **r == Role::Werewolf && *w != id).map(|(w, _)| w.clone()).collect()))),
+            Role::Seer => lines.push(format!("Villagers win once all the werewolves are dead. Each night the seer learns the role of a player. \
+                So far it learnt: {}.",
+                if self.inspections.is_empty() {
+                    "nothing".to_string()
+                } else {
+                    self.inspections.iter().map(|(p, r)| format!("@{} is {}", p, r.describe())).collect::<Vec<_>>().join(", ")
+                })),
+            Role::Villager => lines.push("Villagers win once all the werewolves are dead: find them and vote against them.".to_string()),
+        }
+        let phase = match self.phase {
+            Phase::Night => "night",
+            Phase::Day => "day",
+        };
+        let dead: Vec<String> = self.roles.iter()
+            .filter(|(p, _)| !self.is_alive(p))
+            .map(|(p, r)| format!("@{} ({})", p, r.describe()))
+            .collect();
+        lines.push(format!("It's {} {}. Alive: {}. Dead: {}.", phase, self.round, list(self.alive.clone()),
+            if dead.is_empty() { "none".to_string() } else { dead.join(", ") }));
+        if !self.is_alive(id) {
+            lines.push("The profile is dead and can only watch.".to_string());
+        }
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    /// A game at night with `wolf` and `fang` as werewolves, `seer` as the seer and the others villagers.
+    fn game(players: &[&str]) -> Game {
+        let roles = players.iter().map(|id| {
+            let role = match *id {
+                "wolf" | "fang" => Role::Werewolf,
+                "seer" => Role::Seer,
+                _ => Role::Villager,
+            };
+            (id.to_string(), role)
+        }).collect();
+        Game {
+            roles,
+            alive: players.iter().map(|id| id.to_string()).collect(),
+            phase: Phase::Night,
+            round: 1,
+            votes: BTreeMap::new(),
+            inspections: Vec::new(),
+            spectator: None,
+            winner: None,
+        }
+    }
+
+    #[test]
+    fn tally_breaks_ties_with_the_rng() {
+        let mut game = game(&["wolf", "seer", "ann", "bob", "cid"]);
+        game.phase = Phase::Day;
+        for (voter, target) in [("wolf", "ann"), ("seer", "wolf"), ("ann", "wolf"), ("bob", "ann"), ("cid", "bob")] {
+            game.vote(voter, target).unwrap();
+        }
+        let picks: Vec<String> = (0..20).map(|seed| game.tally(&mut StdRng::seed_from_u64(seed)).unwrap()).collect();
+        assert!(picks.iter().all(|p| p == "ann" || p == "wolf"), "{:?}", picks);
+        assert!(picks.contains(&"ann".to_string()) && picks.contains(&"wolf".to_string()), "{:?}", picks);
+        // The same seed always picks the same player
+        assert_eq!(game.tally(&mut StdRng::seed_from_u64(7)), game.tally(&mut StdRng::seed_from_u64(7)));
+
+        game.votes.clear();
+        assert_eq!(game.tally(&mut StdRng::seed_from_u64(0)), None);
+    }
+
+    #[test]
+    fn tally_only_counts_the_werewolves_at_night() {
+        let mut game = game(&["wolf", "fang", "seer", "ann", "bob"]);
+        game.vote("wolf", "ann").unwrap();
+        game.vote("fang", "ann").unwrap();
+        game.vote("seer", "bob").unwrap();
+        assert_eq!(game.tally(&mut StdRng::seed_from_u64(0)), Some("ann".to_string()));
+    }
+
+    #[test]
+    fn werewolves_win_at_parity_and_villagers_once_they_are_dead() {
+        let mut game = game(&["wolf", "fang", "seer", "ann", "bob"]);
+        assert_eq!(game.check_winner(), None);
+        game.eliminate("bob");
+        assert_eq!(game.check_winner(), Some(Role::Werewolf));
+
+        let mut game = self::game(&["wolf", "seer", "ann"]);
+        assert_eq!(game.check_winner(), None);
+        game.eliminate("wolf");
+        assert_eq!(game.check_winner(), Some(Role::Villager));
+    }
+
+    #[test]
+    fn candidates_exclude_the_dead_and_the_other_werewolves_at_night() {
+        let mut game = game(&["wolf", "fang", "seer", "ann", "bob"]);
+        game.eliminate("bob");
+        assert_eq!(game.candidates("wolf"), ["seer", "ann"]);
+        assert_eq!(game.candidates("seer"), ["wolf", "fang", "ann"]);
+        assert_eq!(game.vote("seer", "@bob").unwrap_err(), "@seer can't vote for @bob");
+        assert_eq!(game.vote("ann", "wolf").unwrap_err(), "@ann doesn't vote now");
+        game.vote("seer", "@fang").unwrap();
+        assert_eq!(game.votes.get("seer").map(String::as_str), Some("fang"));
+
+        game.phase = Phase::Day;
+        assert_eq!(game.candidates("wolf"), ["fang", "seer", "ann"]);
+    }
+}
@@ -0,0 +1,177 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// A collection of world knowledge entries, injected into the prompts when relevant.
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Lorebook {
+    #[serde(default)]
+    pub entries: Vec<LoreEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoreEntry {
+    /// Short name shown in the chat when the entry is activated
+    pub name: String,
+    /// The entry is activated when one of the keywords appears in the recent messages
+    #[serde(default)]
+    pub keywords: Keywords,
+    pub content: String,
+    /// Entries with a higher priority are kept first when the token budget is short
+    #[serde(default)]
+    pub priority: i32,
+    /// Always include the entry, whatever the keywords
+    #[serde(default)]
+    pub always_on: bool,
+}
+
+impl Lorebook {
+    pub async fn load_from_yaml(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = tokio::fs::read_to_string(path).await?;
+        Ok(serde_yaml::from_str(&content).map_err(|e| format!("Invalid lorebook {}: {}", path, e))?)
+    }
+}
+
+/// The keywords of an entry, with the regex matching them compiled once when the entry is loaded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct Keywords {
+    words: Vec<String>,
+    /// Matches any of the non-blank keywords as a whole word, ignoring case. `None` without any.
+    regex: Option<Regex>,
+}
+
+impl TryFrom<Vec<String>> for Keywords {
+    type Error = regex::Error;
+
+    fn try_from(words: Vec<String>) -> Result<Self, Self::Error> {
+        let alternatives: Vec<String> = words.iter()
+            .map(|k| k.trim())
+            .filter(|k| !k.is_empty())
+            .map(regex::escape)
+            .collect();
+        let regex = if alternatives.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives.join("|")))?)
+        };
+        Ok(Keywords { words, regex })
+    }
+}
+
+impl From<Keywords> for Vec<String> {
+    fn from(keywords: Keywords) -> Self {
+        keywords.words
+    }
+}
+
+impl PartialEq for Keywords {
+    fn eq(&self, other: &Self) -> bool {
+        self.words == other.words
+    }
+}
+
+impl LoreEntry {
+    /// Whether one of the keywords appears as a whole word in the text, ignoring case.
+    pub fn matches(&self, text: &str) -> bool {
+        self.keywords.regex.as_ref().is_some_and(|re| re.is_match(text))
+    }
+}
+
+/// Rough number of tokens in the text, assuming about 4 characters per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Text searched for keywords: the last `scan_depth` messages, most recent first,
+/// then the context searched whatever the depth, like the narrated scene.
+pub fn scanned_text<'a>(messages: impl DoubleEndedIterator<Item = &'a str>, context: impl IntoIterator<Item = &'a str>, scan_depth: usize) -> String {
+    messages.rev().take(scan_depth)
+        .chain(context)
+        .collect::<Vec<_>>().join("\n")
+}
+
+/// Picks the entries to include in a prompt: always-on entries and the ones triggered
+/// by the text, by priority, as long as they fit in the token budget.
+pub fn activate<'a>(entries: impl IntoIterator<Item = &'a LoreEntry>, text: &str, token_budget: usize) -> Vec<&'a LoreEntry> {
+    let mut candidates: Vec<&LoreEntry> = entries.into_iter()
+        .filter(|e| e.always_on || e.matches(text))
+        .collect();
+    candidates.sort_by_key(|e| (!e.always_on, -e.priority));
+    let mut remaining = token_budget;
+    candidates.into_iter()
+        .filter(|e| {
+            let tokens = estimate_tokens(&e.content);
+            let fits = tokens <= remaining;
+            if fits {
+                remaining -= tokens;
+            }
+            fits
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, keywords: &[&str], tokens: usize, priority: i32) -> LoreEntry {
+        LoreEntry {
+            name: name.to_string(),
+            keywords: keywords.iter().map(|k| k.to_string()).collect::<Vec<_>>().try_into().unwrap(),
+            content: "x".repeat(tokens * 4),
+            priority,
+            always_on: false,
+        }
+    }
+
+    fn names(entries: Vec<&LoreEntry>) -> Vec<&str> {
+        entries.into_iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn matches_keywords_as_whole_words_ignoring_case() {
+        let dragon = entry("dragon", &["dragon", " Red Keep ", ""], 1, 0);
+        assert!(dragon.matches("A DRAGON flew over"));
+        assert!(dragon.matches("We reach the red keep at dawn"));
+        assert!(!dragon.matches("The dragonfly and the keep"));
+        assert!(!entry("none", &[" "], 1, 0).matches("anything"));
+    }
+
+    #[test]
+    fn loads_keywords_from_yaml() {
+        let entry: LoreEntry = serde_yaml::from_str("name: Inn\nkeywords: [inn, tavern]\ncontent: The Prancing Pony\n").unwrap();
+        assert!(entry.matches("Meet me at the tavern"));
+        assert_eq!(serde_yaml::to_value(&entry.keywords).unwrap(), serde_yaml::from_str::<serde_yaml::Value>("[inn, tavern]").unwrap());
+    }
+
+    #[test]
+    fn activates_always_on_then_by_priority_within_the_budget() {
+        let mut always = entry("always", &[], 2, -5);
+        always.always_on = true;
+        let entries = [
+            entry("low", &["ship"], 2, 1),
+            entry("high", &["ship"], 3, 9),
+            entry("big", &["ship"], 10, 5),
+            entry("unrelated", &["castle"], 1, 10),
+            always,
+        ];
+        assert_eq!(names(activate(&entries, "The ship sails", 100)), ["always", "high", "big", "low"]);
+        // `big` doesn't fit in what's left after `always` and `high`, the smaller `low` still does
+        assert_eq!(names(activate(&entries, "The ship sails", 8)), ["always", "high", "low"]);
+        assert!(activate(&entries, "The ship sails", 1).is_empty());
+    }
+
+    #[test]
+    fn scans_the_recent_messages_and_the_context() {
+        let messages = ["Talk of the castle", "Hello", "Nice weather"];
+        let scanned = scanned_text(messages.into_iter(), ["The ship docks"], 2);
+        assert_eq!(scanned, "Nice weather\nHello\nThe ship docks");
+
+        let entries = [entry("castle", &["castle"], 1, 0), entry("ship", &["ship"], 1, 0)];
+        assert_eq!(names(activate(&entries, &scanned, 10)), ["ship"]);
+        assert_eq!(names(activate(&entries, &scanned_text(messages.into_iter(), [], 3), 10)), ["castle"]);
+    }
+}
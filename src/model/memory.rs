@@ -0,0 +1,129 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Words too common to tell memories apart.
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "are", "but", "not", "you", "all", "any", "can", "had", "her", "was", "one", "our",
+    "out", "has", "him", "his", "how", "its", "who", "did", "yes", "she", "they", "them", "this", "that",
+    "with", "have", "from", "what", "when", "will", "would", "there", "their", "about", "been", "were",
+];
+
+/// Something a profile remembers from a past session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Memory {
+    pub kind: MemoryKind,
+    pub content: String,
+    /// Session the memory was extracted from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub created_at: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryKind {
+    /// About someone the profile talked with
+    Person,
+    /// Something that happened
+    Event,
+    /// Something the profile or someone else promised to do
+    Promise,
+    Fact,
+}
+
+fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(|w| w.to_lowercase())
+        .filter(|w| w.chars().count() >= 3 && !STOP_WORDS.contains(&w.as_str()))
+        .collect()
+}
+
+/// Picks the memories sharing the most distinctive words with the query text, best first.
+/// Words that are rare among the memories weigh more, and long memories don't win just by being long.
+pub fn retrieve<'a>(memories: &'a [Memory], query: &str, limit: usize) -> Vec<&'a Memory> {
+    let query_terms: HashSet<String> = terms(query).into_iter().collect();
+    let memory_terms: Vec<Vec<String>> = memories.iter().map(|m| terms(&m.content)).collect();
+    let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+    for t in memory_terms.iter() {
+        for term in t.iter().collect::<HashSet<_>>() {
+            *document_frequency.entry(term.as_str()).or_default() += 1;
+        }
+    }
+    let count = memories.len() as f64;
+    let mut scored: Vec<(f64, &Memory)> = memories.iter().zip(memory_terms.iter())
+        .map(|(memory, t)| {
+            let matched: f64 = query_terms.iter()
+                .filter(|q| t.contains(q))
+                .map(|q| (1.0 + count / document_frequency[q.as_str()] as f64).ln())
+                .sum();
+            (matched / (t.len().max(1) as f64).sqrt(), memory)
+        })
+        .filter(|(score, _)| *score > 0.0)
+        .collect();
+    // Newer memories first when the scores are equal
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.created_at.cmp(&a.1.created_at)));
+    scored.into_iter().take(limit).map(|(_, m)| m).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn memory(content: &str, day: u32) -> Memory {
+        Memory {
+            kind: MemoryKind::Fact,
+            content: content.to_string(),
+            session_id: None,
+            created_at: Local.with_ymd_and_hms(2024, 5, day, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn contents(memories: Vec<&Memory>) -> Vec<&str> {
+        memories.into_iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn ranks_rare_words_higher_and_long_memories_lower() {
+        let memories = [
+            memory("Alice loves sailing boats", 1),
+            memory("Bob loves cooking", 2),
+            memory("Carol loves sailing and cooking pasta every sunday evening with friends", 3),
+        ];
+        // `pasta` is only in Carol's memory, so it outweighs her memory being long
+        assert_eq!(contents(retrieve(&memories, "Who loves pasta?", 10)), [
+            "Carol loves sailing and cooking pasta every sunday evening with friends",
+            "Bob loves cooking",
+            "Alice loves sailing boats",
+        ]);
+        // With the same word matched, the shorter memory wins
+        assert_eq!(contents(retrieve(&memories, "SAILING", 10)), [
+            "Alice loves sailing boats",
+            "Carol loves sailing and cooking pasta every sunday evening with friends",
+        ]);
+    }
+
+    #[test]
+    fn keeps_at_most_the_limit() {
+        let memories = [memory("Bob loves cooking", 1), memory("Bob hates cooking", 2), memory("Bob went fishing", 3)];
+        assert_eq!(retrieve(&memories, "bob", 2).len(), 2);
+        assert!(retrieve(&memories, "bob", 0).is_empty());
+    }
+
+    #[test]
+    fn puts_newer_memories_first_on_ties() {
+        let memories = [memory("The ship sank", 1), memory("The ship sank", 9), memory("The ship sank", 5)];
+        let days: Vec<String> = retrieve(&memories, "ship", 10).iter().map(|m| m.created_at.format("%d").to_string()).collect();
+        assert_eq!(days, ["09", "05", "01"]);
+    }
+
+    #[test]
+    fn finds_nothing_without_distinctive_words() {
+        let memories = [memory("Bob loves cooking", 1)];
+        assert!(retrieve(&memories, "", 10).is_empty());
+        assert!(retrieve(&memories, "What was that about them?", 10).is_empty());
+        assert!(retrieve(&[], "cooking", 10).is_empty());
+    }
+}
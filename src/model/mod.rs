@@ -1 +1,15 @@
-pub mod profile;
\ No newline at end of file
+pub mod profile;
+pub mod session;
+pub mod lorebook;
+pub mod memory;
+pub mod relationship;
+pub mod profile_state;
+pub mod schedule;
+pub mod event_script;
+pub mod world;
+pub mod game;
+pub mod dice;
+pub mod character_sheet;
+pub mod poll;
+pub mod mood;
+pub mod room_config;
@@ -0,0 +1,142 @@
+use chrono::TimeDelta;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Time for a mood to get halfway back to the usual mood of the profile.
+const HALF_LIFE: TimeDelta = TimeDelta::minutes(10);
+/// Emotions weaker than this are forgotten.
+const MIN_EMOTION: f32 = 0.05;
+
+/// How a profile feels at the moment, whoever it's talking to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Mood {
+    /// From -1 (miserable) to 1 (elated)
+    #[serde(default)]
+    pub valence: f32,
+    /// From -1 (drowsy, calm) to 1 (agitated, excited)
+    #[serde(default)]
+    pub arousal: f32,
+    /// Named emotions by intensity from 0 to 1, e.g. `curiosity: 0.6`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub emotions: BTreeMap<String, f32>,
+}
+
+impl Mood {
+    /// Applies a change, keeping the values in range. Emotions change by their own amounts.
+    pub fn adjust(&mut self, valence: f32, arousal: f32, emotions: &BTreeMap<String, f32>) {
+        self.valence = (self.valence + valence).clamp(-1.0, 1.0);
+        self.arousal = (self.arousal + arousal).clamp(-1.0, 1.0);
+        for (name, change) in emotions {
+            let intensity = self.emotions.entry(name.trim().to_lowercase()).or_default();
+            *intensity = (*intensity + change).clamp(0.0, 1.0);
+        }
+        self.emotions.retain(|_, intensity| *intensity >= MIN_EMOTION);
+    }
+
+    /// The mood after some time, moving back toward the usual mood of the profile.
+    pub fn decayed(&self, usual: &Mood, elapsed: TimeDelta) -> Mood {
+        let kept = 0.5f32.powf(elapsed.as_seconds_f32().max(0.0) / HALF_LIFE.as_seconds_f32());
+        let toward = |current: f32, target: f32| target + (current - target) * kept;
+        let mut emotions = BTreeMap::new();
+        for name in self.emotions.keys().chain(usual.emotions.keys()) {
+            let current = self.emotions.get(name).copied().unwrap_or(0.0);
+            let target = usual.emotions.get(name).copied().unwrap_or(0.0);
+            let intensity = toward(current, target);
+            if intensity >= MIN_EMOTION {
+                emotions.insert(name.clone(), intensity);
+            }
+        }
+        Mood { valence: toward(self.valence, usual.valence), arousal: toward(self.arousal, usual.arousal), emotions }
+    }
+
+    /// How likely the profile is to speak compared to a neutral one, from 0.5 when drowsy to 1.5 when excited.
+    pub fn talkativeness(&self) -> f32 {
+        1.0 + self.arousal / 2.0
+    }
+
+    /// The strongest emotions first.
+    pub fn strongest_emotions(&self, count: usize) -> Vec<(&String, f32)> {
+        let mut emotions: Vec<(&String, f32)> = self.emotions.iter().map(|(name, i)| (name, *i)).collect();
+        emotions.sort_by(|a, b| b.1.total_cmp(&a.1));
+        emotions.truncate(count);
+        emotions
+    }
+
+    /// Describes the mood in words for the prompt of its profile, e.g. `you feel happy and restless, with strong curiosity`.
+    pub fn describe(&self) -> String {
+        let valence = match self.valence {
+            v if v <= -0.6 => "miserable",
+            v if v <= -0.2 => "down",
+            v if v < 0.2 => "neither happy nor unhappy",
+            v if v < 0.6 => "happy",
+            _ => "elated",
+        };
+        let arousal = match self.arousal {
+            a if a <= -0.6 => "drowsy",
+            a if a <= -0.2 => "calm",
+            a if a < 0.2 => "alert",
+            a if a < 0.6 => "restless",
+            _ => "agitated",
+        };
+        let emotions: Vec<String> = self.strongest_emotions(3).into_iter()
+            .map(|(name, intensity)| {
+                let strength = match intensity {
+                    i if i < 0.3 => "a little",
+                    i if i < 0.7 => "some",
+                    _ => "strong",
+                };
+                format!("{} {}", strength, name)
+            })
+            .collect();
+        if emotions.is_empty() {
+            format!("you feel {} and {}", valence, arousal)
+        } else {
+            format!("you feel {} and {}, with {}", valence, arousal, emotions.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emotions(pairs: &[(&str, f32)]) -> BTreeMap<String, f32> {
+        pairs.iter().map(|(name, i)| (name.to_string(), *i)).collect()
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn adjust_keeps_values_in_range() {
+        let mut mood = Mood { valence: 0.9, arousal: -0.9, emotions: emotions(&[("joy", 0.5), ("fear", 0.1)]) };
+        mood.adjust(0.3, -0.3, &emotions(&[(" Joy ", 0.7), ("fear", -0.08), ("anger", -0.2), ("curiosity", 0.2)]));
+        assert_eq!(mood.valence, 1.0);
+        assert_eq!(mood.arousal, -1.0);
+        // Weak emotions are forgotten, and names are normalized
+        assert_eq!(mood.emotions, emotions(&[("curiosity", 0.2), ("joy", 1.0)]));
+    }
+
+    #[test]
+    fn decayed_moves_halfway_to_the_usual_mood_each_half_life() {
+        let usual = Mood { valence: 0.2, arousal: 0.0, emotions: emotions(&[("calm", 0.4)]) };
+        let mood = Mood { valence: 1.0, arousal: -0.8, emotions: emotions(&[("anger", 0.8), ("fear", 0.08)]) };
+
+        let half = mood.decayed(&usual, HALF_LIFE);
+        assert_near(half.valence, 0.6);
+        assert_near(half.arousal, -0.4);
+        assert_eq!(half.emotions.keys().collect::<Vec<_>>(), ["anger", "calm"]);
+        assert_near(half.emotions["anger"], 0.4);
+        assert_near(half.emotions["calm"], 0.2);
+
+        let later = mood.decayed(&usual, TimeDelta::days(1));
+        assert_near(later.valence, usual.valence);
+        assert_eq!(later.emotions.keys().collect::<Vec<_>>(), ["calm"]);
+
+        // A clock going backward doesn't push the mood away from the usual one
+        assert_eq!(mood.decayed(&usual, TimeDelta::minutes(-5)).valence, mood.valence);
+        assert_eq!(mood.decayed(&usual, TimeDelta::zero()), mood);
+    }
+}
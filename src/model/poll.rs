@@ -0,0 +1,119 @@
+use serde::Deserialize;
+
+/// A question put to every agent of the room, with the options they choose from.
+#[derive(Debug, Clone)]
+pub struct Poll {
+    pub question: String,
+    pub options: Vec<String>,
+}
+
+/// The vote of an agent, as the arguments of its call of the vote tool.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Ballot {
+    /// One of the options of the poll
+    pub choice: String,
+    /// Why the agent chose it, in its own voice
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// The ballots of a poll, by voter. Voters without a valid ballot abstained, e.g. when their LLM failed.
+#[derive(Debug, Clone)]
+pub struct PollResult {
+    pub poll: Poll,
+    pub ballots: Vec<(String, Option<Ballot>)>,
+}
+
+impl Poll {
+    pub fn new(question: String, options: Vec<String>) -> Result<Self, String> {
+        if question.trim().is_empty() {
+            return Err("The poll has no question".to_string());
+        }
+        if options.len() < 2 {
+            return Err("A poll needs at least 2 options".to_string());
+        }
+        if let Some((i, option)) = options.iter().enumerate()
+            .find(|(i, o)| options[..*i].iter().any(|other| other.eq_ignore_ascii_case(o))) {
+            return Err(format!("Option {} `{}` is given twice", i + 1, option));
+        }
+        Ok(Poll { question, options })
+    }
+
+    /// The option the ballot chose, written as in the poll. Returns `None` if it isn't one of the options.
+    pub fn option_of(&self, ballot: &Ballot) -> Option<&String> {
+        let choice = ballot.choice.trim().trim_matches(['"', '`', '.']);
+        self.options.iter().find(|o| o.eq_ignore_ascii_case(choice))
+    }
+}
+
+impl PollResult {
+    /// Number of votes of each option, in the order of the poll.
+    pub fn tally(&self) -> Vec<(&String, usize)> {
+        self.poll.options.iter()
+            .map(|option| (option, self.ballots.iter()
+                .filter(|(_, b)| b.as_ref().is_some_and(|b| &b.choice == option))
+                .count()))
+            .collect()
+    }
+
+    /// The result in a few lines for the transcript: the tally, then each ballot with its reason.
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("Poll: {}", self.poll.question)];
+        lines.extend(self.tally().into_iter().map(|(option, votes)| format!("{}: {} vote(s)", option, votes)));
+        for (voter, ballot) in self.ballots.iter() {
+            lines.push(match ballot {
+                Some(ballot) => format!("@{} votes {}: {}", voter, ballot.choice, ballot.reason),
+                None => format!("@{} abstains", voter),
+            });
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll() -> Poll {
+        Poll::new("Where to eat?".to_string(), vec!["Pizza".to_string(), "Sushi".to_string(), "Tacos".to_string()]).unwrap()
+    }
+
+    fn ballot(choice: &str) -> Ballot {
+        Ballot { choice: choice.to_string(), reason: String::new() }
+    }
+
+    #[test]
+    fn new_needs_a_question_and_distinct_options() {
+        let options = |o: &[&str]| o.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        assert_eq!(Poll::new(" ".to_string(), options(&["a", "b"])).unwrap_err(), "The poll has no question");
+        assert_eq!(Poll::new("Q?".to_string(), options(&["a"])).unwrap_err(), "A poll needs at least 2 options");
+        assert_eq!(Poll::new("Q?".to_string(), options(&["a", "b", "A"])).unwrap_err(), "Option 3 `A` is given twice");
+        assert_eq!(Poll::new("Q?".to_string(), options(&["a", "b"])).unwrap().options, ["a", "b"]);
+    }
+
+    #[test]
+    fn option_of_ignores_case_quotes_and_trailing_dots() {
+        let poll = poll();
+        assert_eq!(poll.option_of(&ballot("Sushi")), Some(&"Sushi".to_string()));
+        assert_eq!(poll.option_of(&ballot(" \"tacos\". ")), Some(&"Tacos".to_string()));
+        assert_eq!(poll.option_of(&ballot("`PIZZA`")), Some(&"Pizza".to_string()));
+        assert_eq!(poll.option_of(&ballot("Burgers")), None);
+        assert_eq!(poll.option_of(&ballot("Sush")), None);
+    }
+
+    #[test]
+    fn tally_counts_the_votes_of_each_option_in_order() {
+        let result = PollResult {
+            poll: poll(),
+            ballots: vec![
+                ("ann".to_string(), Some(ballot("Sushi"))),
+                ("bob".to_string(), None),
+                ("cy".to_string(), Some(ballot("Sushi"))),
+                ("dee".to_string(), Some(ballot("Pizza"))),
+            ],
+        };
+        let tally: Vec<(&str, usize)> = result.tally().into_iter().map(|(o, n)| (o.as_str(), n)).collect();
+        assert_eq!(tally, [("Pizza", 1), ("Sushi", 2), ("Tacos", 0)]);
+        assert!(result.describe().contains("@bob abstains"));
+    }
+}
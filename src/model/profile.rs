@@ -1,8 +1,57 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::model::character_sheet::CharacterSheet;
+use crate::model::lorebook::{self, LoreEntry};
+use crate::model::mood::Mood;
+use crate::model::relationship::Relationship;
+use crate::model::schedule::ScheduleEntry;
+
+/// Version of the profile format written by this build. Older documents are upgraded on load.
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+/// Profile fields together with the guidance written above them in profile templates.
+pub const PROFILE_FIELD_GUIDE: &[(&str, &str)] = &[
+    ("schema_version", "Version of the profile format. Upgraded by `migrate-profiles`, don't edit it by hand."),
+    ("extends", "Optional id of a profile to inherit from. This profile's fields are merged on top of it."),
+    ("include", "Optional list of shared fragment files, by name without the `.yaml` extension, merged before this profile."),
+    ("replace", "Optional list of list fields that replace the inherited lists instead of being appended to them.\n\
+        Lists inside a mapping are named by their path, e.g. `sheet.inventory`."),
+    ("id", "Unique id of the profile. Must match the file name without the `.yaml` extension."),
+    ("name", "Display name used in the chat, e.g. `Captain Ahab`. Must not be empty."),
+    ("background", "Who the character is: history, personality, goals and the way they see the world.\n\
+        Write it in plain prose. Use `|` for multi-line text. Must not be empty."),
+    ("conversation_examples", "Sample messages written in the character's voice, one list item per message."),
+    ("lorebook", "Private knowledge of the character, shown to it when relevant. Each entry has a `name`, `keywords`,\n\
+        `content`, and optionally a `priority` and `always_on: true` to include it whatever the keywords."),
+    ("relationships", "Optional feelings toward other participants by their id, e.g. `bob: { affinity: 0.5, trust: -0.3,\n\
+        description: childhood friend }`. Affinity and trust go from -1 to 1 and change during the chats."),
+    ("schedule", "Optional daily routine, e.g. `- { from: \"22:00\", to: \"07:00\", activity: sleeping, availability: asleep }`.\n\
+        Availability is one of available, busy, away or asleep. Away and asleep profiles don't take part in the chat."),
+    ("sheet", "Optional character sheet for tabletop role-play, e.g. `{ hp: 10, max_hp: 10, stats: { strength: 14 },\n\
+        inventory: [rope] }`. HP and inventory change during the chats when the room plays with dice."),
+    ("mood", "Optional usual mood, e.g. `{ valence: 0.3, arousal: -0.2, emotions: { curiosity: 0.5 } }`. Valence and arousal\n\
+        go from -1 to 1, emotions from 0 to 1. The mood changes during the chats and settles back to this one over time."),
+    ("llm_provider", "LLM provider used for this profile. Leave empty to use the chat default."),
+    ("llm_model", "LLM model used for this profile. Leave empty to use the chat default."),
+];
 
 /// A bot profile containing personal information and conversation examples.
-#[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Profile {
+    pub schema_version: u32,
+
+    /// Id of the profile this one inherits from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+
+    /// Names of the shared fragments merged into this profile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// List fields that replace inherited lists instead of being appended to them, nested ones by their dotted path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replace: Vec<String>,
+
     pub id: String,
 
     /// The user's display name or username
@@ -14,6 +63,140 @@ pub struct Profile {
     /// Sample conversations or phrases that represent the user's communication style
     pub conversation_examples: Vec<String>,
 
+    /// Knowledge only this profile has, injected into its prompt when relevant
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lorebook: Vec<LoreEntry>,
+
+    /// Initial relationships with other participants by their id
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub relationships: BTreeMap<String, Relationship>,
+
+    /// What the profile does during the day, used with the room clock
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduleEntry>,
+
+    /// Stats, hit points and inventory, used when the room plays a tabletop RPG
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sheet: Option<CharacterSheet>,
+
+    /// Usual mood of the profile, which its mood settles back to after changing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mood: Option<Mood>,
+
+    #[serde(default)]
     pub llm_provider: String,
+    #[serde(default)]
     pub llm_model: String,
 }
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            extends: None,
+            include: Vec::new(),
+            replace: Vec::new(),
+            id: String::new(),
+            name: String::new(),
+            background: String::new(),
+            conversation_examples: Vec::new(),
+            lorebook: Vec::new(),
+            relationships: BTreeMap::new(),
+            schedule: Vec::new(),
+            sheet: None,
+            mood: None,
+            llm_provider: String::new(),
+            llm_model: String::new(),
+        }
+    }
+}
+
+impl Profile {
+    /// Checks the content of the profile, returning a description of every problem found.
+    pub fn validate(&self, expected_id: &str) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.id != expected_id {
+            errors.push(format!("id `{}` doesn't match the file name `{}`", self.id, expected_id));
+        }
+        if self.name.trim().is_empty() {
+            errors.push("name must not be empty".to_string());
+        }
+        if self.background.trim().is_empty() {
+            errors.push("background must not be empty".to_string());
+        }
+        if let Some(sheet) = &self.sheet
+            && sheet.hp > sheet.max_hp {
+            errors.push(format!("sheet: hp {} is above max_hp {}", sheet.hp, sheet.max_hp));
+        }
+        if let Some(mood) = &self.mood {
+            if !(-1.0..=1.0).contains(&mood.valence) || !(-1.0..=1.0).contains(&mood.arousal) {
+                errors.push("mood: valence and arousal must be between -1 and 1".to_string());
+            }
+            if let Some((name, _)) = mood.emotions.iter().find(|(_, i)| !(0.0..=1.0).contains(*i)) {
+                errors.push(format!("mood: emotion `{}` must be between 0 and 1", name));
+            }
+        }
+        errors
+    }
+
+    /// The conversation examples to show the LLM, in order, as long as they fit in the token budget.
+    pub fn examples_within(&self, token_budget: usize) -> Vec<&String> {
+        let mut remaining = token_budget;
+        self.conversation_examples.iter()
+            .filter(|example| {
+                let tokens = lorebook::estimate_tokens(example);
+                let fits = tokens <= remaining;
+                if fits {
+                    remaining -= tokens;
+                }
+                fits
+            })
+            .collect()
+    }
+
+    /// Names of the fields with a different value in the other profile.
+    pub fn changed_fields(&self, other: &Profile) -> Vec<String> {
+        match (serde_json::to_value(self), serde_json::to_value(other)) {
+            // Fields left empty aren't serialized, so a field may be on either side only
+            (Ok(serde_json::Value::Object(fields)), Ok(serde_json::Value::Object(other_fields))) => fields.keys()
+                .chain(other_fields.keys().filter(|name| !fields.contains_key(*name)))
+                .filter(|name| fields.get(*name) != other_fields.get(*name))
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples_within_keeps_the_examples_that_fit_in_order() {
+        let profile = Profile {
+            conversation_examples: vec!["a".repeat(40), "b".repeat(400), "c".repeat(20)],
+            ..Profile::default()
+        };
+        // 10 + 100 + 5 tokens
+        assert_eq!(profile.examples_within(20), [&"a".repeat(40), &"c".repeat(20)]);
+        assert_eq!(profile.examples_within(1000).len(), 3);
+        assert!(profile.examples_within(0).is_empty());
+    }
+
+    #[test]
+    fn changed_fields_includes_the_fields_set_on_either_side_only() {
+        let profile = Profile { id: "ann".to_string(), name: "Ann".to_string(), ..Profile::default() };
+        let with_sheet = Profile {
+            id: "ann".to_string(),
+            name: "Ann".to_string(),
+            sheet: Some(CharacterSheet::default()),
+            ..Profile::default()
+        };
+        assert_eq!(profile.changed_fields(&with_sheet), ["sheet"]);
+        assert_eq!(with_sheet.changed_fields(&profile), ["sheet"]);
+        let renamed = Profile { name: "Annie".to_string(), ..with_sheet };
+        assert_eq!(profile.changed_fields(&renamed), ["name", "sheet"]);
+        assert!(profile.changed_fields(&profile).is_empty());
+    }
+}
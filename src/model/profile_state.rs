@@ -0,0 +1,22 @@
+use crate::model::character_sheet::CharacterSheet;
+use crate::model::mood::Mood;
+use crate::model::relationship::Relationship;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What changed about a profile during the chats, kept apart from the profile document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ProfileState {
+    /// Current relationships with other participants by their id. Override the ones declared in the profile.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub relationships: BTreeMap<String, Relationship>,
+    /// Mood when it last changed. Settles back to the mood of the profile over time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mood: Option<Mood>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mood_changed_at: Option<DateTime<Local>>,
+    /// Character sheet at the end of the last tabletop role-play. Overrides the one of the profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sheet: Option<CharacterSheet>,
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+/// How a profile feels about another participant.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Relationship {
+    /// From -1 (hates) to 1 (loves)
+    #[serde(default)]
+    pub affinity: f32,
+    /// From -1 (fully distrusts) to 1 (fully trusts)
+    #[serde(default)]
+    pub trust: f32,
+    /// What the relationship is, e.g. `older brother, owes him money`
+    #[serde(default)]
+    pub description: String,
+}
+
+impl Relationship {
+    /// Applies a change, keeping the values in range.
+    pub fn adjust(&mut self, affinity: f32, trust: f32) {
+        self.affinity = (self.affinity + affinity).clamp(-1.0, 1.0);
+        self.trust = (self.trust + trust).clamp(-1.0, 1.0);
+    }
+
+    /// Describes the relationship in words for the prompt of its profile, e.g. `you like @bob and distrust them`.
+    pub fn describe(&self, target: &str) -> String {
+        let affinity = match self.affinity {
+            a if a <= -0.6 => "hate",
+            a if a <= -0.2 => "dislike",
+            a if a < 0.2 => "feel neutral about",
+            a if a < 0.6 => "like",
+            _ => "love",
+        };
+        let trust = match self.trust {
+            t if t <= -0.6 => "deeply distrust them",
+            t if t <= -0.2 => "distrust them",
+            t if t < 0.2 => "are unsure whether to trust them",
+            t if t < 0.6 => "trust them",
+            _ => "trust them completely",
+        };
+        let description = if self.description.trim().is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", self.description.trim())
+        };
+        format!("you {} @{}{} and {}", affinity, target, description, trust)
+    }
+}
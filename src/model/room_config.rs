@@ -0,0 +1,419 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Definition of a chat room, usually loaded from a `room.yaml` file.
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RoomConfig {
+    /// Ids of the agent profiles in the room
+    #[serde(default)]
+    pub participants: Vec<String>,
+
+    /// Id of the profile the human chats as. Required, unless given with `--as`
+    #[serde(default)]
+    pub user: Option<String>,
+
+    #[serde(default)]
+    pub speaker_selection: SpeakerSelection,
+
+    /// Agents in an excited mood speak more often, and calm or drowsy ones less
+    #[serde(default)]
+    pub mood_talkativeness: bool,
+
+    #[serde(default)]
+    pub llm: LLMRouting,
+
+    /// What the room is about: the scenario, setting or topic of the conversation
+    #[serde(default)]
+    pub topic: Option<String>,
+
+    /// Lorebook file with the world knowledge shared by all agents
+    #[serde(default)]
+    pub lorebook: Option<String>,
+
+    /// Event script file with the events happening in the world of the room
+    #[serde(default)]
+    pub events: Option<String>,
+
+    /// World file with the locations and objects of the room, and where everyone is
+    #[serde(default)]
+    pub world: Option<String>,
+
+    #[serde(default)]
+    pub limits: RoomLimits,
+
+    #[serde(default)]
+    pub evaluators: Evaluators,
+
+    #[serde(default)]
+    pub autonomous: AutonomousConfig,
+
+    /// Simulated time of the room. The room has no notion of time without it
+    #[serde(default)]
+    pub clock: Option<ClockConfig>,
+
+    /// Game master describing the scene between the character turns. The room has no narrator without it
+    #[serde(default)]
+    pub narrator: Option<NarratorConfig>,
+
+    /// Game of Werewolf played by the participants, with the turns driven by the game instead of the planner
+    #[serde(default)]
+    pub game: Option<GameConfig>,
+
+    /// Tabletop role-play with dice and the character sheets of the profiles. The room has no dice without it
+    #[serde(default)]
+    pub rpg: Option<RpgConfig>,
+
+    /// Formal structure of the conversation, with the turns driven by the protocol instead of the planner
+    #[serde(default)]
+    pub protocol: Option<ProtocolConfig>,
+}
+
+/// A turn protocol enforcing who speaks when, and for how long.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolConfig {
+    #[serde(flatten)]
+    pub format: ProtocolFormat,
+    /// Most words an agent can say in a turn. Longer replies are cut off
+    #[serde(default = "default_max_words")]
+    pub max_words: usize,
+    /// Seconds a speaker has to take its turn before the protocol moves on
+    #[serde(default = "default_turn_secs")]
+    pub turn_secs: f64,
+}
+
+fn default_max_words() -> usize {
+    150
+}
+
+fn default_turn_secs() -> f64 {
+    120.0
+}
+
+fn default_questions() -> usize {
+    3
+}
+
+fn default_rebuttal_rounds() -> usize {
+    1
+}
+
+/// The formats of the turn protocols. Speakers can be agents or the human.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum ProtocolFormat {
+    /// Opening statements, rebuttals and closing statements on the topic of the room, in a fixed order
+    Debate {
+        /// Ids of the debaters in speaking order. All the agents if empty
+        #[serde(default)]
+        speakers: Vec<String>,
+        #[serde(default = "default_rebuttal_rounds")]
+        rebuttal_rounds: usize,
+    },
+    /// The host asks questions and the guest answers them
+    Interview {
+        /// Id of the host. The human hosts if not given, until they send /end
+        #[serde(default)]
+        host: Option<String>,
+        guest: String,
+        /// Number of questions an agent host asks
+        #[serde(default = "default_questions")]
+        questions: usize,
+    },
+    /// The moderator asks questions and addresses them to panelists by @id. Only those panelists answer
+    Panel {
+        /// Id of the moderator, an agent or the human
+        moderator: String,
+        /// Ids of the panelists. All the others if empty
+        #[serde(default)]
+        panelists: Vec<String>,
+        /// Number of questions an agent moderator asks. The human moderates until they send /end
+        #[serde(default = "default_questions")]
+        questions: usize,
+    },
+}
+
+/// Dice rolled by the room, so the LLMs never make up the results.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct RpgConfig {
+    /// Seed of the dice, to replay the same rolls. Drawn at random when not set, and recorded in the session either way
+    pub seed: Option<u64>,
+}
+
+/// A game of Werewolf: hidden roles, secret kills at night, and a vote to lynch a suspect each day.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct GameConfig {
+    /// Number of werewolves among the players
+    pub werewolves: usize,
+    /// Add a seer who learns the role of a player each night
+    pub seer: bool,
+    /// Let the human watch the game and its secrets instead of playing
+    pub spectate: bool,
+    /// Number of times each player speaks during the day before the vote
+    pub discussion_rounds: usize,
+    /// Seconds the human has to vote before the vote closes without them
+    pub vote_timeout_secs: f64,
+    /// Seconds an agent has to take its turn before the game goes on without it
+    pub turn_secs: f64,
+    /// Seed of the roles and the tie-breaks, to replay the same game
+    pub seed: Option<u64>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig { werewolves: 1, seer: true, spectate: false, discussion_rounds: 2, vote_timeout_secs: 60.0, turn_secs: 120.0, seed: None }
+    }
+}
+
+/// A narrator who describes scenes and resolves actions instead of playing a character.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct NarratorConfig {
+    /// Display name in the chat
+    pub name: String,
+    /// Instructions on the style and duties of the narrator, replacing the built-in ones
+    pub prompt: Option<String>,
+    /// Narrate after this number of character messages, on top of when the planner picks the narrator
+    pub every: Option<usize>,
+}
+
+impl Default for NarratorConfig {
+    fn default() -> Self {
+        NarratorConfig { name: "Narrator".to_string(), prompt: None, every: None }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClockConfig {
+    /// Simulated time when the chat starts, as `YYYY-MM-DD HH:MM`
+    pub start: String,
+    /// Simulated seconds per real second in autonomous mode
+    #[serde(default = "default_clock_speed")]
+    pub speed: f64,
+}
+
+fn default_clock_speed() -> f64 {
+    60.0
+}
+
+/// Parses a simulated time written as `YYYY-MM-DD HH:MM`.
+pub fn parse_world_time(time: &str) -> Result<NaiveDateTime, String> {
+    ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time.trim(), format).ok())
+        .ok_or(format!("invalid time `{}`, expected YYYY-MM-DD HH:MM", time))
+}
+
+/// Lets the agents keep the conversation going without the human.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct AutonomousConfig {
+    pub enabled: bool,
+    /// Seconds to wait before each reply, so the conversation can be followed
+    pub pace_secs: f64,
+    /// Seconds of silence before an agent starts a new thread
+    pub idle_timeout_secs: f64,
+}
+
+impl Default for AutonomousConfig {
+    fn default() -> Self {
+        AutonomousConfig { enabled: false, pace_secs: 3.0, idle_timeout_secs: 30.0 }
+    }
+}
+
+/// Background tasks that watch the chat and update the state of the agents.
+/// Each asks the LLM about every message, so they are off unless the room enables them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Evaluators {
+    /// Update the relationships between the participants after each message
+    pub relationships: bool,
+    /// Update the moods of the agents after each message
+    pub mood: bool,
+}
+
+/// How the next agent to reply is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeakerSelection {
+    /// The planner LLM decides who replies, if anyone
+    #[default]
+    Planner,
+    /// Agents take turns replying to the human
+    RoundRobin,
+    /// A random agent replies to the human
+    Random,
+}
+
+/// Paths of the LLM config files used in the room.
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LLMRouting {
+    /// Used by everything without a more specific config
+    pub default: Option<String>,
+    /// Used to select the next speaker
+    pub planner: Option<String>,
+    /// Used by the evaluators updating the state of the agents
+    pub evaluator: Option<String>,
+    /// Used by the narrator
+    pub narrator: Option<String>,
+    /// Used by the agent with the profile id
+    #[serde(default)]
+    pub agents: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RoomLimits {
+    /// Number of messages the room can buffer for slow readers
+    pub channel_size: usize,
+    /// Agents stop replying after this number of messages in the room
+    pub max_messages: Option<usize>,
+    /// Number of recent messages included in the prompts
+    pub history_size: usize,
+    /// Max number of tokens of lorebook entries included in a reply prompt
+    pub lore_token_budget: usize,
+    /// Number of recent messages searched for lorebook keywords
+    pub lore_scan_depth: usize,
+    /// Max number of long-term memories recalled in a reply prompt
+    pub memory_count: usize,
+    /// Max number of tokens of the conversation examples of the profile included in a reply prompt
+    pub examples_token_budget: usize,
+}
+
+impl Default for RoomLimits {
+    fn default() -> Self {
+        RoomLimits {
+            channel_size: 100,
+            max_messages: None,
+            history_size: 50,
+            lore_token_budget: 500,
+            lore_scan_depth: 5,
+            memory_count: 5,
+            examples_token_budget: 300,
+        }
+    }
+}
+
+impl RoomConfig {
+    /// Loads a room file. Relative paths in it are resolved against the directory of the file.
+    pub async fn load_from_yaml(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let mut config: RoomConfig = serde_yaml::from_str(&content)
+            .map_err(|e| format!("Invalid room file {}: {}", path, e))?;
+        let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let resolve = |p: &mut String| *p = base_dir.join(&*p).to_string_lossy().to_string();
+        config.lorebook.iter_mut().for_each(resolve);
+        config.events.iter_mut().for_each(resolve);
+        config.world.iter_mut().for_each(resolve);
+        config.llm.default.iter_mut().for_each(resolve);
+        config.llm.planner.iter_mut().for_each(resolve);
+        config.llm.evaluator.iter_mut().for_each(resolve);
+        config.llm.narrator.iter_mut().for_each(resolve);
+        config.llm.agents.values_mut().for_each(resolve);
+        let errors = config.validate();
+        if !errors.is_empty() {
+            return Err(format!("Invalid room file {}:\n{}", path, errors.join("\n")).into());
+        }
+        Ok(config)
+    }
+
+    /// Problems with the values of the room, which can't be caught when parsing.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.limits.channel_size == 0 {
+            errors.push("limits.channel_size must be at least 1".to_string());
+        }
+        if self.limits.history_size == 0 {
+            errors.push("limits.history_size must be at least 1".to_string());
+        }
+        if let Some(clock) = &self.clock
+            && !(clock.speed.is_finite() && clock.speed > 0.0) {
+            errors.push(format!("clock.speed must be a number above 0, not {}", clock.speed));
+        }
+        check_secs(&mut errors, "autonomous.pace_secs", self.autonomous.pace_secs);
+        check_secs(&mut errors, "autonomous.idle_timeout_secs", self.autonomous.idle_timeout_secs);
+        if let Some(game) = &self.game {
+            check_secs(&mut errors, "game.vote_timeout_secs", game.vote_timeout_secs);
+            check_secs(&mut errors, "game.turn_secs", game.turn_secs);
+        }
+        if let Some(protocol) = &self.protocol {
+            check_secs(&mut errors, "protocol.turn_secs", protocol.turn_secs);
+        }
+        errors
+    }
+}
+
+/// Durations are given in seconds, which must be a finite number that isn't negative.
+fn check_secs(errors: &mut Vec<String>, field: &str, secs: f64) {
+    if !secs.is_finite() || secs < 0.0 {
+        errors.push(format!("{} must be a number of seconds of at least 0, not {}", field, secs));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    async fn load(yaml: &str) -> Result<RoomConfig, String> {
+        let mut file = tempfile::Builder::new().suffix(".yaml").tempfile().unwrap();
+        file.write_all(yaml.as_bytes()).unwrap();
+        RoomConfig::load_from_yaml(&file.path().to_string_lossy()).await.map_err(|e| e.to_string())
+    }
+
+    async fn load_error(yaml: &str) -> String {
+        let error = load(yaml).await.unwrap_err();
+        assert!(error.starts_with("Invalid room file"), "{}", error);
+        error
+    }
+
+    #[tokio::test]
+    async fn load_accepts_the_defaults() {
+        let config = load("participants: [ann]\n").await.unwrap();
+        assert_eq!(config.participants, ["ann"]);
+    }
+
+    #[tokio::test]
+    async fn load_rejects_empty_limits() {
+        assert!(load_error("limits: { channel_size: 0 }\n").await.contains("limits.channel_size must be at least 1"));
+        assert!(load_error("limits: { history_size: 0 }\n").await.contains("limits.history_size must be at least 1"));
+    }
+
+    #[tokio::test]
+    async fn load_rejects_clocks_that_dont_move_forward() {
+        for speed in ["0", "-60", ".nan", ".inf"] {
+            let error = load_error(&format!("clock: {{ start: 2024-05-01 08:00, speed: {} }}\n", speed)).await;
+            assert!(error.contains("clock.speed must be a number above 0"), "{}", error);
+        }
+        assert!(load("clock: { start: 2024-05-01 08:00, speed: 0.5 }\n").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn load_rejects_invalid_autonomous_timings() {
+        assert!(load_error("autonomous: { pace_secs: -1 }\n").await
+            .contains("autonomous.pace_secs must be a number of seconds of at least 0, not -1"));
+        assert!(load_error("autonomous: { idle_timeout_secs: .nan }\n").await.contains("autonomous.idle_timeout_secs"));
+        assert!(load_error("autonomous: { idle_timeout_secs: .inf }\n").await.contains("autonomous.idle_timeout_secs"));
+        assert!(load("autonomous: { enabled: true, pace_secs: 0 }\n").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn load_rejects_invalid_game_timings() {
+        assert!(load_error("game: { vote_timeout_secs: .nan }\n").await.contains("game.vote_timeout_secs"));
+        assert!(load_error("game: { turn_secs: -5 }\n").await.contains("game.turn_secs"));
+        assert_eq!(load("game: {}\n").await.unwrap().game.unwrap().turn_secs, 120.0);
+    }
+
+    #[tokio::test]
+    async fn load_rejects_invalid_protocol_turn_times() {
+        assert!(load_error("protocol: { format: debate, turn_secs: -1 }\n").await.contains("protocol.turn_secs"));
+        assert!(load_error("protocol: { format: debate, turn_secs: .nan }\n").await.contains("protocol.turn_secs"));
+        assert!(load("protocol: { format: debate, turn_secs: 30 }\n").await.is_ok());
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+/// What a profile usually does at some time of the day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+    /// Start time as `HH:MM`
+    #[serde(with = "hh_mm")]
+    pub from: NaiveTime,
+    /// End time as `HH:MM`. An end before the start means the entry goes over midnight.
+    #[serde(with = "hh_mm")]
+    pub to: NaiveTime,
+    /// What the profile is doing, e.g. `working at the bakery`
+    pub activity: String,
+    #[serde(default)]
+    pub availability: Availability,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Availability {
+    #[default]
+    Available,
+    /// Can chat, but has something else to do
+    Busy,
+    Away,
+    Asleep,
+}
+
+impl Availability {
+    /// Whether the profile can take part in the conversation.
+    pub fn can_talk(&self) -> bool {
+        matches!(self, Availability::Available | Availability::Busy)
+    }
+}
+
+pub fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|_| format!("invalid time `{}`, expected HH:MM", time))
+}
+
+/// Times of the day written as `HH:MM`, rejecting anything else when loading.
+mod hh_mm {
+    use chrono::NaiveTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time.format("%H:%M"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        super::parse_time(&time).map_err(serde::de::Error::custom)
+    }
+}
+
+impl ScheduleEntry {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+/// The entry of the schedule at the time of the day, if any.
+pub fn activity_at(schedule: &[ScheduleEntry], time: NaiveTime) -> Option<&ScheduleEntry> {
+    schedule.iter().find(|e| e.contains(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(yaml: &str) -> ScheduleEntry {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn contains_the_start_but_not_the_end() {
+        let work = entry("{ from: \"09:00\", to: \"17:30\", activity: working, availability: busy }");
+        assert!(work.contains(at("09:00")));
+        assert!(work.contains(at("17:29")));
+        assert!(!work.contains(at("17:30")));
+        assert!(!work.contains(at("08:59")));
+    }
+
+    #[test]
+    fn contains_times_on_both_sides_of_midnight() {
+        let sleep = entry("{ from: \"22:00\", to: \"07:00\", activity: sleeping, availability: asleep }");
+        assert!(sleep.contains(at("22:00")));
+        assert!(sleep.contains(at("23:59")));
+        assert!(sleep.contains(at("00:00")));
+        assert!(sleep.contains(at("06:59")));
+        assert!(!sleep.contains(at("07:00")));
+        assert!(!sleep.contains(at("12:00")));
+        assert!(!sleep.contains(at("21:59")));
+
+        let schedule = [sleep, entry("{ from: \"07:00\", to: \"22:00\", activity: awake }")];
+        assert_eq!(activity_at(&schedule, at("03:00")).map(|e| e.activity.as_str()), Some("sleeping"));
+        assert_eq!(activity_at(&schedule, at("07:00")).map(|e| e.availability), Some(Availability::Available));
+    }
+
+    #[test]
+    fn loads_and_writes_times_as_hh_mm() {
+        let nap = entry("{ from: \" 13:05\", to: \"14:00\", activity: napping }");
+        assert_eq!(nap.from, at("13:05"));
+        assert_eq!(serde_yaml::to_string(&nap).unwrap(), "from: 13:05\nto: 14:00\nactivity: napping\navailability: available\n");
+
+        let error = serde_yaml::from_str::<ScheduleEntry>("{ from: \"25:00\", to: \"07:00\", activity: x }").unwrap_err();
+        assert!(error.to_string().contains("invalid time `25:00`, expected HH:MM"), "{}", error);
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+/// Transcript of a chat session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub started_at: DateTime<Local>,
+    /// Ids of the agent profiles in the room.
+    pub profile_ids: Vec<String>,
+    pub messages: Vec<SessionMessage>,
+    /// What the participants didn't know, e.g. the secret roles and votes of a game, kept apart for analysis.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hidden: Vec<String>,
+    /// Seed of the dice of the tabletop role-play, to replay the same rolls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dice_seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionMessage {
+    pub from_user_id: String,
+    pub from_username: String,
+    pub role: String,
+    pub content: String,
+    /// Who could see the message besides its sender. Public messages are stored without it.
+    #[serde(default, skip_serializing_if = "Visibility::is_public")]
+    pub visibility: Visibility,
+}
+
+/// Who can see a chat message, besides its sender.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Only the participants with these ids see the message. A whisper from the
+    /// human to a single agent is a private conversation between them.
+    Whisper(Vec<String>),
+    /// Said at a location of the world: only the participants present there see the message.
+    Local { location: String, present: Vec<String> },
+}
+
+impl Visibility {
+    pub fn is_public(&self) -> bool {
+        matches!(self, Visibility::Public)
+    }
+
+    /// Whether the participant could have seen a message sent by `from`.
+    pub fn includes(&self, from: &str, id: &str) -> bool {
+        match self {
+            Visibility::Public => true,
+            Visibility::Whisper(to) | Visibility::Local { present: to, .. } => from == id || to.iter().any(|t| t == id),
+        }
+    }
+
+    /// Describes who the message is whispered to or where it's said, for prompts and transcripts.
+    pub fn label(&self) -> Option<String> {
+        match self {
+            Visibility::Public => None,
+            Visibility::Whisper(to) => Some(format!("whisper to {}",
+                to.iter().map(|t| format!("@{}", t)).collect::<Vec<_>>().join(", "))),
+            Visibility::Local { location, .. } => Some(format!("at {}", location)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_public_messages_without_visibility() {
+        let message = SessionMessage {
+            from_user_id: "ann".to_string(),
+            from_username: "Ann".to_string(),
+            role: "user".to_string(),
+            content: "hi".to_string(),
+            visibility: Visibility::Public,
+        };
+        let yaml = serde_yaml::to_string(&message).unwrap();
+        assert!(!yaml.contains("visibility"), "{}", yaml);
+        let reloaded: SessionMessage = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(reloaded.visibility, Visibility::Public);
+        let whisper = SessionMessage { visibility: Visibility::Whisper(vec!["bob".to_string()]), ..message };
+        let reloaded: SessionMessage = serde_yaml::from_str(&serde_yaml::to_string(&whisper).unwrap()).unwrap();
+        assert_eq!(reloaded.visibility, whisper.visibility);
+    }
+}
@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// The places and objects of the world of a room, and where the participants are.
+/// Usually loaded from a file next to the room file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct World {
+    /// Locations by id
+    #[serde(default)]
+    pub locations: BTreeMap<String, Location>,
+    /// Objects by id
+    #[serde(default)]
+    pub objects: BTreeMap<String, WorldObject>,
+    /// Id of the location of each participant. Participants without a location hear everything
+    #[serde(default)]
+    pub positions: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Ids of the locations reachable from this one. Every location is reachable if empty
+    #[serde(default)]
+    pub exits: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorldObject {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Id of the location the object is at, or of the participant holding it
+    pub at: String,
+}
+
+/// Something a participant does in the world, issued by the agents as a tool call.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum WorldAction {
+    /// Go to another location
+    Move { to: String },
+    /// Pick up an object at the current location
+    Take { object: String },
+    /// Hand a held object to a participant at the same location
+    Give { object: String, to: String },
+    /// Say something to the participants at the same location
+    Say { text: String },
+}
+
+/// The result of an action: what happened, and the locations where it could be seen.
+#[derive(Debug)]
+pub struct WorldChange {
+    pub text: String,
+    pub locations: Vec<String>,
+}
+
+impl WorldAction {
+    /// What the action tries to do, e.g. ``take `key` ``.
+    pub fn describe(&self) -> String {
+        match self {
+            WorldAction::Move { to } => format!("go to `{}`", to),
+            WorldAction::Take { object } => format!("take `{}`", object),
+            WorldAction::Give { object, to } => format!("give `{}` to @{}", object, to),
+            WorldAction::Say { .. } => "say something".to_string(),
+        }
+    }
+}
+
+impl World {
+    pub async fn load_from_yaml(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = tokio::fs::read_to_string(path).await?;
+        Ok(serde_yaml::from_str(&content).map_err(|e| format!("Invalid world file {}: {}", path, e))?)
+    }
+
+    /// Checks that everything refers to known locations, objects and participants.
+    pub fn validate(&self, participants: &[String]) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (id, location) in self.locations.iter() {
+            for exit in location.exits.iter().filter(|e| !self.locations.contains_key(*e)) {
+                errors.push(format!("location `{}` has an exit to the unknown location `{}`", id, exit));
+            }
+        }
+        for (id, object) in self.objects.iter() {
+            if !self.locations.contains_key(&object.at) && !participants.contains(&object.at) {
+                errors.push(format!("object `{}` is at `{}`, which is neither a location nor a participant", id, object.at));
+            }
+        }
+        for (id, location) in self.positions.iter() {
+            if !participants.contains(id) {
+                errors.push(format!("`{}` has a position but isn't a participant", id));
+            }
+            if !self.locations.contains_key(location) {
+                errors.push(format!("`{}` is at the unknown location `{}`", id, location));
+            }
+        }
+        errors
+    }
+
+    /// Display name of the location, or its id if it's unknown.
+    pub fn location_name(&self, id: &str) -> String {
+        self.locations.get(id).map_or_else(|| id.to_string(), |l| l.name.clone())
+    }
+
+    /// Ids of the participants at the location.
+    pub fn present_at(&self, location: &str) -> Vec<String> {
+        self.positions.iter().filter(|(_, at)| *at == location).map(|(id, _)| id.clone()).collect()
+    }
+
+    /// Validates the action of the participant and applies it. `Say` doesn't change the world
+    /// and is left to the chat.
+    pub fn apply(&mut self, actor: &str, action: &WorldAction) -> Result<WorldChange, String> {
+        let here = self.positions.get(actor).cloned().ok_or_else(|| format!("@{} isn't anywhere in the world", actor))?;
+        match action {
+            WorldAction::Move { to } => {
+                let destination = self.locations.get(to).ok_or_else(|| format!("there is no location `{}`", to))?;
+                if *to == here {
+                    return Err(format!("@{} is already at {}", actor, destination.name));
+                }
+                let exits = &self.locations[&here].exits;
+                if !exits.is_empty() && !exits.contains(to) {
+                    return Err(format!("{} can't be reached from {}", destination.name, self.location_name(&here)));
+                }
+                let text = format!("@{} goes from {} to {}.", actor, self.location_name(&here), destination.name);
+                self.positions.insert(actor.to_string(), to.clone());
+                Ok(WorldChange { text, locations: vec![here, to.clone()] })
+            }
+            WorldAction::Take { object } => {
+                let item = self.objects.get_mut(object).ok_or_else(|| format!("there is no object `{}`", object))?;
+                if item.at != here {
+                    return Err(format!("{} isn't here", item.name));
+                }
+                item.at = actor.to_string();
+                Ok(WorldChange { text: format!("@{} takes {}.", actor, item.name), locations: vec![here] })
+            }
+            WorldAction::Give { object, to } => {
+                if self.positions.get(to) != Some(&here) {
+                    return Err(format!("@{} isn't here", to));
+                }
+                let item = self.objects.get_mut(object).ok_or_else(|| format!("there is no object `{}`", object))?;
+                if item.at != actor {
+                    return Err(format!("@{} doesn't have {}", actor, item.name));
+                }
+                item.at = to.clone();
+                Ok(WorldChange { text: format!("@{} gives {} to @{}.", actor, item.name, to), locations: vec![here] })
+            }
+            WorldAction::Say { .. } => Err("saying something doesn't change the world".to_string()),
+        }
+    }
+
+    /// What the participant perceives around it, for its prompt.
+    pub fn describe_surroundings(&self, id: &str) -> Option<String> {
+        let here = self.positions.get(id)?;
+        let location = self.locations.get(here)?;
+        let others: Vec<String> = self.present_at(here).into_iter().filter(|p| p != id).map(|p| format!("@{}", p)).collect();
+        let others = if others.is_empty() { "nobody else".to_string() } else { others.join(", ") };
+        let objects_at = |at: &str| -> Vec<String> {
+            self.objects.iter().filter(|(_, o)| o.at == at).map(|(oid, o)| format!("{} (`{}`)", o.name, oid)).collect()
+        };
+        let exits: Vec<String> = if location.exits.is_empty() {
+            self.locations.keys().filter(|l| *l != here).cloned().collect()
+        } else {
+            location.exits.clone()
+        };
+        let list = |items: Vec<String>| if items.is_empty() { "nothing".to_string() } else { items.join(", ") };
+        Some(format!("The profile is at {} (`{}`). {}\nPresent: {}.\nObjects here: {}.\nHeld by the profile: {}.\nExits to: {}.",
+            location.name, here, location.description, others, list(objects_at(here)), list(objects_at(id)),
+            list(exits.iter().map(|e| format!("{} (`{}`)", self.location_name(e), e)).collect())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The hall leads to the cellar and the tower, which don't lead to each other.
+    fn world() -> World {
+        serde_yaml::from_str("
+            locations:
+              hall: { name: the hall, exits: [cellar, tower] }
+              cellar: { name: the cellar, exits: [hall] }
+              tower: { name: the tower, exits: [hall] }
+            objects:
+              key: { name: a rusty key, at: cellar }
+              lamp: { name: a lamp, at: bob }
+            positions:
+              ann: cellar
+              bob: cellar
+              cid: hall
+        ").unwrap()
+    }
+
+    fn participants() -> Vec<String> {
+        ["ann", "bob", "cid"].iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn move_follows_the_exits() {
+        let mut world = world();
+        let change = world.apply("ann", &WorldAction::Move { to: "hall".to_string() }).unwrap();
+        assert_eq!(change.text, "@ann goes from the cellar to the hall.");
+        assert_eq!(change.locations, ["cellar", "hall"]);
+        assert_eq!(world.present_at("hall"), ["ann", "cid"]);
+
+        assert_eq!(world.apply("bob", &WorldAction::Move { to: "tower".to_string() }).unwrap_err(),
+            "the tower can't be reached from the cellar");
+        assert_eq!(world.apply("bob", &WorldAction::Move { to: "cellar".to_string() }).unwrap_err(),
+            "@bob is already at the cellar");
+        assert_eq!(world.apply("bob", &WorldAction::Move { to: "moon".to_string() }).unwrap_err(), "there is no location `moon`");
+        assert_eq!(world.apply("dan", &WorldAction::Move { to: "hall".to_string() }).unwrap_err(), "@dan isn't anywhere in the world");
+    }
+
+    #[test]
+    fn take_only_picks_up_objects_lying_here() {
+        let mut world = world();
+        assert_eq!(world.apply("ann", &WorldAction::Take { object: "lamp".to_string() }).unwrap_err(), "a lamp isn't here");
+        assert_eq!(world.objects["lamp"].at, "bob");
+        assert_eq!(world.apply("cid", &WorldAction::Take { object: "key".to_string() }).unwrap_err(), "a rusty key isn't here");
+
+        let change = world.apply("ann", &WorldAction::Take { object: "key".to_string() }).unwrap();
+        assert_eq!(change.text, "@ann takes a rusty key.");
+        assert_eq!(world.objects["key"].at, "ann");
+    }
+
+    #[test]
+    fn give_needs_the_object_and_the_receiver_here() {
+        let mut world = world();
+        assert_eq!(world.apply("bob", &WorldAction::Give { object: "lamp".to_string(), to: "cid".to_string() }).unwrap_err(),
+            "@cid isn't here");
+        assert_eq!(world.apply("ann", &WorldAction::Give { object: "lamp".to_string(), to: "bob".to_string() }).unwrap_err(),
+            "@ann doesn't have a lamp");
+
+        let change = world.apply("bob", &WorldAction::Give { object: "lamp".to_string(), to: "ann".to_string() }).unwrap();
+        assert_eq!(change.text, "@bob gives a lamp to @ann.");
+        assert_eq!(change.locations, ["cellar"]);
+        assert_eq!(world.objects["lamp"].at, "ann");
+    }
+
+    #[test]
+    fn validate_reports_unknown_references() {
+        assert!(world().validate(&participants()).is_empty());
+
+        let mut world = world();
+        world.locations.get_mut("tower").unwrap().exits.push("roof".to_string());
+        world.objects.get_mut("key").unwrap().at = "dan".to_string();
+        world.positions.insert("eve".to_string(), "attic".to_string());
+        assert_eq!(world.validate(&participants()), [
+            "location `tower` has an exit to the unknown location `roof`",
+            "object `key` is at `dan`, which is neither a location nor a participant",
+            "`eve` has a position but isn't a participant",
+            "`eve` is at the unknown location `attic`",
+        ]);
+    }
+}
@@ -1,12 +1,15 @@
-use crate::chat::message::{ChatMessage, ErrorMessage, Message};
-use crate::chat::room::Room;
+use crate::chat::message::{ChatMessage, ContentState, ErrorMessage, EventMessage, Message, NoticeMessage, NARRATOR_ID};
+use crate::chat::room::{Room, TIME_FORMAT};
+use crate::model::poll::PollResult;
 use crate::llm::ROLE_USER;
+use crate::ui::command::UserCommand;
+use crate::model::session::Visibility;
 use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Style},
+    style::{Color, Modifier, Style},
     text::{Line, Span, Text},
-    widgets::{Block, Borders, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Wrap},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Scrollbar, ScrollbarOrientation, ScrollbarState, Table, Wrap},
     Frame,
 };
 use std::error::Error;
@@ -21,6 +24,17 @@ pub struct CliUI {
     username: Arc<String>,
 }
 
+/// An item shown in the messages area.
+enum ChatEntry {
+    Chat(Arc<ChatMessage>, watch::Receiver<ContentState>),
+    Notice(Arc<NoticeMessage>),
+    Event(Arc<EventMessage>),
+    WorldChanged(Arc<EventMessage>),
+    /// Secrets of the game, shown when the human spectates
+    Hidden(Arc<NoticeMessage>),
+    PollResult(Arc<PollResult>),
+}
+
 struct ScrollState {
     vertical_scroll: usize,
     vertical_scroll_state: ScrollbarState,
@@ -39,35 +53,56 @@ impl CliUI {
         let mut terminal = ratatui::init();
         terminal.clear()?;
 
-        let mut textarea = TextArea::default();
-        textarea.set_block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title("Input (Press Enter to send, Esc to quit)")
-        );
+        let mut textarea = Self::new_textarea();
 
-        let mut messages: Vec<Arc<ChatMessage>> = Vec::new();
-        let mut message_receivers: Vec<watch::Receiver<(Arc<RwLock<Vec<String>>>, bool)>> = Vec::new();
+        let mut entries: Vec<ChatEntry> = Vec::new();
         let mut errors: Vec<Arc<ErrorMessage>> = Vec::new();
         let mut receiver = self.room.subscribe();
         let mut scroll_state = ScrollState {
             vertical_scroll: 0,
             vertical_scroll_state: ScrollbarState::default(),
         };
+        let mut show_relationships = false;
+        let mut show_roster = true;
 
         loop {
             // Try to receive new messages (non-blocking)
             let mut new_messages = false;
             loop {
                 match receiver.try_recv() {
+                    // Whispers between other participants stay hidden from the human
+                    Ok(Message::Chat(chat_msg)) if !chat_msg.visible_to(&self.user_id) => {}
                     Ok(Message::Chat(chat_msg)) => {
-                        messages.push(chat_msg.clone());
-                        message_receivers.push(chat_msg.content_stream.subscribe());
+                        let content_receiver = chat_msg.content_stream.subscribe();
+                        entries.push(ChatEntry::Chat(chat_msg, content_receiver));
                         new_messages = true;
                     }
                     Ok(Message::Error(err_msg)) => {
                         errors.push(err_msg);
                     }
+                    Ok(Message::Notice(notice)) => {
+                        entries.push(ChatEntry::Notice(notice));
+                        new_messages = true;
+                    }
+                    Ok(Message::Event(event)) if !event.to_chat().visible_to(&self.user_id) => {}
+                    Ok(Message::Event(event)) => {
+                        entries.push(ChatEntry::Event(event));
+                        new_messages = true;
+                    }
+                    Ok(Message::WorldChanged(change)) if !change.to_chat().visible_to(&self.user_id) => {}
+                    Ok(Message::WorldChanged(change)) => {
+                        entries.push(ChatEntry::WorldChanged(change));
+                        new_messages = true;
+                    }
+                    Ok(Message::Hidden(notice)) => if self.is_spectating() {
+                        entries.push(ChatEntry::Hidden(notice));
+                        new_messages = true;
+                    },
+                    Ok(Message::PollResult(result)) => {
+                        entries.push(ChatEntry::PollResult(result));
+                        new_messages = true;
+                    }
+                    Ok(Message::ProfileUpdated(_)) | Ok(Message::Simulation(_)) | Ok(Message::Turn(_)) | Ok(Message::Poll(_)) => {}
                     Err(TryRecvError::Empty) => break,
                     Err(TryRecvError::Lagged(_)) => {
                         // Messages were dropped, continue
@@ -82,8 +117,9 @@ impl CliUI {
 
             // Check for updates in all message receivers
             let mut content_updated = false;
-            for receiver in &mut message_receivers {
-                if receiver.has_changed().unwrap_or(false) {
+            for entry in &mut entries {
+                if let ChatEntry::Chat(_, receiver) = entry
+                    && receiver.has_changed().unwrap_or(false) {
                     receiver.mark_unchanged();
                     content_updated = true;
                 }
@@ -96,85 +132,315 @@ impl CliUI {
 
             // Draw the UI
             terminal.draw(|frame| {
-                self.draw(frame, &messages, &errors, &textarea, &mut scroll_state, &mut message_receivers);
+                self.draw(frame, &entries, &errors, &textarea, &mut scroll_state, (show_relationships, show_roster));
             })?;
 
             // Handle input events
-            if event::poll(std::time::Duration::from_millis(100))? {
-                if let Event::Key(key) = event::read()? {
-                    if key.kind == KeyEventKind::Press {
-                        match key.code {
-                            KeyCode::Esc => {
-                                ratatui::restore();
-                                return Ok(());
-                            }
-                            KeyCode::Enter => {
-                                let input = textarea.lines().join("\n");
-                                if !input.trim().is_empty() {
-                                    let (sender, _rx) = watch::channel((Arc::new(RwLock::new(vec![input.clone()])), true));
-                                    let msg = Arc::new(ChatMessage {
-                                        from_user_id: (*self.user_id).clone(),
-                                        from_username: (*self.username).clone(),
-                                        role: ROLE_USER.into(),
-                                        content_stream: Arc::new(sender),
-                                    });
-                                    self.room.send_chat(msg)?;
-                                    textarea = TextArea::default();
-                                    textarea.set_block(
-                                        Block::default()
-                                            .borders(Borders::ALL)
-                                            .title("Input (Press Enter to send, Esc to quit)")
-                                    );
-                                }
-                            }
-                            KeyCode::Up => {
-                                scroll_state.vertical_scroll = scroll_state.vertical_scroll.saturating_sub(1);
-                                scroll_state.vertical_scroll_state = scroll_state.vertical_scroll_state.position(scroll_state.vertical_scroll);
-                            }
-                            KeyCode::Down => {
-                                scroll_state.vertical_scroll = scroll_state.vertical_scroll.saturating_add(1);
-                                scroll_state.vertical_scroll_state = scroll_state.vertical_scroll_state.position(scroll_state.vertical_scroll);
-                            }
-                            KeyCode::PageUp => {
-                                scroll_state.vertical_scroll = scroll_state.vertical_scroll.saturating_sub(10);
-                                scroll_state.vertical_scroll_state = scroll_state.vertical_scroll_state.position(scroll_state.vertical_scroll);
-                            }
-                            KeyCode::PageDown => {
-                                scroll_state.vertical_scroll = scroll_state.vertical_scroll.saturating_add(10);
-                                scroll_state.vertical_scroll_state = scroll_state.vertical_scroll_state.position(scroll_state.vertical_scroll);
+            if event::poll(std::time::Duration::from_millis(100))?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press {
+                match key.code {
+                    KeyCode::Esc => {
+                        ratatui::restore();
+                        return Ok(());
+                    }
+                    KeyCode::Enter => {
+                        let input = textarea.lines().join("\n");
+                        if let Some(command) = UserCommand::parse(&input) {
+                            match command {
+                                Ok(UserCommand::Relationships) => show_relationships = !show_relationships,
+                                Ok(UserCommand::Roster) => show_roster = !show_roster,
+                                Ok(command) => if let Err(msg) = self.run_command(command) {
+                                    errors.push(Arc::new(ErrorMessage { msg }));
+                                },
+                                Err(msg) => errors.push(Arc::new(ErrorMessage { msg })),
                             }
-                            _ => {
-                                textarea.input(key);
+                            textarea = Self::new_textarea();
+                        } else if !input.trim().is_empty() {
+                            match self.room.speech_visibility(&self.user_id) {
+                                Ok(visibility) => self.send_chat(input, visibility)?,
+                                Err(msg) => errors.push(Arc::new(ErrorMessage { msg })),
                             }
+                            textarea = Self::new_textarea();
                         }
                     }
+                    KeyCode::Up => {
+                        scroll_state.vertical_scroll = scroll_state.vertical_scroll.saturating_sub(1);
+                        scroll_state.vertical_scroll_state = scroll_state.vertical_scroll_state.position(scroll_state.vertical_scroll);
+                    }
+                    KeyCode::Down => {
+                        scroll_state.vertical_scroll = scroll_state.vertical_scroll.saturating_add(1);
+                        scroll_state.vertical_scroll_state = scroll_state.vertical_scroll_state.position(scroll_state.vertical_scroll);
+                    }
+                    KeyCode::PageUp => {
+                        scroll_state.vertical_scroll = scroll_state.vertical_scroll.saturating_sub(10);
+                        scroll_state.vertical_scroll_state = scroll_state.vertical_scroll_state.position(scroll_state.vertical_scroll);
+                    }
+                    KeyCode::PageDown => {
+                        scroll_state.vertical_scroll = scroll_state.vertical_scroll.saturating_add(10);
+                        scroll_state.vertical_scroll_state = scroll_state.vertical_scroll_state.position(scroll_state.vertical_scroll);
+                    }
+                    _ => {
+                        textarea.input(key);
+                    }
                 }
             }
         }
     }
 
-    fn draw(&self, frame: &mut Frame, messages: &[Arc<ChatMessage>], errors: &[Arc<ErrorMessage>], textarea: &TextArea, scroll_state: &mut ScrollState, message_receivers: &mut [watch::Receiver<(Arc<RwLock<Vec<String>>>, bool)>]) {
-        let chunks = Layout::default()
+    fn send_chat(&self, content: String, visibility: Visibility) -> Result<(), Box<dyn Error>> {
+        let (sender, _rx) = watch::channel((Arc::new(RwLock::new(vec![content])), true));
+        let msg = Arc::new(ChatMessage {
+            from_user_id: (*self.user_id).clone(),
+            from_username: (*self.username).clone(),
+            role: ROLE_USER.into(),
+            content_stream: Arc::new(sender),
+            activated_lore: Vec::new(),
+            visibility,
+        });
+        self.room.send_chat(msg)
+    }
+
+    fn run_command(&self, command: UserCommand) -> Result<(), String> {
+        let result = match command {
+            UserCommand::Topic(topic) => self.room.set_topic(topic),
+            UserCommand::Relationships | UserCommand::Roster => Ok(()),
+            UserCommand::Simulation(command) => self.room.control_simulation(command),
+            UserCommand::Advance(delta) => self.room.advance_clock(delta),
+            UserCommand::Whisper { to, content } => {
+                let visibility = self.room.speech_visibility(&self.user_id)?;
+                let profiles = self.room.profiles();
+                if let Some(unknown) = to.iter().find(|id| !profiles.iter().any(|p| &p.id == *id)) {
+                    return Err(format!("No agent @{} in the room", unknown));
+                }
+                // During a game, only the players who can hear the human can be whispered to, e.g. the other werewolves at night
+                if self.room.game().is_some()
+                    && let Visibility::Whisper(listeners) = &visibility
+                    && let Some(outside) = to.iter().find(|id| !listeners.contains(id)) {
+                    return Err(format!("@{} can't hear you now", outside));
+                }
+                self.send_chat(content, Visibility::Whisper(to))
+            }
+            UserCommand::Poll(poll) => {
+                if self.room.profiles().is_empty() {
+                    return Err("No agent in the room to vote".to_string());
+                }
+                self.room.start_poll(poll)
+            }
+            UserCommand::Roll { dice, reason } => self.room.roll_dice(&self.user_id, &dice, reason.as_deref()).map(|_| ()),
+            UserCommand::Vote(target) => {
+                self.room.cast_vote(&self.user_id, &target)?;
+                self.room.send_notice(Arc::new(NoticeMessage { msg: format!("You vote for @{}", target) }))
+            }
+        };
+        result.map_err(|e| e.to_string())
+    }
+
+    /// Whether the human watches a game, and can see its secrets.
+    fn is_spectating(&self) -> bool {
+        self.room.game().is_some_and(|g| g.spectator.as_deref() == Some(self.user_id.as_str()))
+    }
+
+    /// Red for clearly negative values from -1 to 1, green for clearly positive ones.
+    fn value_style(value: f32) -> Style {
+        Style::default().fg(if value <= -0.2 {
+            Color::Red
+        } else if value >= 0.2 {
+            Color::Green
+        } else {
+            Color::Gray
+        })
+    }
+
+    fn draw_relationships(&self, frame: &mut Frame, area: Rect) {
+        let rows: Vec<Row> = self.room.all_relationships().into_iter()
+            .map(|(from, to, r)| Row::new(vec![
+                Cell::from(format!("@{}", from)),
+                Cell::from(format!("@{}", to)),
+                Cell::from(format!("{:+.1}", r.affinity)).style(Self::value_style(r.affinity)),
+                Cell::from(format!("{:+.1}", r.trust)).style(Self::value_style(r.trust)),
+                Cell::from(r.description),
+            ]))
+            .collect();
+        let table = Table::new(rows, [
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Min(10),
+        ])
+            .header(Row::new(vec!["From", "To", "Affinity", "Trust", "Description"])
+                .style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::default().borders(Borders::ALL).title("Relationships (/rel to hide)"));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_roster(&self, frame: &mut Frame, area: Rect) {
+        let mut text = Text::default();
+        for profile in self.room.profiles() {
+            let mood = self.room.mood(&profile.id);
+            text.lines.push(Line::from(Span::styled(format!("{}(@{})", profile.name, profile.id),
+                Style::default().fg(Color::Cyan))));
+            text.lines.push(Line::from(vec![
+                Span::raw("  valence "),
+                Span::styled(format!("{:+.1}", mood.valence), Self::value_style(mood.valence)),
+                Span::raw(" arousal "),
+                Span::styled(format!("{:+.1}", mood.arousal), Self::value_style(mood.arousal)),
+            ]));
+            let emotions = mood.strongest_emotions(3).into_iter()
+                .map(|(name, intensity)| format!("{} {:.1}", name, intensity))
+                .collect::<Vec<_>>();
+            if !emotions.is_empty() {
+                text.lines.push(Line::from(Span::styled(format!("  {}", emotions.join(", ")),
+                    Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC))));
+            }
+        }
+        let roster = Paragraph::new(text)
+            .block(Block::default().borders(Borders::ALL).title("Roster (/roster to hide)"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(roster, area);
+    }
+
+    /// The result of a poll as a card: a bar per option, then the ballots with their reasons.
+    fn draw_poll_result(text: &mut Text, result: &PollResult) {
+        let border = Style::default().fg(Color::Magenta);
+        let tally = result.tally();
+        let voters = result.ballots.len().max(1);
+        let width = tally.iter().map(|(option, _)| option.chars().count()).max().unwrap_or(0);
+        text.lines.push(Line::from(vec![
+            Span::styled("┌ Poll: ", border),
+            Span::styled(result.poll.question.clone(), Style::default().add_modifier(Modifier::BOLD)),
+        ]));
+        for (option, votes) in tally {
+            let bar = "█".repeat(votes * 20 / voters);
+            text.lines.push(Line::from(vec![
+                Span::styled("│ ", border),
+                Span::raw(format!("{:width$} ", option)),
+                Span::styled(format!("{:20}", bar), Style::default().fg(Color::Magenta)),
+                Span::raw(format!(" {} ({}%)", votes, votes * 100 / voters)),
+            ]));
+        }
+        text.lines.push(Line::from(Span::styled("├", border)));
+        for (voter, ballot) in result.ballots.iter() {
+            let line = match ballot {
+                Some(ballot) => vec![
+                    Span::styled(format!("@{}", voter), Style::default().fg(Color::Cyan)),
+                    Span::raw(format!(" → {}: ", ballot.choice)),
+                    Span::styled(ballot.reason.clone(), Style::default().add_modifier(Modifier::ITALIC)),
+                ],
+                None => vec![
+                    Span::styled(format!("@{}", voter), Style::default().fg(Color::Cyan)),
+                    Span::styled(" abstained", Style::default().fg(Color::DarkGray)),
+                ],
+            };
+            text.lines.push(Line::from([vec![Span::styled("│ ", border)], line].concat()));
+        }
+        text.lines.push(Line::from(Span::styled("└", border)));
+        text.lines.push(Line::from(""));
+    }
+
+    fn new_textarea() -> TextArea<'static> {
+        let mut textarea = TextArea::default();
+        textarea.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Input (Enter to send, /w @id <text> to whisper, /rel for relationships, /roster for moods, /topic <text>, /pause, /resume, /step, /end, /advance 2h, /vote @id, /roll 2d6, /poll \"question\" a b, Esc to quit)")
+        );
+        textarea
+    }
+
+    fn draw(&self, frame: &mut Frame, entries: &[ChatEntry], errors: &[Arc<ErrorMessage>], textarea: &TextArea,
+            scroll_state: &mut ScrollState, (show_relationships, show_roster): (bool, bool)) {
+        let mut chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints(vec![
                 Constraint::Percentage(60),
                 Constraint::Percentage(10),
                 Constraint::Percentage(30),
             ])
-            .split(frame.area());
+            .split(frame.area())
+            .to_vec();
+
+        // Relationships table next to the messages
+        if show_relationships {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Percentage(60), Constraint::Percentage(40)])
+                .split(chunks[0]);
+            chunks[0] = columns[0];
+            self.draw_relationships(frame, columns[1]);
+        }
+
+        // Roster of the agents with their moods, on the right of the messages
+        if show_roster {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Min(40), Constraint::Length(32)])
+                .split(chunks[0]);
+            chunks[0] = columns[0];
+            self.draw_roster(frame, columns[1]);
+        }
 
         // Messages area (top 60%)
         let mut message_text = Text::default();
-        for (msg_index, msg) in messages.iter().enumerate() {
-            let role_line = Line::from(vec![
+        for entry in entries.iter() {
+            let (msg, receiver) = match entry {
+                ChatEntry::Chat(msg, receiver) => (msg, receiver),
+                ChatEntry::Notice(notice) => {
+                    message_text.lines.push(Line::from(Span::styled(format!("* {}", notice.msg),
+                        Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC))));
+                    message_text.lines.push(Line::from(""));
+                    continue;
+                }
+                ChatEntry::Event(event) => {
+                    for (i, line) in event.text.lines().enumerate() {
+                        let prefix = if i == 0 { "» " } else { "  " };
+                        message_text.lines.push(Line::from(Span::styled(format!("{}{}", prefix, line),
+                            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))));
+                    }
+                    message_text.lines.push(Line::from(""));
+                    continue;
+                }
+                ChatEntry::Hidden(notice) => {
+                    message_text.lines.push(Line::from(Span::styled(format!("[hidden] {}", notice.msg),
+                        Style::default().fg(Color::DarkGray))));
+                    message_text.lines.push(Line::from(""));
+                    continue;
+                }
+                ChatEntry::PollResult(result) => {
+                    Self::draw_poll_result(&mut message_text, result);
+                    continue;
+                }
+                ChatEntry::WorldChanged(change) => {
+                    message_text.lines.push(Line::from(Span::styled(format!("~ {}", change.text),
+                        Style::default().fg(Color::Blue))));
+                    message_text.lines.push(Line::from(""));
+                    continue;
+                }
+            };
+            // The narrator isn't a character, its narration stands out from the chat
+            let content_style = if msg.from_user_id == NARRATOR_ID {
+                Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC)
+            } else {
+                Style::default()
+            };
+            let mut role_spans = vec![
                 Span::styled(format!("{}(@{})", &msg.from_username, &msg.from_user_id),
-                             Style::default().fg(Color::Cyan)),
-                Span::raw(": "),
-            ]);
+                             Style::default().fg(Color::Cyan).patch(content_style)),
+            ];
+            if let Some(label) = msg.visibility_label() {
+                role_spans.push(Span::styled(format!(" ({})", label), Style::default().fg(Color::Magenta)));
+            }
+            role_spans.push(Span::raw(": "));
+            let role_line = Line::from(role_spans);
             message_text.lines.push(role_line);
+            if !msg.activated_lore.is_empty() {
+                message_text.lines.push(Line::from(Span::styled(format!("lore: {}", msg.activated_lore.join(", ")),
+                    Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC))));
+            }
 
             // Get the accumulated content for this message from the watch receiver
-            if let Some(receiver) = message_receivers.get(msg_index) {
+            {
                 let (content_chunks, _is_complete) = &*receiver.borrow();
                 // Since we're in a synchronous drawing context, we can't await the lock
                 // Use try_read to avoid blocking
@@ -182,7 +448,7 @@ impl CliUI {
                     let content = chunks.join("");
                     // Split content into lines and add each as a separate line
                     for content_line in content.lines() {
-                        message_text.lines.push(Line::from(content_line.to_string()));
+                        message_text.lines.push(Line::from(Span::styled(content_line.to_string(), content_style)));
                     }
                 }
             }
@@ -205,11 +471,22 @@ impl CliUI {
             .content_length(total_lines)
             .position(scroll_state.vertical_scroll);
 
+        let mut title = "Messages".to_string();
+        if let Some(time) = self.room.world_time() {
+            title.push_str(&format!(" - {}", time.format(TIME_FORMAT)));
+        }
+        if let Some(location) = self.room.location_of(&self.user_id) {
+            title.push_str(&format!(" - at {}", location));
+        }
+        if let Some(topic) = self.room.topic() {
+            title.push_str(&format!(" - {}", topic));
+        }
+        title.push_str(" (Use Up/Down/PgUp/PgDown to scroll)");
         let messages_paragraph = Paragraph::new(message_text)
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .title("Messages (Use Up/Down/PgUp/PgDown to scroll)")
+                    .title(title)
             )
             .wrap(Wrap { trim: false })
             .scroll((scroll_state.vertical_scroll as u16, 0));
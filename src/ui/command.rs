@@ -0,0 +1,120 @@
+use chrono::TimeDelta;
+use crate::chat::message::SimulationCommand;
+use crate::model::poll::Poll;
+
+/// A command typed in the input box, starting with `/`.
+pub enum UserCommand {
+    /// `/topic <text>` changes the topic of the room, `/topic` alone clears it.
+    Topic(Option<String>),
+    /// `/w @id [@id...] <text>` whispers a message to the given participants only.
+    Whisper { to: Vec<String>, content: String },
+    /// `/relationships` shows or hides the table of relationships between the participants.
+    Relationships,
+    /// `/roster` shows or hides the agents of the room with their moods.
+    Roster,
+    /// `/pause`, `/resume` and `/step` control the turns of the agents.
+    Simulation(SimulationCommand),
+    /// `/advance 2h` moves the clock of the room forward, e.g. by `45m`, `1h30m` or `1d`.
+    Advance(TimeDelta),
+    /// `/vote @id` votes for a player in the current phase of the game.
+    Vote(String),
+    /// `/roll 2d6+3 [reason]` rolls dice in a tabletop role-play.
+    Roll { dice: String, reason: Option<String> },
+    /// `/poll "question" option1 option2...` asks every agent to vote. Quotes group words.
+    Poll(Poll),
+}
+
+impl UserCommand {
+    /// Parses the input as a command. Returns `None` if the input is a chat message.
+    pub fn parse(input: &str) -> Option<Result<Self, String>> {
+        let input = input.trim();
+        let command = input.strip_prefix('/')?;
+        let (name, args) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let args = args.trim();
+        Some(match name {
+            "topic" => Ok(UserCommand::Topic(Some(args.to_string()).filter(|a| !a.is_empty()))),
+            "w" | "whisper" => Self::parse_whisper(args),
+            "rel" | "relationships" => Ok(UserCommand::Relationships),
+            "roster" => Ok(UserCommand::Roster),
+            "pause" => Ok(UserCommand::Simulation(SimulationCommand::Pause)),
+            "resume" => Ok(UserCommand::Simulation(SimulationCommand::Resume)),
+            "step" => Ok(UserCommand::Simulation(SimulationCommand::Step)),
+            "end" => Ok(UserCommand::Simulation(SimulationCommand::End)),
+            "advance" => parse_duration(args).map(UserCommand::Advance),
+            "roll" => match args.split_once(char::is_whitespace) {
+                _ if args.is_empty() => Err("Usage: /roll <dice> [reason], e.g. /roll 1d20+2 climbing".to_string()),
+                Some((dice, reason)) => Ok(UserCommand::Roll { dice: dice.to_string(), reason: Some(reason.trim().to_string()) }),
+                None => Ok(UserCommand::Roll { dice: args.to_string(), reason: None }),
+            },
+            "poll" => split_quoted(args).and_then(|mut words| {
+                if words.is_empty() {
+                    return Err("Usage: /poll \"question\" option1 option2...".to_string());
+                }
+                let question = words.remove(0);
+                Poll::new(question, words).map(UserCommand::Poll)
+            }),
+            "vote" => match args.trim_start_matches('@') {
+                "" => Err("Usage: /vote @id".to_string()),
+                id => Ok(UserCommand::Vote(id.to_string())),
+            },
+            _ => Err(format!("Unknown command /{}", name)),
+        })
+    }
+
+    fn parse_whisper(args: &str) -> Result<Self, String> {
+        let mut to = Vec::new();
+        let mut rest = args;
+        while let Some(mention) = rest.strip_prefix('@') {
+            let (id, remaining) = mention.split_once(char::is_whitespace).unwrap_or((mention, ""));
+            to.extend(id.split(',').map(|i| i.trim_start_matches('@')).filter(|i| !i.is_empty()).map(String::from));
+            rest = remaining.trim_start();
+        }
+        if to.is_empty() || rest.is_empty() {
+            return Err("Usage: /w @id [@id...] <message>".to_string());
+        }
+        Ok(UserCommand::Whisper { to, content: rest.to_string() })
+    }
+}
+
+/// Splits the arguments on whitespace, keeping the words between double quotes together.
+fn split_quoted(args: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut rest = args.trim_start();
+    while !rest.is_empty() {
+        let (word, remaining) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').ok_or("Unclosed quote")?,
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        words.push(word.to_string());
+        rest = remaining.trim_start();
+    }
+    Ok(words)
+}
+
+/// Parses a duration like `2h`, `45m`, `1h30m`, `1d` or `90s`.
+fn parse_duration(text: &str) -> Result<TimeDelta, String> {
+    let usage = || format!("Invalid duration `{}`, expected e.g. 2h, 45m or 1h30m", text);
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n: i64 = number.parse().map_err(|_| usage())?;
+        number.clear();
+        let delta = match c {
+            'd' => TimeDelta::try_days(n),
+            'h' => TimeDelta::try_hours(n),
+            'm' => TimeDelta::try_minutes(n),
+            's' => TimeDelta::try_seconds(n),
+            _ => return Err(usage()),
+        };
+        total = delta.and_then(|delta| total.checked_add(&delta))
+            .ok_or(format!("Duration `{}` is too long", text))?;
+    }
+    if !number.is_empty() || total <= TimeDelta::zero() {
+        return Err(usage());
+    }
+    Ok(total)
+}
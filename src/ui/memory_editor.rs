@@ -0,0 +1,22 @@
+use crate::dao::memory_dao::MemoryDao;
+use crate::model::memory::Memory;
+use crate::ui::profile_editor::edit_until_valid;
+use std::error::Error;
+
+/// Opens the memories of the profile in `$VISUAL` / `$EDITOR` as a YAML list and saves them once they parse.
+/// Saving an empty file aborts the editing, `[]` forgets everything. Returns whether the memories were saved.
+pub async fn edit_memories(dao: &dyn MemoryDao, id: &str) -> Result<bool, Box<dyn Error>> {
+    let memories = dao.get(id).await?;
+    let document = format!("# Memories of {}. Each one has a `kind` (person, event, promise or fact), \
+        a `content` and a `created_at` time.\n{}", id, serde_yaml::to_string(&memories)?);
+    let edited = edit_until_valid(&format!("v-world-memories-{}-", id), document,
+        async |contents| serde_yaml::from_str::<Vec<Memory>>(contents).map(|_| ()).map_err(|e| vec![e.to_string()])).await?;
+    match edited {
+        Some(contents) => {
+            let memories: Vec<Memory> = serde_yaml::from_str(&contents)?;
+            dao.save(id, &memories).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
@@ -1 +1,4 @@
-pub mod cli_ui;
\ No newline at end of file
+pub mod cli_ui;
+pub mod command;
+pub mod profile_editor;
+pub mod memory_editor;
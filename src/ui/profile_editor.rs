@@ -0,0 +1,84 @@
+use crate::dao::profile_dao::ProfileDao;
+use crate::dao::profile_document::{to_commented_yaml, validate_document};
+use crate::model::profile::Profile;
+use std::error::Error;
+use std::path::Path;
+use tokio::fs;
+use tokio::process::Command;
+
+const ERROR_PREFIX: &str = "# ERROR: ";
+
+/// Opens the profile document in `$VISUAL` / `$EDITOR` until it passes validation.
+///
+/// The document is edited in a temporary file and only saved to the store once it's valid.
+/// Validation errors are written to the top of the file as comments before it's re-opened.
+/// Saving an empty file aborts the editing. Returns whether the profile was saved.
+pub async fn edit_profile(dao: &dyn ProfileDao, id: &str) -> Result<bool, Box<dyn Error>> {
+    let document = match dao.get_document(id).await? {
+        Some(document) => document,
+        None => to_commented_yaml(&Profile { id: id.to_string(), ..Default::default() })?,
+    };
+    let edited = edit_until_valid(&format!("v-world-profile-{}-", id), document,
+        async |contents| validate_document(dao, id, contents).await.map(|_| ())).await?;
+    match edited {
+        Some(contents) => {
+            dao.save_document(id, &contents).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Opens the document in a temporary file named with the prefix until `validate` accepts it,
+/// writing the errors to the top of the file as comments. Returns `None` if the editing was aborted.
+/// The file is removed however the editing ends.
+pub(crate) async fn edit_until_valid<F>(prefix: &str, mut document: String, mut validate: F) -> Result<Option<String>, Box<dyn Error>>
+where
+    F: AsyncFnMut(&str) -> Result<(), Vec<String>>,
+{
+    let file = tempfile::Builder::new().prefix(prefix).suffix(".yaml").tempfile()?;
+    let path = file.path();
+    let result = loop {
+        fs::write(path, &document).await?;
+        open_editor(path).await?;
+        let contents = strip_errors(&fs::read_to_string(path).await?);
+        if contents.trim().is_empty() {
+            break None;
+        }
+        match validate(&contents).await {
+            Ok(()) => break Some(contents),
+            Err(errors) => {
+                document = String::new();
+                for err in errors {
+                    for line in err.lines() {
+                        document.push_str(&format!("{}{}\n", ERROR_PREFIX, line));
+                    }
+                }
+                document.push_str(&format!("{}(save an empty file to abort)\n", ERROR_PREFIX));
+                document.push_str(&contents);
+            }
+        }
+    };
+    Ok(result)
+}
+
+async fn open_editor(path: &Path) -> Result<(), Box<dyn Error>> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // The editor may come with arguments, e.g. `code --wait`
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().ok_or("editor command is empty")?;
+    let status = Command::new(program).args(parts).arg(path).status().await?;
+    if !status.success() {
+        return Err(format!("editor `{}` exited with {}", editor, status).into());
+    }
+    Ok(())
+}
+
+fn strip_errors(contents: &str) -> String {
+    contents.lines()
+        .filter(|line| !line.starts_with(ERROR_PREFIX))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}